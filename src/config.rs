@@ -0,0 +1,404 @@
+use std::{env, path::PathBuf, time::Duration};
+
+use cookie::SameSite;
+use jsonwebtoken::Algorithm;
+
+use crate::{
+    security::{
+        access::{AccessRules, RolePermissions},
+        binding::BindingPolicy,
+        cookies::{CookieConfig, CookieMode, derive_key},
+        jwt::JwtConfig,
+    },
+    server::SyncError,
+};
+
+// 会话与用户数据的存储后端
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreKind {
+    Memory,
+    Redis(String),
+    // 数据目录
+    File(PathBuf),
+}
+
+// 会话有效期策略，时长均以秒为单位配置
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionConfig {
+    // 无访问超过该时长后失效，每次访问顺延
+    pub idle_timeout: Duration,
+    // 自登录起的最长有效期，顺延不能超过该时长
+    pub max_lifetime: Duration,
+    // 定期更换会话键，限制会话键泄露后的可用时间
+    pub rotate_interval: Duration,
+}
+
+impl SessionConfig {
+    // created_at 时登录的会话距最长有效期的剩余时间
+    pub fn lifetime_left(&self, created_at: u64, now: u64) -> Duration {
+        let deadline = created_at + self.max_lifetime.as_secs();
+        Duration::from_secs(deadline.saturating_sub(now))
+    }
+
+    // 此刻续期后会话的有效期
+    pub fn ttl(&self, created_at: u64, now: u64) -> Duration {
+        self.idle_timeout.min(self.lifetime_left(created_at, now))
+    }
+}
+
+pub struct Config {
+    pub listen_addr: String,
+    pub store: StoreKind,
+    // 启动时写入的初始用户，格式 user:password，多个以逗号分隔
+    pub seed_users: Vec<(String, String)>,
+    // 上传文件保存的目录，位于 static 下时可直接通过文件路由访问
+    pub upload_dir: PathBuf,
+    // 单个上传文件的大小上限（字节）
+    pub max_upload_size: usize,
+    // 启动时即拥有 admin 角色的用户，其余角色通过管理接口分配
+    pub admin_users: Vec<String>,
+    // JWT 模式下不生效，令牌有效期由 jwt.ttl 决定，不会顺延或更换
+    pub session: SessionConfig,
+    // 会话与客户端 IP、User-Agent 的绑定，JWT 模式下不生效
+    pub binding: BindingPolicy,
+    // 各路径所需的权限
+    pub access: AccessRules,
+    // 角色拥有的权限，用户的角色保存在用户存储中
+    pub permissions: RolePermissions,
+    pub cookie: CookieConfig,
+    // SESSION_MODE=jwt 时登录签发 JWT，不再使用会话存储；会话列表与批量注销接口不可用
+    pub jwt: Option<JwtConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::from_vars(|_| None).expect("default config")
+    }
+}
+
+impl Config {
+    // 从环境变量读取：LISTEN_ADDR、STORE（redis | memory | file）、REDIS_URL、DATA_DIR、SEED_USERS、
+    // UPLOAD_DIR、MAX_UPLOAD_SIZE、ADMIN_USERS、
+    // SESSION_IDLE_TIMEOUT、SESSION_MAX_LIFETIME、SESSION_ROTATE_INTERVAL、
+    // SESSION_BINDING（none，或 ip | prefix 与 ua 组合）、SESSION_BIND_PREFIX_V4、SESSION_BIND_PREFIX_V6、
+    // COOKIE_NAME、COOKIE_DOMAIN、COOKIE_PATH、COOKIE_SAME_SITE（lax | strict | none）、COOKIE_SECURE、
+    // COOKIE_HTTP_ONLY、COOKIE_MODE（plain | signed | private）、COOKIE_KEYS（逗号分隔，第一个用于签发）、
+    // ACCESS_RULES（以 `;` 分隔的访问规则）或 ACCESS_RULES_FILE（每行一条规则的文件）、
+    // ROLE_PERMISSIONS（如 editor=upload,files.write;auditor=sessions.manage）、
+    // SESSION_MODE（store | jwt）、JWT_ALGORITHM（HS256 | EdDSA | RS256）、JWT_SECRET 或
+    // JWT_PRIVATE_KEY_FILE 与 JWT_PUBLIC_KEY_FILE、JWT_ISSUER、JWT_AUDIENCE、JWT_TTL、JWT_LEEWAY、
+    // JWT_CLAIMS（附加声明的 JSON 对象）
+    pub fn from_env() -> Result<Self, Box<SyncError>> {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        Self::from_vars(var)
+    }
+
+    // 内置角色：所有用户都有 user，ADMIN_USERS 中的用户还有 admin
+    pub fn roles(&self, user: &str) -> Vec<String> {
+        let mut roles = vec!["user".to_string()];
+        if self.admin_users.iter().any(|admin| admin == user) {
+            roles.push("admin".into());
+        }
+        roles
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, Box<SyncError>> {
+        let listen_addr = var("LISTEN_ADDR").unwrap_or_else(|| "127.0.0.1:25823".into());
+        let store = match var("STORE").as_deref() {
+            None | Some("redis") => StoreKind::Redis(
+                var("REDIS_URL").unwrap_or_else(|| "redis://127.0.0.1:6379".into()),
+            ),
+            Some("memory") => StoreKind::Memory,
+            Some("file") => {
+                StoreKind::File(var("DATA_DIR").unwrap_or_else(|| "data".into()).into())
+            }
+            Some(other) => return Err(format!("Err: Unknown STORE backend {}", other).into()),
+        };
+        let mut seed_users = Vec::new();
+        for entry in var("SEED_USERS").unwrap_or_default().split(',') {
+            if entry.is_empty() {
+                continue;
+            }
+            match entry.split_once(':') {
+                Some((user, password)) if !user.is_empty() => {
+                    seed_users.push((user.to_string(), password.to_string()))
+                }
+                _ => return Err(format!("Err: Invalid SEED_USERS entry {}", entry).into()),
+            }
+        }
+        let upload_dir = var("UPLOAD_DIR")
+            .unwrap_or_else(|| "static/uploads".into())
+            .into();
+        let max_upload_size = match var("MAX_UPLOAD_SIZE") {
+            Some(size) => size
+                .parse()
+                .map_err(|_| format!("Err: Invalid MAX_UPLOAD_SIZE {}", size))?,
+            None => 10 * 1024 * 1024,
+        };
+        let admin_users = var("ADMIN_USERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(String::from)
+            .collect();
+        let seconds = |name: &str, default: u64| match var(name) {
+            Some(value) => value
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| format!("Err: Invalid {} {}", name, value)),
+            None => Ok(Duration::from_secs(default)),
+        };
+        let session = SessionConfig {
+            idle_timeout: seconds("SESSION_IDLE_TIMEOUT", 3600 * 24)?,
+            max_lifetime: seconds("SESSION_MAX_LIFETIME", 3600 * 24 * 7)?,
+            rotate_interval: seconds("SESSION_ROTATE_INTERVAL", 3600)?,
+        };
+        let prefix = |name: &str, default: u8| match var(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("Err: Invalid {} {}", name, value)),
+            None => Ok(default),
+        };
+        let binding = match var("SESSION_BINDING") {
+            Some(value) => BindingPolicy::parse(
+                &value,
+                prefix("SESSION_BIND_PREFIX_V4", 24)?,
+                prefix("SESSION_BIND_PREFIX_V6", 64)?,
+            )?,
+            None => BindingPolicy::default(),
+        };
+        let access = match (var("ACCESS_RULES"), var("ACCESS_RULES_FILE")) {
+            (Some(_), Some(_)) => {
+                return Err(
+                    "Err: Only one of ACCESS_RULES and ACCESS_RULES_FILE can be set".into(),
+                );
+            }
+            (Some(rules), None) => AccessRules::parse(&rules)?,
+            (None, Some(file)) => AccessRules::parse(
+                &std::fs::read_to_string(&file)
+                    .map_err(|e| format!("Err: Cannot read ACCESS_RULES_FILE {}: {}", file, e))?,
+            )?,
+            (None, None) => AccessRules::default(),
+        };
+        let permissions = match var("ROLE_PERMISSIONS") {
+            Some(value) => RolePermissions::parse(&value)?,
+            None => RolePermissions::default(),
+        };
+        let flag = |name: &str, default: bool| match var(name).as_deref() {
+            None => Ok(default),
+            Some("true" | "1") => Ok(true),
+            Some("false" | "0") => Ok(false),
+            Some(other) => Err(format!("Err: Invalid {} {}", name, other)),
+        };
+        let cookie = CookieConfig {
+            name: var("COOKIE_NAME").unwrap_or_else(|| "key".into()),
+            domain: var("COOKIE_DOMAIN"),
+            path: var("COOKIE_PATH").unwrap_or_else(|| "/".into()),
+            same_site: match var("COOKIE_SAME_SITE").as_deref() {
+                None | Some("lax") => SameSite::Lax,
+                Some("strict") => SameSite::Strict,
+                Some("none") => SameSite::None,
+                Some(other) => {
+                    return Err(format!("Err: Invalid COOKIE_SAME_SITE {}", other).into());
+                }
+            },
+            secure: flag("COOKIE_SECURE", false)?,
+            http_only: flag("COOKIE_HTTP_ONLY", true)?,
+            mode: match var("COOKIE_MODE").as_deref() {
+                None | Some("plain") => CookieMode::Plain,
+                Some("signed") => CookieMode::Signed,
+                Some("private") => CookieMode::Private,
+                Some(other) => return Err(format!("Err: Invalid COOKIE_MODE {}", other).into()),
+            },
+            keys: var("COOKIE_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .filter(|key| !key.is_empty())
+                .map(derive_key)
+                .collect::<Result<_, _>>()?,
+        };
+        if cookie.mode != CookieMode::Plain && cookie.keys.is_empty() {
+            return Err("Err: Signed or private cookies require COOKIE_KEYS".into());
+        }
+        // 浏览器会拒绝不带 Secure 的 SameSite=None
+        if cookie.same_site == SameSite::None && !cookie.secure {
+            return Err("Err: COOKIE_SAME_SITE=none requires COOKIE_SECURE=true".into());
+        }
+        let jwt = match var("SESSION_MODE").as_deref() {
+            None | Some("store") => None,
+            Some("jwt") => {
+                let read = |name: &str| -> Result<Vec<u8>, Box<SyncError>> {
+                    let file = var(name).ok_or_else(|| format!("Err: JWT requires {}", name))?;
+                    std::fs::read(&file)
+                        .map_err(|e| format!("Err: Cannot read {} {}: {}", name, file, e).into())
+                };
+                let mut jwt = match var("JWT_ALGORITHM").as_deref() {
+                    None | Some("HS256") => JwtConfig::hs256(
+                        &var("JWT_SECRET").ok_or("Err: HS256 JWT requires JWT_SECRET")?,
+                    )?,
+                    Some("EdDSA") => JwtConfig::from_pem(
+                        Algorithm::EdDSA,
+                        &read("JWT_PRIVATE_KEY_FILE")?,
+                        &read("JWT_PUBLIC_KEY_FILE")?,
+                    )?,
+                    Some("RS256") => JwtConfig::from_pem(
+                        Algorithm::RS256,
+                        &read("JWT_PRIVATE_KEY_FILE")?,
+                        &read("JWT_PUBLIC_KEY_FILE")?,
+                    )?,
+                    Some(other) => {
+                        return Err(format!("Err: Invalid JWT_ALGORITHM {}", other).into());
+                    }
+                };
+                if let Some(issuer) = var("JWT_ISSUER") {
+                    jwt.issuer = issuer;
+                }
+                if let Some(audience) = var("JWT_AUDIENCE") {
+                    jwt.audience = audience;
+                }
+                jwt.ttl = seconds("JWT_TTL", jwt.ttl.as_secs())?;
+                jwt.leeway = seconds("JWT_LEEWAY", jwt.leeway.as_secs())?;
+                if let Some(claims) = var("JWT_CLAIMS") {
+                    jwt.set_claims(&claims)?;
+                }
+                Some(jwt)
+            }
+            Some(other) => return Err(format!("Err: Invalid SESSION_MODE {}", other).into()),
+        };
+        Ok(Config {
+            listen_addr,
+            store,
+            seed_users,
+            upload_dir,
+            max_upload_size,
+            admin_users,
+            session,
+            binding,
+            access,
+            permissions,
+            cookie,
+            jwt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::security::{access::Access, binding::IpBinding};
+
+    fn config(vars: &[(&str, &str)]) -> Result<Config, Box<SyncError>> {
+        Config::from_vars(|name| {
+            vars.iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.to_string())
+        })
+    }
+
+    #[test]
+    fn from_vars() {
+        let c = config(&[]).unwrap();
+        assert_eq!(c.listen_addr, "127.0.0.1:25823");
+        assert_eq!(c.store, StoreKind::Redis("redis://127.0.0.1:6379".into()));
+
+        let c = config(&[("STORE", "memory"), ("SEED_USERS", "a:1,b:x:y")]).unwrap();
+        assert_eq!(c.store, StoreKind::Memory);
+        assert_eq!(
+            c.seed_users,
+            vec![("a".into(), "1".into()), ("b".into(), "x:y".into())]
+        );
+
+        let c = config(&[("ADMIN_USERS", "root, ops,")]).unwrap();
+        assert_eq!(c.admin_users, vec!["root".to_string(), "ops".to_string()]);
+
+        let c = config(&[("STORE", "file"), ("DATA_DIR", "/var/lib/srs")]).unwrap();
+        assert_eq!(c.store, StoreKind::File("/var/lib/srs".into()));
+
+        let c = config(&[
+            ("SESSION_IDLE_TIMEOUT", "600"),
+            ("SESSION_MAX_LIFETIME", "3600"),
+        ])
+        .unwrap();
+        assert_eq!(c.session.idle_timeout, Duration::from_secs(600));
+        // 接近最长有效期时剩余时间不足一个空闲周期
+        assert_eq!(c.session.ttl(1000, 1000), Duration::from_secs(600));
+        assert_eq!(c.session.ttl(1000, 4400), Duration::from_secs(200));
+        assert_eq!(c.session.ttl(1000, 5000), Duration::ZERO);
+
+        let c = config(&[
+            ("SESSION_BINDING", "prefix,ua"),
+            ("SESSION_BIND_PREFIX_V4", "16"),
+        ])
+        .unwrap();
+        assert_eq!(c.binding.ip, IpBinding::Prefix { v4: 16, v6: 64 });
+        assert!(c.binding.user_agent);
+        assert_eq!(config(&[]).unwrap().binding.ip, IpBinding::Exact);
+        assert!(
+            config(&[
+                ("SESSION_BINDING", "prefix"),
+                ("SESSION_BIND_PREFIX_V6", "129")
+            ])
+            .is_err()
+        );
+
+        let c = config(&[("ACCESS_RULES", "* /** public")]).unwrap();
+        assert_eq!(c.access.access("GET", "/1.txt"), &Access::Public);
+        assert!(config(&[("ACCESS_RULES", "* /** everyone")]).is_err());
+        assert!(config(&[("ACCESS_RULES_FILE", "/nonexistent/rules")]).is_err());
+        let c = config(&[("ROLE_PERMISSIONS", "editor=upload")]).unwrap();
+        assert!(c.permissions.allows(&["editor".into()], "upload"));
+        assert!(config(&[("ROLE_PERMISSIONS", "admin=upload")]).is_err());
+
+        let key = "k".repeat(32);
+        let c = config(&[
+            ("COOKIE_MODE", "private"),
+            ("COOKIE_KEYS", &key),
+            ("COOKIE_SECURE", "1"),
+        ])
+        .unwrap();
+        assert_eq!(c.cookie.mode, CookieMode::Private);
+        assert!(c.cookie.secure && c.cookie.http_only);
+        assert!(config(&[("COOKIE_MODE", "signed")]).is_err());
+        assert!(config(&[("COOKIE_KEYS", "too-short")]).is_err());
+        assert!(config(&[("COOKIE_SAME_SITE", "none")]).is_err());
+
+        assert!(config(&[]).unwrap().jwt.is_none());
+        let secret = "s".repeat(32);
+        let c = config(&[
+            ("SESSION_MODE", "jwt"),
+            ("JWT_SECRET", &secret),
+            ("JWT_TTL", "600"),
+            ("JWT_ISSUER", "https://srs.example"),
+            ("JWT_CLAIMS", r#"{"tenant": "a"}"#),
+        ])
+        .unwrap();
+        let jwt = c.jwt.unwrap();
+        assert_eq!(jwt.ttl, Duration::from_secs(600));
+        assert_eq!(jwt.issuer, "https://srs.example");
+        assert_eq!(jwt.audience, "srs");
+        assert!(config(&[("SESSION_MODE", "jwt")]).is_err());
+        assert!(
+            config(&[
+                ("SESSION_MODE", "jwt"),
+                ("JWT_ALGORITHM", "RS256"),
+                ("JWT_SECRET", &secret)
+            ])
+            .is_err()
+        );
+        assert!(
+            config(&[
+                ("SESSION_MODE", "jwt"),
+                ("JWT_SECRET", &secret),
+                ("JWT_CLAIMS", r#"{"exp": 1}"#)
+            ])
+            .is_err()
+        );
+        assert!(config(&[("SESSION_MODE", "cookie")]).is_err());
+
+        assert!(config(&[("STORE", "mongo")]).is_err());
+        assert!(config(&[("SESSION_ROTATE_INTERVAL", "1h")]).is_err());
+        assert!(config(&[("MAX_UPLOAD_SIZE", "10M")]).is_err());
+        assert!(config(&[("SEED_USERS", "nopassword")]).is_err());
+    }
+}
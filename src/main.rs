@@ -7,11 +7,22 @@ mod middleware;
 
 mod protocol;
 
+mod config;
+
+mod store;
+
+mod security;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<SyncError>> {
-    let listen_addr = "127.0.0.1:25823";
-    let db_addr = "127.0.0.1:6379";
-    let mut server = server::Server::new(listen_addr, db_addr).await?;
+    let config = config::Config::from_env()?;
+    let db = store::DataBase::open(&config.store)?;
+    db.load_scripts().await;
+    // 只创建不存在的用户，不覆盖用户已修改的密码
+    for (user, password) in &config.seed_users {
+        db.users.create_user(user, password).await?;
+    }
+    let mut server = server::Server::new(config, db).await?;
     server.run().await?;
 
     Ok(())
@@ -1,44 +1,341 @@
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc, time::Duration};
 
+use base64::{Engine, engine::general_purpose::STANDARD};
 use httparse::Request;
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use tokio::io::AsyncWriteExt;
 
-use crate::{protocol::Redis, server::SyncError};
+use serde_json::json;
+use subtle::ConstantTimeEq;
 
+use crate::{
+    config::Config,
+    middleware::log::security_event,
+    router::{
+        Context, header,
+        json::{send_json, wants_json},
+    },
+    security::{access::Access, jwt, tokens},
+    server::{AppState, SyncError, conn::Conn},
+    store::{Session, now},
+};
+
+pub const LOGIN_PAGE: &str = "/srs/LoginInterface.html";
+// WWW-Authenticate 质询中的 realm
+const REALM: &str = "srs";
+
+// 最后访问时间与有效期的刷新间隔，避免每个请求都写一次存储
+const TOUCH_INTERVAL: u64 = 60;
+// 更换会话键后旧键的保留时间，供同时发出的其他请求使用
+const ROTATION_GRACE: Duration = Duration::from_secs(30);
+
+// 请求所用的凭据
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credential {
+    // 浏览器的会话 Cookie
+    Session,
+    // Authorization: Basic，每个请求都校验密码
+    Basic,
+    // Authorization: Bearer API 令牌，权限还受令牌范围限制
+    Token {
+        id: String,
+        scopes: Vec<String>,
+    },
+    // JWT 模式下的登录令牌，由 Cookie 或 Authorization: Bearer 携带
+    Jwt {
+        jti: String,
+        expires_at: u64,
+        cookie: bool,
+    },
+}
+
+// 已登录请求的身份
+pub struct Identity {
+    // 会话键，只在以会话 Cookie 登录时有效
+    pub key: String,
+    // Basic、令牌与 JWT 方式的会话记录只存在于本次请求
+    pub session: Session,
+    pub credential: Credential,
+}
+
+impl Identity {
+    pub fn can(&self, config: &Config, permission: &str) -> bool {
+        config.permissions.allows(&self.session.roles, permission)
+            && match &self.credential {
+                Credential::Token { scopes, .. } => {
+                    scopes.iter().any(|s| s == "*" || s == permission)
+                }
+                _ => true,
+            }
+    }
+
+    // 请求头 X-CSRF-Token 须与会话中的令牌一致；浏览器不会自动携带令牌，Basic 质询也只发给已使用 Basic 的客户端，
+    // 浏览器不会缓存 Basic 凭据，其他凭据无需校验
+    pub fn check_csrf(&self, req: &Request<'_, '_>) -> bool {
+        if !matches!(
+            self.credential,
+            Credential::Session | Credential::Jwt { cookie: true, .. }
+        ) {
+            return true;
+        }
+        header(req, "X-CSRF-Token").is_some_and(|token| {
+            token
+                .as_bytes()
+                .ct_eq(self.session.csrf_token.as_bytes())
+                .into()
+        })
+    }
+}
+
+// 按访问规则鉴权，通过时返回请求上下文，否则回复 401（或跳转登录页）、403 并返回 None
 pub async fn auth(
-    stream: &mut TcpStream,
-    db: Arc<Redis>,
+    stream: &mut Conn,
+    state: Arc<AppState>,
     req: &Request<'_, '_>,
-) -> Result<bool, Box<SyncError>> {
-    // SRS 目录不需要特殊权限就可以进入
-    let path = req.path.unwrap();
-    if path.to_uppercase().starts_with("/SRS") && !path.contains("..") {
-        return Ok(true);
-    }
-    // 否则进行鉴权
-    let cookie_header = req
-        .headers
-        .iter()
-        .find(|h| h.name.eq_ignore_ascii_case("Cookie"))
-        .and_then(|h| str::from_utf8(h.value).ok());
-
-    if let Some(cookie_raw) = cookie_header {
-        let cookies = cookie::Cookie::split_parse(cookie_raw);
-        for cookie in cookies {
-            let cookie = cookie?;
-            if cookie.name() == "key"
-                && db
-                    .judge_session_key(cookie.value_trimmed(), stream.peer_addr()?.ip().to_string())
-                    .await?
-            {
-                return Ok(true);
+) -> Result<Option<Context>, Box<SyncError>> {
+    let mut ctx = Context::default();
+    let path = req.path.unwrap_or("/");
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let access = state
+        .config
+        .access
+        .access(req.method.unwrap_or_default(), path);
+    // 带有 Authorization 的请求不再查看 Cookie，凭据无效时拒绝；公开路径按未登录处理
+    if let Some(authorization) = header(req, "Authorization") {
+        match authenticate(stream, &state, req, authorization).await? {
+            Some(identity) => ctx.identity = Some(identity),
+            None if matches!(access, Access::Public) => {}
+            None => {
+                unauthorized(stream, req, Some("invalid credentials")).await?;
+                return Ok(None);
             }
         }
+    } else if state.config.jwt.is_some() {
+        if let Some(token) = state.config.cookie.session_key(req) {
+            ctx.identity = verify_jwt(stream, &state, req, &token, true).await?;
+        }
+    } else if let Some(key) = state.config.cookie.session_key(req)
+        && let Some(session) = state.db.sessions.session(&key).await?
+    {
+        let ip = stream.peer_addr()?.ip();
+        let user_agent = header(req, "User-Agent").unwrap_or_default();
+        match state.config.binding.check(&session, ip, user_agent) {
+            Ok(()) => ctx.identity = renew(stream, &state, key, session, ip, user_agent).await?,
+            // 会话键可能已泄露，按未登录处理
+            Err(mismatch) => security_event(
+                "binding_mismatch",
+                &key,
+                &session,
+                json!({
+                    "reason": mismatch.as_str(),
+                    "bound_ip": session.ip,
+                    "ip": ip.to_string(),
+                    "bound_user_agent": session.user_agent,
+                    "user_agent": user_agent,
+                }),
+            ),
+        }
+    }
+    let granted = match (&ctx.identity, access) {
+        (_, Access::Public) => true,
+        (None, _) => {
+            unauthorized(stream, req, None).await?;
+            return Ok(None);
+        }
+        (Some(_), Access::Authenticated) => true,
+        (Some(identity), Access::Role(role)) => identity.session.has_role(role),
+        (Some(identity), Access::Permission(permission)) => identity.can(&state.config, permission),
+    };
+    if !granted {
+        // 令牌范围不足时提示所需的权限
+        let challenge = match (&ctx.identity, access) {
+            (
+                Some(Identity {
+                    credential: Credential::Token { .. },
+                    ..
+                }),
+                Access::Permission(permission),
+            ) => Some(format!(
+                "WWW-Authenticate: Bearer realm=\"{}\", error=\"insufficient_scope\", scope=\"{}\"",
+                REALM, permission
+            )),
+            _ => None,
+        };
+        forbidden(stream, req, challenge).await?;
+        return Ok(None);
     }
-    // 鉴权失败
-    let body = "<!DOCTYPE html><html><head><meta http-equiv=\"refresh\" content=\"4; url=/srs/LoginInterface.html\"><title>NOT Authoried</title></head><body><h1>NOT Authoried</h1></body></html>";
+    Ok(Some(ctx))
+}
+
+// 校验 Authorization 中的 Basic 密码或 Bearer 令牌，无效时返回 None
+async fn authenticate(
+    stream: &Conn,
+    state: &AppState,
+    req: &Request<'_, '_>,
+    authorization: &str,
+) -> Result<Option<Identity>, Box<SyncError>> {
+    let Some((scheme, value)) = authorization.trim().split_once(' ') else {
+        return Ok(None);
+    };
+    let value = value.trim();
+    let (user, credential) =
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let Some((user, password)) = STANDARD
+                .decode(value)
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .and_then(|decoded| {
+                    let (user, password) = decoded.split_once(':')?;
+                    Some((user.to_string(), password.to_string()))
+                })
+            else {
+                return Ok(None);
+            };
+            // 短时间内校验过的凭据不再计算哈希；失败过多的 IP 直接拒绝
+            let basic = &state.basic;
+            if !basic.is_verified(&user, &password) {
+                let ip = stream.peer_addr()?.ip();
+                if basic.is_throttled(ip) {
+                    return Ok(None);
+                }
+                if !state.db.users.verify_password(&user, &password).await? {
+                    basic.failed(ip);
+                    return Ok(None);
+                }
+                basic.verified(&user, &password);
+            }
+            (user, Credential::Basic)
+        } else if scheme.eq_ignore_ascii_case("Bearer") {
+            if state.config.jwt.is_some() && jwt::is_jwt(value) {
+                return verify_jwt(stream, state, req, value, false).await;
+            }
+            let Some((id, secret)) = tokens::parse(value) else {
+                return Ok(None);
+            };
+            let now = now();
+            let Some(token) = state.db.tokens.token(id).await?.filter(|token| {
+                !token.is_expired(now) && tokens::verify_secret(secret, &token.hash)
+            }) else {
+                return Ok(None);
+            };
+            if token
+                .last_used
+                .is_none_or(|last_used| now >= last_used + TOUCH_INTERVAL)
+            {
+                state.db.tokens.touch_token(id, now).await?;
+            }
+            let credential = Credential::Token {
+                id: token.id,
+                scopes: token.scopes,
+            };
+            (token.user, credential)
+        } else {
+            return Ok(None);
+        };
+    let session = Session::new(
+        &user,
+        user_roles(state, &user).await?,
+        &stream.peer_addr()?.ip().to_string(),
+        header(req, "User-Agent").unwrap_or_default(),
+    );
+    Ok(Some(Identity {
+        key: String::new(),
+        session,
+        credential,
+    }))
+}
+
+// JWT 模式：校验签名与声明后由声明还原会话，不读取会话存储，只查询注销列表
+async fn verify_jwt(
+    stream: &Conn,
+    state: &AppState,
+    req: &Request<'_, '_>,
+    token: &str,
+    cookie: bool,
+) -> Result<Option<Identity>, Box<SyncError>> {
+    let Some(claims) = state.config.jwt.as_ref().and_then(|jwt| jwt.verify(token)) else {
+        return Ok(None);
+    };
+    if state.db.sessions.is_denied(&claims.jti).await? {
+        return Ok(None);
+    }
+    let session = Session {
+        user: claims.sub,
+        roles: claims.roles,
+        ip: stream.peer_addr()?.ip().to_string(),
+        user_agent: header(req, "User-Agent").unwrap_or_default().to_string(),
+        csrf_token: claims.csrf,
+        created_at: claims.iat,
+        last_seen: now(),
+        rotated_at: claims.iat,
+        replaced: false,
+    };
+    Ok(Some(Identity {
+        key: String::new(),
+        session,
+        credential: Credential::Jwt {
+            jti: claims.jti,
+            expires_at: claims.exp,
+            cookie,
+        },
+    }))
+}
+
+// 未登录：API 客户端得到带 WWW-Authenticate 的 JSON 401，浏览器跳转到登录页并带上原地址
+pub(crate) async fn unauthorized(
+    stream: &mut Conn,
+    req: &Request<'_, '_>,
+    error: Option<&str>,
+) -> Result<(), Box<SyncError>> {
+    if wants_json(req) || error.is_some() {
+        // 只向已经使用 Basic 的客户端发出 Basic 质询，浏览器不会因此弹窗并缓存密码，
+        // 也就不会在跨站请求中自动带上 Basic 凭据
+        let basic = header(req, "Authorization").is_some_and(|authorization| {
+            authorization
+                .trim_start()
+                .get(..6)
+                .is_some_and(|scheme| scheme.eq_ignore_ascii_case("Basic "))
+        });
+        let basic = match basic {
+            true => format!("Basic realm=\"{}\", ", REALM),
+            false => String::new(),
+        };
+        let challenge = match error {
+            Some(_) => format!(
+                "WWW-Authenticate: {}Bearer realm=\"{}\", error=\"invalid_token\"",
+                basic, REALM
+            ),
+            None => format!("WWW-Authenticate: {}Bearer realm=\"{}\"", basic, REALM),
+        };
+        let body = json!({"ok": false, "error": error.unwrap_or("authentication required")});
+        return send_json(stream, "401 Unauthorized", &[challenge], &body).await;
+    }
+    let next = utf8_percent_encode(req.path.unwrap_or("/"), NON_ALPHANUMERIC);
     let response = format!(
-        "HTTP/1.1 401 NOT Authoried\r\n\
+        "HTTP/1.1 303 See Other\r\n\
+        Location: {}?next={}\r\n\
+        Content-Length: 0\r\n\
+        Connection: close\r\n\r\n",
+        LOGIN_PAGE, next
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+// 已登录但缺少所需角色或权限
+async fn forbidden(
+    stream: &mut Conn,
+    req: &Request<'_, '_>,
+    challenge: Option<String>,
+) -> Result<(), Box<SyncError>> {
+    if wants_json(req) || challenge.is_some() {
+        let body = json!({"ok": false, "error": "permission denied"});
+        return send_json(stream, "403 Forbidden", &Vec::from_iter(challenge), &body).await;
+    }
+    let body = "<!DOCTYPE html><html><head><title>Forbidden</title></head><body><h1>Forbidden</h1></body></html>";
+    let response = format!(
+        "HTTP/1.1 403 Forbidden\r\n\
             Content-Type: text/html\r\n\
             Content-Length: {}\r\n\
             Connection: close\r\n\r\n\
@@ -46,8 +343,112 @@ pub async fn auth(
         body.len(),
         body
     );
-
     stream.write_all(response.as_bytes()).await?;
     stream.flush().await?;
-    Ok(false)
+    Ok(())
+}
+
+// 写入会话的角色：内置角色加上用户存储中分配的角色，每次请求与会话比对，变化时立即生效
+pub async fn user_roles(state: &AppState, user: &str) -> Result<Vec<String>, Box<SyncError>> {
+    let mut roles = state.config.roles(user);
+    for role in state.db.users.roles(user).await? {
+        if !roles.contains(&role) {
+            roles.push(role);
+        }
+    }
+    Ok(roles)
+}
+
+// 会话 Cookie 保留到会话的最长有效期，空闲超时由服务端判断
+pub fn session_cookie(config: &Config, key: &str, session: &Session) -> String {
+    let max_age = config.session.lifetime_left(session.created_at, now());
+    config.cookie.set_cookie(key, max_age)
+}
+
+// 顺延会话的有效期，定期或角色变化时更换会话键；超过最长有效期时注销会话并返回 None
+async fn renew(
+    stream: &mut Conn,
+    state: &AppState,
+    key: String,
+    mut session: Session,
+    ip: IpAddr,
+    user_agent: &str,
+) -> Result<Option<Identity>, Box<SyncError>> {
+    let policy = &state.config.session;
+    let sessions = &state.db.sessions;
+    let now = now();
+    let ttl = policy.ttl(session.created_at, now);
+    if ttl.is_zero() {
+        sessions.delete_session(&key).await?;
+        return Ok(None);
+    }
+    // 旧会话键在宽限期结束后自然过期，不走顺延与更换
+    if session.replaced {
+        return Ok(Some(Identity {
+            key,
+            session,
+            credential: Credential::Session,
+        }));
+    }
+    let current = session.clone();
+    // 绑定策略允许的客户端变化，记录后以新的 IP 与 User-Agent 继续绑定
+    let ip = ip.to_string();
+    let moved = state.config.binding.changed(&session, &ip, user_agent);
+    if moved {
+        security_event(
+            "binding_changed",
+            &key,
+            &session,
+            json!({
+                "bound_ip": session.ip,
+                "ip": ip,
+                "bound_user_agent": session.user_agent,
+                "user_agent": user_agent,
+            }),
+        );
+        session.ip = ip;
+        session.user_agent = user_agent.to_string();
+    }
+    let roles = user_roles(state, &session.user).await?;
+    if roles != session.roles || now >= session.rotated_at + policy.rotate_interval.as_secs() {
+        session.roles = roles;
+        session.rotated_at = now;
+        session.last_seen = now;
+        // 旧会话键短暂保留后自然过期；并发的请求中只有一个能完成更换，其余继续使用旧键
+        let retired = Session {
+            replaced: true,
+            ..session.clone()
+        };
+        if !sessions
+            .replace_session(&key, &current, &retired, ROTATION_GRACE.min(ttl))
+            .await?
+        {
+            return Ok(Some(Identity {
+                key,
+                session,
+                credential: Credential::Session,
+            }));
+        }
+        let new_key = loop {
+            let new_key = uuid::Uuid::new_v4().to_string();
+            if sessions.create_session(&new_key, &session, ttl).await? {
+                break new_key;
+            }
+        };
+        stream.add_header(session_cookie(&state.config, &new_key, &session));
+        return Ok(Some(Identity {
+            key: new_key,
+            session,
+            credential: Credential::Session,
+        }));
+    }
+    if moved || now >= session.last_seen + TOUCH_INTERVAL {
+        session.last_seen = now;
+        sessions.update_session(&key, &session, ttl).await?;
+    }
+    Ok(Some(Identity {
+        key,
+        session,
+        credential: Credential::Session,
+    }))
 }
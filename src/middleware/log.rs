@@ -0,0 +1,43 @@
+use serde_json::{Value, json};
+
+use crate::store::{Session, now, session_id};
+
+// 安全事件以单行 JSON 写到标准错误，便于日志系统采集；detail 中的字段合并到记录中
+pub fn security_event(event: &str, key: &str, session: &Session, detail: Value) {
+    eprintln!("security: {}", event_record(event, key, session, detail));
+}
+
+fn event_record(event: &str, key: &str, session: &Session, detail: Value) -> Value {
+    let mut record = json!({
+        "time": now(),
+        "event": event,
+        "user": session.user,
+        "session": session_id(key),
+    });
+    if let (Value::Object(record), Value::Object(detail)) = (&mut record, detail) {
+        record.extend(detail);
+    }
+    record
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let session = Session::new("alice", Vec::new(), "10.0.0.1", "curl");
+        let record = event_record(
+            "binding_changed",
+            "secret-key",
+            &session,
+            json!({ "ip": "10.0.0.2\nforged" }),
+        );
+        assert_eq!(record["event"], "binding_changed");
+        assert_eq!(record["user"], "alice");
+        assert_eq!(record["session"], session_id("secret-key"));
+        // 字段经过转义，客户端提供的值无法伪造日志行
+        let line = record.to_string();
+        assert!(!line.contains('\n') && !line.contains("secret-key"));
+    }
+}
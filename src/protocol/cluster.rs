@@ -0,0 +1,515 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use tokio::time::timeout;
+
+use crate::{
+    protocol::{
+        config::{RedisAddr, RedisConfig, RedisMode},
+        conn::{Connection, command},
+        resp::RespValue,
+        types::FromResp,
+    },
+    server::SyncError,
+};
+
+pub const SLOT_COUNT: u16 = 16384;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+    pub master: RedisAddr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redirect {
+    // 槽已永久迁移，需要更新路由表
+    Moved(u16, RedisAddr),
+    // 槽正在迁移，仅本次请求改发到目标节点（需先发送 ASKING）
+    Ask(u16, RedisAddr),
+}
+
+// 集群拓扑：哈希槽到主节点的映射，按需从 CLUSTER SLOTS / CLUSTER SHARDS 刷新
+pub struct Cluster {
+    config: RedisConfig,
+    slots: RwLock<Vec<SlotRange>>,
+    stale: AtomicBool,
+}
+
+impl Cluster {
+    pub fn new(config: &RedisConfig) -> Option<Self> {
+        if config.mode != RedisMode::Cluster {
+            return None;
+        }
+        Some(Self {
+            config: config.clone(),
+            slots: RwLock::new(Vec::new()),
+            stale: AtomicBool::new(true),
+        })
+    }
+
+    // 负责该槽的主节点；没有键的命令发往任意已知主节点
+    pub async fn node_for(&self, slot: Option<u16>) -> Result<RedisAddr, Box<SyncError>> {
+        if self.stale.load(Ordering::Relaxed) {
+            self.refresh().await?;
+        }
+        let slots = self.slots.read().unwrap();
+        let found = match slot {
+            Some(slot) => slots.iter().find(|r| r.start <= slot && slot <= r.end),
+            None => slots.first(),
+        };
+        match found {
+            Some(range) => Ok(range.master.clone()),
+            None => Err(format!("Err: Redis cluster slot {:?} is not covered", slot).into()),
+        }
+    }
+
+    // 收到 MOVED 后立即修正该槽，并在下次请求前刷新完整拓扑
+    pub fn moved(&self, slot: u16, addr: RedisAddr) {
+        let mut slots = self.slots.write().unwrap();
+        let mut patched = Vec::with_capacity(slots.len() + 2);
+        for range in slots.drain(..) {
+            if range.start <= slot && slot <= range.end {
+                if range.start < slot {
+                    patched.push(SlotRange {
+                        end: slot - 1,
+                        ..range.clone()
+                    });
+                }
+                if slot < range.end {
+                    patched.push(SlotRange {
+                        start: slot + 1,
+                        ..range.clone()
+                    });
+                }
+            } else {
+                patched.push(range);
+            }
+        }
+        patched.push(SlotRange {
+            start: slot,
+            end: slot,
+            master: addr,
+        });
+        patched.sort_by_key(|r| r.start);
+        *slots = patched;
+        self.mark_stale();
+    }
+
+    pub fn mark_stale(&self) {
+        self.stale.store(true, Ordering::Relaxed);
+    }
+
+    async fn refresh(&self) -> Result<(), Box<SyncError>> {
+        // 优先询问已知主节点，其次是种子节点
+        let mut candidates: Vec<RedisAddr> = Vec::new();
+        for node in self
+            .slots
+            .read()
+            .unwrap()
+            .iter()
+            .map(|r| r.master.clone())
+            .chain(self.config.nodes.iter().cloned())
+        {
+            if !candidates.contains(&node) {
+                candidates.push(node);
+            }
+        }
+        let mut errors = Vec::new();
+        for node in candidates {
+            match self.fetch_slots(&node).await {
+                Ok(ranges) if !ranges.is_empty() => {
+                    *self.slots.write().unwrap() = ranges;
+                    self.stale.store(false, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Ok(_) => errors.push(format!("{}: no slots assigned", node)),
+                Err(e) => errors.push(format!("{}: {}", node, e)),
+            }
+        }
+        Err(format!(
+            "Err: Unable to load Redis cluster topology ({})",
+            errors.join("; ")
+        )
+        .into())
+    }
+
+    async fn fetch_slots(&self, node: &RedisAddr) -> Result<Vec<SlotRange>, Box<SyncError>> {
+        let mut conn = timeout(
+            self.config.connect_timeout,
+            Connection::connect(node, &self.config),
+        )
+        .await
+        .map_err(|_| "connect timed out")??;
+        let reply = timeout(
+            self.config.command_timeout,
+            conn.request(command(["CLUSTER", "SLOTS"])),
+        )
+        .await
+        .map_err(|_| "command timed out")??;
+        if let RespValue::Error(_) = reply {
+            // CLUSTER SLOTS 在新版本中已弃用，改用 CLUSTER SHARDS
+            let reply = timeout(
+                self.config.command_timeout,
+                conn.request(command(["CLUSTER", "SHARDS"])),
+            )
+            .await
+            .map_err(|_| "command timed out")??;
+            return parse_shards(reply, node);
+        }
+        parse_slots(reply, node)
+    }
+}
+
+// CLUSTER SLOTS: [[start, end, [host, port, id, ...], replicas...], ...]
+pub fn parse_slots(
+    reply: RespValue,
+    queried: &RedisAddr,
+) -> Result<Vec<SlotRange>, Box<SyncError>> {
+    let mut ranges = Vec::new();
+    for entry in Vec::<Vec<RespValue>>::from_resp(reply)? {
+        let mut entry = entry.into_iter();
+        let (Some(start), Some(end), Some(master)) = (entry.next(), entry.next(), entry.next())
+        else {
+            return Err("Err: malformed CLUSTER SLOTS entry".into());
+        };
+        let mut master = Vec::<RespValue>::from_resp(master)?.into_iter();
+        let (Some(host), Some(port)) = (master.next(), master.next()) else {
+            return Err("Err: malformed CLUSTER SLOTS node".into());
+        };
+        let Some(addr) = node_addr(&String::from_resp(host)?, i64::from_resp(port)?, queried)
+        else {
+            continue;
+        };
+        ranges.push(SlotRange {
+            start: slot_number(i64::from_resp(start)?)?,
+            end: slot_number(i64::from_resp(end)?)?,
+            master: addr,
+        });
+    }
+    ranges.sort_by_key(|r| r.start);
+    Ok(ranges)
+}
+
+// CLUSTER SHARDS: [["slots", [start, end, ...], "nodes", [[k, v, ...], ...]], ...]
+pub fn parse_shards(
+    reply: RespValue,
+    queried: &RedisAddr,
+) -> Result<Vec<SlotRange>, Box<SyncError>> {
+    let mut ranges = Vec::new();
+    for shard in Vec::<RespValue>::from_resp(reply)? {
+        let mut shard = HashMap::<String, RespValue>::from_resp(shard)?;
+        let slots = Vec::<i64>::from_resp(shard.remove("slots").unwrap_or(RespValue::Null))?;
+        let nodes = Vec::<RespValue>::from_resp(shard.remove("nodes").unwrap_or(RespValue::Null))?;
+        let mut master = None;
+        for node in nodes {
+            let node = HashMap::<String, RespValue>::from_resp(node)?;
+            let field = |name: &str| node.get(name).cloned().map(String::from_resp);
+            if field("role").transpose()?.as_deref() != Some("master") {
+                continue;
+            }
+            let host = match field("endpoint").transpose()? {
+                Some(endpoint) if endpoint != "?" && !endpoint.is_empty() => endpoint,
+                _ => field("ip").transpose()?.unwrap_or_default(),
+            };
+            let port = node
+                .get("port")
+                .or_else(|| node.get("tls-port"))
+                .cloned()
+                .map(i64::from_resp)
+                .transpose()?
+                .ok_or("Err: CLUSTER SHARDS node without port")?;
+            master = node_addr(&host, port, queried);
+        }
+        let Some(master) = master else {
+            continue;
+        };
+        for pair in slots.chunks(2) {
+            if let [start, end] = pair {
+                ranges.push(SlotRange {
+                    start: slot_number(*start)?,
+                    end: slot_number(*end)?,
+                    master: master.clone(),
+                });
+            }
+        }
+    }
+    ranges.sort_by_key(|r| r.start);
+    Ok(ranges)
+}
+
+// 空主机名表示被询问的节点本身，"?" 表示地址未知
+fn node_addr(host: &str, port: i64, queried: &RedisAddr) -> Option<RedisAddr> {
+    let port = u16::try_from(port).ok()?;
+    match host {
+        "?" => None,
+        "" => match queried {
+            RedisAddr::Tcp { host, .. } => Some(RedisAddr::Tcp {
+                host: host.clone(),
+                port,
+            }),
+            RedisAddr::Unix(_) => None,
+        },
+        host => Some(RedisAddr::Tcp {
+            host: host.to_string(),
+            port,
+        }),
+    }
+}
+
+fn slot_number(n: i64) -> Result<u16, Box<SyncError>> {
+    u16::try_from(n)
+        .ok()
+        .filter(|slot| *slot < SLOT_COUNT)
+        .ok_or_else(|| format!("Err: invalid cluster slot {}", n).into())
+}
+
+// MOVED 3999 127.0.0.1:6381 / ASK 3999 127.0.0.1:6381
+pub fn parse_redirect(err: &str) -> Option<Redirect> {
+    let mut parts = err.split_whitespace();
+    let kind = parts.next()?;
+    let slot = parts.next()?.parse::<u16>().ok()?;
+    let (host, port) = parts.next()?.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addr = RedisAddr::Tcp {
+        host: host.to_string(),
+        port: port.parse().ok()?,
+    };
+    match kind {
+        "MOVED" => Some(Redirect::Moved(slot, addr)),
+        "ASK" => Some(Redirect::Ask(slot, addr)),
+        _ => None,
+    }
+}
+
+// CRC16-CCITT (XMODEM)，与 Redis 集群规范一致
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// 键中第一个非空的 {hashtag} 决定槽位，保证相关键落在同一节点
+pub fn key_slot(key: &str) -> u16 {
+    let bytes = key.as_bytes();
+    let hashed = match bytes.iter().position(|&b| b == b'{') {
+        Some(open) => match bytes[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &bytes[open + 1..open + 1 + len],
+            _ => bytes,
+        },
+        None => bytes,
+    };
+    crc16(hashed) % SLOT_COUNT
+}
+
+// 命令中决定路由的键，返回 None 表示可发往任意节点
+pub fn command_slot(cmd: &[String]) -> Option<u16> {
+    let name = cmd.first()?.to_ascii_uppercase();
+    let key = match name.as_str() {
+        "PING" | "SCRIPT" | "PUBLISH" | "SCAN" | "INFO" | "CLUSTER" | "ASKING" => None,
+        // EVAL script numkeys key [key ...] arg [arg ...]
+        "EVAL" | "EVALSHA" => match cmd.get(2)?.parse::<usize>().ok()? {
+            0 => None,
+            _ => cmd.get(3),
+        },
+        _ => cmd.get(1),
+    };
+    key.map(|k| key_slot(k))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::protocol::{Redis, conn::test::fake_server};
+
+    fn bulk(s: &str) -> RespValue {
+        RespValue::BulkString(Some(s.into()))
+    }
+
+    fn tcp(s: &str) -> RedisAddr {
+        let (host, port) = s.rsplit_once(':').unwrap();
+        RedisAddr::Tcp {
+            host: host.into(),
+            port: port.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn slots_and_hashtags() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(key_slot("foo"), 12182);
+        assert_eq!(
+            key_slot("{user1000}.following"),
+            key_slot("{user1000}.followers")
+        );
+        assert_eq!(key_slot("foo{}{bar}"), crc16(b"foo{}{bar}") % SLOT_COUNT);
+        assert_eq!(key_slot("foo{{bar}}zap"), crc16(b"{bar") % SLOT_COUNT);
+        assert_eq!(key_slot("foo{bar}{zap}"), key_slot("bar"));
+
+        let cmd = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(command_slot(&cmd(&["GET", "foo"])), Some(12182));
+        assert_eq!(
+            command_slot(&cmd(&["EVALSHA", "abc", "1", "foo", "x"])),
+            Some(12182)
+        );
+        assert_eq!(command_slot(&cmd(&["EVAL", "return 1", "0"])), None);
+        assert_eq!(command_slot(&cmd(&["SCRIPT", "LOAD", "return 1"])), None);
+    }
+
+    #[test]
+    fn redirects() {
+        assert_eq!(
+            parse_redirect("MOVED 3999 127.0.0.1:6381"),
+            Some(Redirect::Moved(3999, tcp("127.0.0.1:6381")))
+        );
+        assert_eq!(
+            parse_redirect("ASK 1 [::1]:7000"),
+            Some(Redirect::Ask(1, tcp("::1:7000")))
+        );
+        assert_eq!(parse_redirect("ERR wrong"), None);
+    }
+
+    #[test]
+    fn parse_topology() {
+        let queried = tcp("10.0.0.1:7000");
+        let slots = RespValue::Array(vec![
+            RespValue::Array(vec![
+                RespValue::Integer(5461),
+                RespValue::Integer(16383),
+                RespValue::Array(vec![bulk("10.0.0.2"), RespValue::Integer(7001)]),
+            ]),
+            RespValue::Array(vec![
+                RespValue::Integer(0),
+                RespValue::Integer(5460),
+                RespValue::Array(vec![bulk(""), RespValue::Integer(7000), bulk("id")]),
+                RespValue::Array(vec![bulk("10.0.0.3"), RespValue::Integer(7002)]),
+            ]),
+        ]);
+        assert_eq!(
+            parse_slots(slots, &queried).unwrap(),
+            vec![
+                SlotRange {
+                    start: 0,
+                    end: 5460,
+                    master: tcp("10.0.0.1:7000")
+                },
+                SlotRange {
+                    start: 5461,
+                    end: 16383,
+                    master: tcp("10.0.0.2:7001")
+                },
+            ]
+        );
+
+        let node = |ip: &str, port: i64, role: &str| {
+            RespValue::Array(vec![
+                bulk("ip"),
+                bulk(ip),
+                bulk("port"),
+                RespValue::Integer(port),
+                bulk("endpoint"),
+                bulk(ip),
+                bulk("role"),
+                bulk(role),
+            ])
+        };
+        let shards = RespValue::Array(vec![RespValue::Array(vec![
+            bulk("slots"),
+            RespValue::Array(vec![
+                RespValue::Integer(0),
+                RespValue::Integer(99),
+                RespValue::Integer(200),
+                RespValue::Integer(299),
+            ]),
+            bulk("nodes"),
+            RespValue::Array(vec![
+                node("10.0.0.4", 7004, "replica"),
+                node("10.0.0.5", 7005, "master"),
+            ]),
+        ])]);
+        let ranges = parse_shards(shards, &queried).unwrap();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[1].start, 200);
+        assert_eq!(ranges[1].master, tcp("10.0.0.5:7005"));
+    }
+
+    #[test]
+    fn moved_patches_single_slot() {
+        let config = RedisConfig::parse("redis+cluster://a:1").unwrap();
+        let cluster = Cluster::new(&config).unwrap();
+        *cluster.slots.write().unwrap() = vec![SlotRange {
+            start: 0,
+            end: SLOT_COUNT - 1,
+            master: tcp("a:1"),
+        }];
+        cluster.moved(100, tcp("b:2"));
+        let slots = cluster.slots.read().unwrap();
+        assert_eq!(slots.len(), 3);
+        assert_eq!(slots[1].start, 100);
+        assert_eq!(slots[1].end, 100);
+        assert_eq!(slots[1].master, tcp("b:2"));
+        assert_eq!(slots[2].start, 101);
+    }
+
+    #[tokio::test]
+    async fn follows_moved_and_ask() {
+        let log_b = Arc::new(Mutex::new(Vec::new()));
+        let log = log_b.clone();
+        let node_b = fake_server(move |cmd| {
+            log.lock().unwrap().push(cmd.join(" "));
+            match cmd[0].as_str() {
+                "ASKING" => RespValue::SimpleString("OK".into()),
+                _ => bulk(&format!("{}-on-b", cmd[1])),
+            }
+        })
+        .await;
+        let b = node_b.clone();
+        let node_a = Arc::new(Mutex::new(String::new()));
+        let a = node_a.clone();
+        *node_a.lock().unwrap() = fake_server(move |cmd| {
+            let (host, port) = a_addr(&a);
+            match (cmd[0].as_str(), cmd.get(1).map(String::as_str)) {
+                // 节点 A 认为自己拥有全部槽
+                ("CLUSTER", _) => RespValue::Array(vec![RespValue::Array(vec![
+                    RespValue::Integer(0),
+                    RespValue::Integer(16383),
+                    RespValue::Array(vec![bulk(&host), RespValue::Integer(port)]),
+                ])]),
+                (_, Some("foo")) => RespValue::Error(format!("MOVED 12182 {}", b)),
+                (_, Some(key)) => RespValue::Error(format!("ASK {} {}", key_slot(key), b)),
+                _ => RespValue::Error("ERR".into()),
+            }
+        })
+        .await;
+
+        fn a_addr(a: &Arc<Mutex<String>>) -> (String, i64) {
+            let addr = a.lock().unwrap().clone();
+            let (host, port) = addr.rsplit_once(':').unwrap();
+            (host.to_string(), port.parse().unwrap())
+        }
+
+        let seed = node_a.lock().unwrap().clone();
+        let redis = Redis::new(&format!("redis+cluster://{}", seed)).unwrap();
+        assert_eq!(redis.get("foo").await.unwrap().as_deref(), Some("foo-on-b"));
+        assert_eq!(redis.get("bar").await.unwrap().as_deref(), Some("bar-on-b"));
+        let log = log_b.lock().unwrap().clone();
+        assert_eq!(log, vec!["GET foo", "ASKING", "GET bar"]);
+    }
+}
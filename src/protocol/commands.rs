@@ -0,0 +1,222 @@
+// 常用命令的类型化封装
+
+use std::collections::HashMap;
+
+use crate::{
+    protocol::{Redis, resp::RespValue, types::FromResp},
+    server::SyncError,
+};
+
+// SET 命令的可选参数
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SetOptions {
+    expire: Option<Expire>,
+    condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expire {
+    Seconds(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    // 仅当键不存在时写入
+    Nx,
+    // 仅当键已存在时写入
+    Xx,
+}
+
+impl SetOptions {
+    pub fn ex(mut self, seconds: u64) -> Self {
+        self.expire = Some(Expire::Seconds(seconds));
+        self
+    }
+
+    pub fn nx(mut self) -> Self {
+        self.condition = Some(Condition::Nx);
+        self
+    }
+
+    pub fn xx(mut self) -> Self {
+        self.condition = Some(Condition::Xx);
+        self
+    }
+
+    fn append_to(&self, cmd: &mut Vec<String>) {
+        match self.expire {
+            Some(Expire::Seconds(s)) => cmd.extend(["EX".to_string(), s.to_string()]),
+            None => {}
+        }
+        match self.condition {
+            Some(Condition::Nx) => cmd.push("NX".into()),
+            Some(Condition::Xx) => cmd.push("XX".into()),
+            None => {}
+        }
+    }
+}
+
+fn args<const N: usize>(items: [&str; N]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
+impl Redis {
+    // 执行命令并把回复转换为指定类型
+    pub async fn query<T: FromResp>(&self, cmd: Vec<String>) -> Result<T, Box<SyncError>> {
+        let reply = self.redis_cmd(cmd).await?.unwrap_or(RespValue::Null);
+        Ok(T::from_resp(reply)?)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<String>, Box<SyncError>> {
+        self.query(args(["GET", key])).await
+    }
+
+    // 返回是否写入成功，带 NX/XX 条件未满足时为 false
+    pub async fn set(
+        &self,
+        key: &str,
+        value: &str,
+        options: SetOptions,
+    ) -> Result<bool, Box<SyncError>> {
+        let mut cmd = args(["SET", key, value]);
+        options.append_to(&mut cmd);
+        self.query(cmd).await
+    }
+
+    pub async fn exists(&self, key: &str) -> Result<bool, Box<SyncError>> {
+        self.query(args(["EXISTS", key])).await
+    }
+
+    pub async fn del(&self, keys: &[&str]) -> Result<i64, Box<SyncError>> {
+        let mut cmd = args(["DEL"]);
+        cmd.extend(keys.iter().map(|k| k.to_string()));
+        self.query(cmd).await
+    }
+
+    pub async fn expire(&self, key: &str, seconds: u64) -> Result<bool, Box<SyncError>> {
+        self.query(args(["EXPIRE", key, &seconds.to_string()]))
+            .await
+    }
+
+    // 剩余秒数；-1 表示没有过期时间，-2 表示键不存在
+    pub async fn ttl(&self, key: &str) -> Result<i64, Box<SyncError>> {
+        self.query(args(["TTL", key])).await
+    }
+
+    // 计数器是对外提供的接口，服务端本身尚未使用
+    #[allow(dead_code)]
+    pub async fn incr(&self, key: &str) -> Result<i64, Box<SyncError>> {
+        self.query(args(["INCR", key])).await
+    }
+
+    pub async fn hget(&self, key: &str, field: &str) -> Result<Option<String>, Box<SyncError>> {
+        self.query(args(["HGET", key, field])).await
+    }
+
+    // 返回新增字段的数量
+    pub async fn hset(&self, key: &str, fields: &[(&str, &str)]) -> Result<i64, Box<SyncError>> {
+        let mut cmd = args(["HSET", key]);
+        for (field, value) in fields {
+            cmd.extend([field.to_string(), value.to_string()]);
+        }
+        self.query(cmd).await
+    }
+
+    // 仅当字段不存在时写入
+    pub async fn hsetnx(
+        &self,
+        key: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<bool, Box<SyncError>> {
+        self.query(args(["HSETNX", key, field, value])).await
+    }
+
+    #[allow(dead_code)]
+    pub async fn hgetall(&self, key: &str) -> Result<HashMap<String, String>, Box<SyncError>> {
+        self.query(args(["HGETALL", key])).await
+    }
+
+    pub async fn sadd(&self, key: &str, members: &[&str]) -> Result<i64, Box<SyncError>> {
+        let mut cmd = args(["SADD", key]);
+        cmd.extend(members.iter().map(|m| m.to_string()));
+        self.query(cmd).await
+    }
+
+    pub async fn srem(&self, key: &str, members: &[&str]) -> Result<i64, Box<SyncError>> {
+        let mut cmd = args(["SREM", key]);
+        cmd.extend(members.iter().map(|m| m.to_string()));
+        self.query(cmd).await
+    }
+
+    pub async fn smembers(&self, key: &str) -> Result<Vec<String>, Box<SyncError>> {
+        self.query(args(["SMEMBERS", key])).await
+    }
+
+    // 单次 SCAN，返回下一个游标（0 表示遍历结束）与本批键；集群模式下只遍历其中一个节点
+    #[allow(dead_code)]
+    pub async fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> Result<(u64, Vec<String>), Box<SyncError>> {
+        let mut cmd = args(["SCAN", &cursor.to_string()]);
+        if let Some(pattern) = pattern {
+            cmd.extend(["MATCH".to_string(), pattern.to_string()]);
+        }
+        if let Some(count) = count {
+            cmd.extend(["COUNT".to_string(), count.to_string()]);
+        }
+        self.query(cmd).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::mock::MockRedis;
+
+    #[test]
+    fn set_options() {
+        let mut cmd = args(["SET", "k", "v"]);
+        SetOptions::default().ex(60).nx().append_to(&mut cmd);
+        assert_eq!(cmd, args(["SET", "k", "v", "EX", "60", "NX"]));
+
+        let mut cmd = Vec::new();
+        SetOptions::default().ex(5).xx().append_to(&mut cmd);
+        assert_eq!(cmd, args(["EX", "5", "XX"]));
+    }
+
+    #[tokio::test]
+    async fn counters_hashes_and_scan() {
+        let mock = MockRedis::start().await;
+        let redis = Redis::new(&mock.url()).unwrap();
+        assert_eq!(redis.incr("n").await.unwrap(), 1);
+        assert_eq!(redis.incr("n").await.unwrap(), 2);
+        redis.hset("h", &[("a", "1"), ("b", "2")]).await.unwrap();
+        assert_eq!(
+            redis.hgetall("h").await.unwrap(),
+            HashMap::from([("a".into(), "1".into()), ("b".into(), "2".into())])
+        );
+        assert!(redis.hgetall("missing").await.unwrap().is_empty());
+        assert!(redis.incr("h").await.is_err());
+
+        for key in ["user-1", "user-2", "user-3", "other"] {
+            redis.set(key, "v", SetOptions::default()).await.unwrap();
+        }
+        let mut cursor = 0;
+        let mut keys = Vec::new();
+        loop {
+            let (next, batch) = redis.scan(cursor, Some("user-*"), Some(2)).await.unwrap();
+            assert!(batch.len() <= 2);
+            keys.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        keys.sort();
+        assert_eq!(keys, ["user-1", "user-2", "user-3"]);
+    }
+}
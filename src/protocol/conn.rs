@@ -0,0 +1,220 @@
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_util::codec::Framed;
+
+use crate::{
+    protocol::{
+        config::{RedisAddr, RedisConfig},
+        resp::{RespCodec, RespValue},
+    },
+    server::SyncError,
+};
+
+pub trait RespStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> RespStream for T {}
+
+// 一条已完成握手（认证、选库、命名）的 Redis 连接
+pub struct Connection {
+    framed: Framed<Box<dyn RespStream>, RespCodec>,
+}
+
+impl Connection {
+    pub async fn connect(addr: &RedisAddr, config: &RedisConfig) -> Result<Self, Box<SyncError>> {
+        let stream: Box<dyn RespStream> = match addr {
+            RedisAddr::Tcp { host, port } => {
+                Box::new(TcpStream::connect((host.as_str(), *port)).await?)
+            }
+            #[cfg(unix)]
+            RedisAddr::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+            #[cfg(not(unix))]
+            RedisAddr::Unix(_) => return Err("Err: Unix sockets are not supported".into()),
+        };
+        let mut conn = Connection {
+            framed: Framed::new(stream, RespCodec),
+        };
+        conn.handshake(config).await?;
+        Ok(conn)
+    }
+
+    async fn handshake(&mut self, config: &RedisConfig) -> Result<(), Box<SyncError>> {
+        if config.password.is_some() || config.client_name.is_some() {
+            // 优先使用 HELLO 一次完成认证与命名，保持 RESP2 协议
+            let mut hello = vec!["HELLO".to_string(), "2".to_string()];
+            if let Some(pass) = &config.password {
+                let user = config.username.as_deref().unwrap_or("default");
+                hello.extend(["AUTH".to_string(), user.to_string(), pass.clone()]);
+            }
+            if let Some(name) = &config.client_name {
+                hello.extend(["SETNAME".to_string(), name.clone()]);
+            }
+            match self.request(command(hello)).await? {
+                // Redis 6 以前不支持 HELLO，退回 AUTH 与 CLIENT SETNAME
+                RespValue::Error(e) if e.starts_with("ERR unknown command") => {
+                    if let Some(pass) = &config.password {
+                        let mut auth = vec!["AUTH".to_string()];
+                        auth.extend(config.username.clone());
+                        auth.push(pass.clone());
+                        self.request_ok(auth).await?;
+                    }
+                    if let Some(name) = &config.client_name {
+                        self.request_ok(vec!["CLIENT".into(), "SETNAME".into(), name.clone()])
+                            .await?;
+                    }
+                }
+                RespValue::Error(e) => return Err(format!("Err: Redis HELLO: {}", e).into()),
+                _ => {}
+            }
+        }
+        if config.db != 0 {
+            self.request_ok(vec!["SELECT".into(), config.db.to_string()])
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn request_ok(&mut self, cmd: Vec<String>) -> Result<(), Box<SyncError>> {
+        let name = cmd[0].clone();
+        match self.request(command(cmd)).await? {
+            RespValue::Error(e) => Err(format!("Err: Redis {}: {}", name, e).into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn send(&mut self, cmd: RespValue) -> Result<(), Box<SyncError>> {
+        self.framed.send(cmd).await?;
+        Ok(())
+    }
+
+    // 持续读取直到收到完整的回复帧
+    pub async fn read(&mut self) -> Result<RespValue, Box<SyncError>> {
+        match self.framed.next().await {
+            Some(reply) => Ok(reply?),
+            None => Err("Err: Redis connection closed".into()),
+        }
+    }
+
+    pub async fn request(&mut self, cmd: RespValue) -> Result<RespValue, Box<SyncError>> {
+        self.send(cmd).await?;
+        self.read().await
+    }
+}
+
+pub fn command<I, S>(args: I) -> RespValue
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    RespValue::Array(
+        args.into_iter()
+            .map(|s| RespValue::BulkString(Some(s.into())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::protocol::types::FromResp;
+    use tokio::net::TcpListener;
+
+    // 记录收到的命令并按顺序返回预设回复
+    pub(crate) async fn fake_redis(
+        replies: Vec<RespValue>,
+    ) -> (String, tokio::task::JoinHandle<Vec<RespValue>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, RespCodec);
+            let mut received = Vec::new();
+            for reply in replies {
+                received.push(framed.next().await.unwrap().unwrap());
+                framed.send(reply).await.unwrap();
+            }
+            received
+        });
+        (addr, handle)
+    }
+
+    // 可接受多条连接，每条命令的回复由 handler 决定
+    pub(crate) async fn fake_server<F>(handler: F) -> String
+    where
+        F: Fn(Vec<String>) -> RespValue + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handler = std::sync::Arc::new(handler);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let mut framed = Framed::new(stream, RespCodec);
+                    while let Some(Ok(frame)) = framed.next().await {
+                        let args = Vec::<String>::from_resp(frame).unwrap();
+                        if framed.send(handler(args)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn handshake_with_hello() {
+        let ok = RespValue::SimpleString("OK".into());
+        let (addr, server) = fake_redis(vec![RespValue::Array(vec![]), ok.clone()]).await;
+        let config =
+            RedisConfig::parse(&format!("redis://app:pw@{}/5?client_name=web", addr)).unwrap();
+        Connection::connect(&config.nodes[0], &config)
+            .await
+            .unwrap();
+        assert_eq!(
+            server.await.unwrap(),
+            vec![
+                command(["HELLO", "2", "AUTH", "app", "pw", "SETNAME", "web"]),
+                command(["SELECT", "5"]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_falls_back_without_hello() {
+        let ok = RespValue::SimpleString("OK".into());
+        let (addr, server) = fake_redis(vec![
+            RespValue::Error("ERR unknown command 'HELLO'".into()),
+            ok.clone(),
+            ok.clone(),
+        ])
+        .await;
+        let config = RedisConfig::parse(&format!("redis://:pw@{}?client_name=web", addr)).unwrap();
+        Connection::connect(&config.nodes[0], &config)
+            .await
+            .unwrap();
+        assert_eq!(
+            server.await.unwrap(),
+            vec![
+                command(["HELLO", "2", "AUTH", "default", "pw", "SETNAME", "web"]),
+                command(["AUTH", "pw"]),
+                command(["CLIENT", "SETNAME", "web"]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_bad_password() {
+        let (addr, _server) =
+            fake_redis(vec![RespValue::Error("WRONGPASS invalid password".into())]).await;
+        let config = RedisConfig::parse(&format!("redis://:bad@{}", addr)).unwrap();
+        assert!(
+            Connection::connect(&config.nodes[0], &config)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt;
+
+use crate::server::SyncError;
+
+// Redis 不可用时返回的错误，处理函数据此回复 503
+#[derive(Debug)]
+pub enum RedisError {
+    // 连接或命令超时
+    Timeout(&'static str),
+    // 熔断器打开，直接快速失败
+    CircuitOpen,
+    // 连接失败、握手失败或连接中途断开
+    Connection(Box<SyncError>),
+    // 连接到的节点已成为只读从节点（主从切换中）
+    ReadOnly(String),
+}
+
+impl RedisError {
+    // 判断任意错误是否由存储不可用导致
+    pub fn is_unavailable(err: &SyncError) -> bool {
+        err.downcast_ref::<RedisError>().is_some()
+    }
+}
+
+impl fmt::Display for RedisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedisError::Timeout(what) => write!(f, "Err: Redis {} timed out", what),
+            RedisError::CircuitOpen => write!(f, "Err: Redis unavailable (circuit open)"),
+            RedisError::Connection(e) => write!(f, "Err: Redis unavailable: {}", e),
+            RedisError::ReadOnly(e) => write!(f, "Err: Redis failover in progress: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RedisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RedisError::Connection(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
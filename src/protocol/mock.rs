@@ -0,0 +1,643 @@
+// 测试用的内存 RESP 服务器：实现业务用到的命令子集，支持过期时间
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use sha1::{Digest, Sha1};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    protocol::{
+        resp::{RespParser, RespValue},
+        types::FromResp,
+    },
+    store::redis::{REPLACE_HASH, REPLACE_VALUE},
+};
+
+// 用 Rust 函数模拟的 Lua 脚本
+type ScriptFn = fn(&mut Store, &[String], &[String]) -> RespValue;
+
+enum Value {
+    String(String),
+    Hash(HashMap<String, String>),
+    Set(HashSet<String>),
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+struct Store {
+    entries: HashMap<String, Entry>,
+    // 已通过 SCRIPT LOAD 或 EVAL 缓存的脚本
+    loaded: HashMap<String, ScriptFn>,
+    // 人为推进的时间，用于测试过期
+    offset: Duration,
+}
+
+pub struct MockRedis {
+    addr: SocketAddr,
+    store: Arc<Mutex<Store>>,
+}
+
+impl MockRedis {
+    pub async fn start() -> MockRedis {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let store = Arc::new(Mutex::new(Store {
+            entries: HashMap::new(),
+            loaded: HashMap::new(),
+            offset: Duration::ZERO,
+        }));
+        let shared = store.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, shared.clone()));
+            }
+        });
+        MockRedis { addr, store }
+    }
+
+    pub fn url(&self) -> String {
+        format!("redis://{}", self.addr)
+    }
+
+    // 绕过网络直接执行命令，便于准备测试数据
+    pub fn execute(&self, args: &[&str]) -> RespValue {
+        let args = args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        self.store.lock().unwrap().execute(&args)
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.store.lock().unwrap().offset += by;
+    }
+}
+
+async fn serve(mut stream: TcpStream, store: Arc<Mutex<Store>>) {
+    let mut buf = BytesMut::with_capacity(4096);
+    loop {
+        loop {
+            match RespParser::parse(&mut buf) {
+                Ok(Some(frame)) => {
+                    let reply = match Vec::<String>::from_resp(frame) {
+                        Ok(args) if !args.is_empty() => store.lock().unwrap().execute(&args),
+                        _ => RespValue::Error("ERR Protocol error".into()),
+                    };
+                    if stream
+                        .write_all(&RespParser::serializer(reply))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => return,
+            }
+        }
+        match stream.read_buf(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+}
+
+fn ok() -> RespValue {
+    RespValue::SimpleString("OK".into())
+}
+
+fn bulk(s: &str) -> RespValue {
+    RespValue::BulkString(Some(s.into()))
+}
+
+fn wrong_args(name: &str) -> RespValue {
+    RespValue::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        name.to_ascii_lowercase()
+    ))
+}
+
+fn wrong_type() -> RespValue {
+    RespValue::Error("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+}
+
+// 只支持 `*` 与 `?` 的通配符匹配
+fn glob(pattern: &str, s: &str) -> bool {
+    match pattern.chars().next() {
+        None => s.is_empty(),
+        Some('*') => (0..=s.len())
+            .filter(|i| s.is_char_boundary(*i))
+            .any(|i| glob(&pattern[1..], &s[i..])),
+        Some(c) => {
+            let Some(first) = s.chars().next() else {
+                return false;
+            };
+            (c == '?' || c == first) && glob(&pattern[c.len_utf8()..], &s[first.len_utf8()..])
+        }
+    }
+}
+
+fn sha(source: &str) -> String {
+    Sha1::digest(source.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// 应用中使用的脚本及其模拟实现
+fn known_script(sha: &str) -> Option<ScriptFn> {
+    if sha == REPLACE_HASH.sha() {
+        return Some(|store, keys, args| {
+            let current = match store.execute(&["HGET".into(), keys[0].clone(), args[0].clone()]) {
+                RespValue::BulkString(Some(v)) => v,
+                _ => return RespValue::Integer(0),
+            };
+            if current != args[1] {
+                return RespValue::Integer(0);
+            }
+            store.execute(&[
+                "HSET".into(),
+                keys[0].clone(),
+                args[0].clone(),
+                args[2].clone(),
+            ]);
+            RespValue::Integer(1)
+        });
+    }
+    if sha == REPLACE_VALUE.sha() {
+        return Some(|store, keys, args| {
+            match store.execute(&["GET".into(), keys[0].clone()]) {
+                RespValue::BulkString(Some(v)) if v == args[0] => {}
+                _ => return RespValue::Integer(0),
+            }
+            store.execute(&[
+                "SET".into(),
+                keys[0].clone(),
+                args[1].clone(),
+                "EX".into(),
+                args[2].clone(),
+            ]);
+            RespValue::Integer(1)
+        });
+    }
+    None
+}
+
+impl Store {
+    fn now(&self) -> Instant {
+        Instant::now() + self.offset
+    }
+
+    // 惰性删除已过期的键
+    fn live(&mut self, key: &str) -> Option<&mut Entry> {
+        let now = self.now();
+        if self
+            .entries
+            .get(key)
+            .and_then(|e| e.expires_at)
+            .is_some_and(|at| at <= now)
+        {
+            self.entries.remove(key);
+        }
+        self.entries.get_mut(key)
+    }
+
+    fn insert(&mut self, key: &str, value: Value, ttl_seconds: Option<u64>) {
+        let expires_at = ttl_seconds.map(|s| self.now() + Duration::from_secs(s));
+        self.entries
+            .insert(key.to_string(), Entry { value, expires_at });
+    }
+
+    fn execute(&mut self, args: &[String]) -> RespValue {
+        let name = args[0].to_ascii_uppercase();
+        let argc = args.len();
+        match (name.as_str(), argc) {
+            ("PING", 1) => RespValue::SimpleString("PONG".into()),
+            ("SELECT" | "AUTH", _) => ok(),
+            ("CLIENT", _) => ok(),
+            ("GET", 2) => match self.live(&args[1]).map(|e| &e.value) {
+                Some(Value::String(s)) => bulk(s),
+                Some(_) => wrong_type(),
+                None => RespValue::Null,
+            },
+            ("SET", 3..) => self.set(args),
+            ("INCR", 2) => {
+                let current = match self.live(&args[1]).map(|e| &e.value) {
+                    Some(Value::String(s)) => match s.parse::<i64>() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            return RespValue::Error(
+                                "ERR value is not an integer or out of range".into(),
+                            );
+                        }
+                    },
+                    Some(_) => return wrong_type(),
+                    None => 0,
+                };
+                let expires_at = self.live(&args[1]).and_then(|e| e.expires_at);
+                let value = (current + 1).to_string();
+                self.entries.insert(
+                    args[1].clone(),
+                    Entry {
+                        value: Value::String(value),
+                        expires_at,
+                    },
+                );
+                RespValue::Integer(current + 1)
+            }
+            ("EXISTS", 2..) => {
+                let n = args[1..].iter().filter(|k| self.live(k).is_some()).count();
+                RespValue::Integer(n as i64)
+            }
+            ("DEL", 2..) => {
+                let n = args[1..]
+                    .iter()
+                    .filter(|k| self.live(k).is_some() && self.entries.remove(*k).is_some())
+                    .count();
+                RespValue::Integer(n as i64)
+            }
+            ("EXPIRE", 3) => {
+                let Ok(seconds) = args[2].parse::<u64>() else {
+                    return RespValue::Error("ERR value is not an integer or out of range".into());
+                };
+                let at = self.now() + Duration::from_secs(seconds);
+                match self.live(&args[1]) {
+                    Some(entry) => {
+                        entry.expires_at = Some(at);
+                        RespValue::Integer(1)
+                    }
+                    None => RespValue::Integer(0),
+                }
+            }
+            ("TTL", 2) => {
+                let now = self.now();
+                match self.live(&args[1]) {
+                    Some(Entry {
+                        expires_at: Some(at),
+                        ..
+                    }) => RespValue::Integer((*at - now).as_secs_f64().ceil() as i64),
+                    Some(_) => RespValue::Integer(-1),
+                    None => RespValue::Integer(-2),
+                }
+            }
+            ("HGET", 3) => match self.live(&args[1]).map(|e| &e.value) {
+                Some(Value::Hash(h)) => h.get(&args[2]).map_or(RespValue::Null, |v| bulk(v)),
+                Some(_) => wrong_type(),
+                None => RespValue::Null,
+            },
+            ("HGETALL", 2) => match self.live(&args[1]).map(|e| &e.value) {
+                Some(Value::Hash(h)) => {
+                    RespValue::Array(h.iter().flat_map(|(k, v)| [bulk(k), bulk(v)]).collect())
+                }
+                Some(_) => wrong_type(),
+                None => RespValue::Array(Vec::new()),
+            },
+            ("HSET", 4..) if argc.is_multiple_of(2) => {
+                if self.live(&args[1]).is_none() {
+                    self.insert(&args[1], Value::Hash(HashMap::new()), None);
+                }
+                let Some(Entry {
+                    value: Value::Hash(hash),
+                    ..
+                }) = self.entries.get_mut(&args[1])
+                else {
+                    return wrong_type();
+                };
+                let added = args[2..]
+                    .chunks(2)
+                    .filter(|kv| hash.insert(kv[0].clone(), kv[1].clone()).is_none())
+                    .count();
+                RespValue::Integer(added as i64)
+            }
+            ("HSETNX", 4) => {
+                if self.live(&args[1]).is_none() {
+                    self.insert(&args[1], Value::Hash(HashMap::new()), None);
+                }
+                let Some(Entry {
+                    value: Value::Hash(hash),
+                    ..
+                }) = self.entries.get_mut(&args[1])
+                else {
+                    return wrong_type();
+                };
+                if hash.contains_key(&args[2]) {
+                    return RespValue::Integer(0);
+                }
+                hash.insert(args[2].clone(), args[3].clone());
+                RespValue::Integer(1)
+            }
+            ("SADD", 3..) => {
+                if self.live(&args[1]).is_none() {
+                    self.insert(&args[1], Value::Set(HashSet::new()), None);
+                }
+                let Some(Entry {
+                    value: Value::Set(set),
+                    ..
+                }) = self.entries.get_mut(&args[1])
+                else {
+                    return wrong_type();
+                };
+                let added = args[2..]
+                    .iter()
+                    .filter(|m| set.insert(m.to_string()))
+                    .count();
+                RespValue::Integer(added as i64)
+            }
+            ("SREM", 3..) => {
+                let Some(entry) = self.live(&args[1]) else {
+                    return RespValue::Integer(0);
+                };
+                let Value::Set(set) = &mut entry.value else {
+                    return wrong_type();
+                };
+                let removed = args[2..].iter().filter(|m| set.remove(*m)).count();
+                // 与 Redis 一致，空集合即被删除
+                if set.is_empty() {
+                    self.entries.remove(&args[1]);
+                }
+                RespValue::Integer(removed as i64)
+            }
+            ("SMEMBERS", 2) => match self.live(&args[1]).map(|e| &e.value) {
+                Some(Value::Set(set)) => RespValue::Array(set.iter().map(|m| bulk(m)).collect()),
+                Some(_) => wrong_type(),
+                None => RespValue::Array(Vec::new()),
+            },
+            ("SCAN", 2..) if argc.is_multiple_of(2) => self.scan(args),
+            ("SCRIPT", 3) if args[1].eq_ignore_ascii_case("LOAD") => {
+                let sha = sha(&args[2]);
+                match known_script(&sha) {
+                    Some(script) => {
+                        self.loaded.insert(sha.clone(), script);
+                        bulk(&sha)
+                    }
+                    None => RespValue::Error("ERR unsupported script".into()),
+                }
+            }
+            ("SCRIPT", 2) if args[1].eq_ignore_ascii_case("FLUSH") => {
+                self.loaded.clear();
+                ok()
+            }
+            ("EVAL" | "EVALSHA", 3..) => self.eval(&name, args),
+            (
+                "PING" | "GET" | "SET" | "INCR" | "EXISTS" | "DEL" | "EXPIRE" | "TTL" | "HGET"
+                | "HGETALL" | "HSET" | "HSETNX" | "SADD" | "SREM" | "SMEMBERS" | "SCAN" | "SCRIPT"
+                | "EVAL" | "EVALSHA",
+                _,
+            ) => wrong_args(&name),
+            _ => RespValue::Error(format!("ERR unknown command '{}'", args[0])),
+        }
+    }
+
+    // SCAN cursor [MATCH pattern] [COUNT count]；游标为按键名排序后的位置
+    fn scan(&mut self, args: &[String]) -> RespValue {
+        let Ok(cursor) = args[1].parse::<usize>() else {
+            return RespValue::Error("ERR invalid cursor".into());
+        };
+        let mut pattern = "*";
+        let mut count = 10;
+        for opt in args[2..].chunks(2) {
+            match opt[0].to_ascii_uppercase().as_str() {
+                "MATCH" => pattern = &opt[1],
+                "COUNT" => match opt[1].parse::<usize>() {
+                    Ok(n) if n > 0 => count = n,
+                    _ => return RespValue::Error("ERR syntax error".into()),
+                },
+                _ => return RespValue::Error("ERR syntax error".into()),
+            }
+        }
+        let mut keys = self.entries.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        keys.retain(|k| self.live(k).is_some());
+        let end = (cursor + count).min(keys.len());
+        let batch = keys
+            .get(cursor..end)
+            .unwrap_or_default()
+            .iter()
+            .filter(|k| glob(pattern, k))
+            .map(|k| bulk(k))
+            .collect();
+        let next = if end >= keys.len() { 0 } else { end };
+        RespValue::Array(vec![bulk(&next.to_string()), RespValue::Array(batch)])
+    }
+
+    // EVAL script numkeys key... arg... / EVALSHA sha numkeys key... arg...
+    fn eval(&mut self, name: &str, args: &[String]) -> RespValue {
+        let script = if name == "EVAL" {
+            let sha = sha(&args[1]);
+            let Some(script) = known_script(&sha) else {
+                return RespValue::Error("ERR unsupported script".into());
+            };
+            self.loaded.insert(sha, script);
+            script
+        } else {
+            match self.loaded.get(&args[1]) {
+                Some(script) => *script,
+                None => {
+                    return RespValue::Error(
+                        "NOSCRIPT No matching script. Please use EVAL.".into(),
+                    );
+                }
+            }
+        };
+        let numkeys = match args[2].parse::<usize>() {
+            Ok(n) if n <= args.len() - 3 => n,
+            _ => {
+                return RespValue::Error(
+                    "ERR Number of keys can't be greater than number of args".into(),
+                );
+            }
+        };
+        let (keys, argv) = args[3..].split_at(numkeys);
+        script(self, keys, argv)
+    }
+
+    // SET key value [EX seconds | KEEPTTL] [NX|XX]
+    fn set(&mut self, args: &[String]) -> RespValue {
+        let mut ttl = None;
+        let mut keep_ttl = false;
+        let mut nx = false;
+        let mut xx = false;
+        let mut opts = args[3..].iter();
+        while let Some(opt) = opts.next() {
+            match opt.to_ascii_uppercase().as_str() {
+                "EX" => match opts.next().and_then(|s| s.parse::<u64>().ok()) {
+                    Some(s) if s > 0 => ttl = Some(s),
+                    _ => {
+                        return RespValue::Error("ERR invalid expire time in 'set' command".into());
+                    }
+                },
+                "KEEPTTL" => keep_ttl = true,
+                "NX" => nx = true,
+                "XX" => xx = true,
+                _ => return RespValue::Error("ERR syntax error".into()),
+            }
+        }
+        let expires_at = self.live(&args[1]).map(|e| e.expires_at);
+        if (nx && expires_at.is_some()) || (xx && expires_at.is_none()) {
+            return RespValue::Null;
+        }
+        self.insert(&args[1], Value::String(args[2].clone()), ttl);
+        if keep_ttl && let Some(entry) = self.entries.get_mut(&args[1]) {
+            entry.expires_at = expires_at.flatten();
+        }
+        ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        protocol::{Redis, commands::SetOptions},
+        store::{Session, SessionStore, UserStore},
+    };
+
+    #[tokio::test]
+    async fn commands_and_expiry() {
+        let mock = MockRedis::start().await;
+        let redis = Redis::new(&mock.url()).unwrap();
+
+        assert!(
+            redis
+                .set("k", "v", SetOptions::default().ex(10))
+                .await
+                .unwrap()
+        );
+        assert!(
+            !redis
+                .set("k", "w", SetOptions::default().nx())
+                .await
+                .unwrap()
+        );
+        assert_eq!(redis.get("k").await.unwrap().as_deref(), Some("v"));
+        assert!(
+            redis
+                .set("k", "v2", SetOptions::default().ex(10).xx())
+                .await
+                .unwrap()
+        );
+        assert_eq!(redis.get("k").await.unwrap().as_deref(), Some("v2"));
+        assert_eq!(redis.ttl("k").await.unwrap(), 10);
+        assert_eq!(redis.hset("h", &[("a", "1"), ("b", "2")]).await.unwrap(), 2);
+        assert_eq!(redis.hget("h", "b").await.unwrap().as_deref(), Some("2"));
+        assert!(redis.get("h").await.is_err());
+        assert_eq!(redis.ttl("h").await.unwrap(), -1);
+        assert!(redis.expire("h", 5).await.unwrap());
+
+        mock.advance(Duration::from_secs(6));
+        assert!(!redis.exists("h").await.unwrap());
+        assert_eq!(redis.ttl("k").await.unwrap(), 4);
+        mock.advance(Duration::from_secs(4));
+        assert_eq!(redis.get("k").await.unwrap(), None);
+        assert_eq!(redis.del(&["k", "h"]).await.unwrap(), 0);
+
+        assert_eq!(redis.sadd("s", &["a", "b", "a"]).await.unwrap(), 2);
+        assert_eq!(redis.srem("s", &["a", "c"]).await.unwrap(), 1);
+        assert_eq!(redis.smembers("s").await.unwrap(), vec!["b".to_string()]);
+        assert_eq!(redis.srem("s", &["b"]).await.unwrap(), 1);
+        assert!(!redis.exists("s").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn create_session_key() {
+        let mock = MockRedis::start().await;
+        let redis = Redis::new(&mock.url()).unwrap();
+        assert!(
+            redis
+                .create_session_key("s", "1.2.3.4".into(), 60)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !redis
+                .create_session_key("s", "5.6.7.8".into(), 60)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            redis.get("Session-s").await.unwrap().as_deref(),
+            Some("1.2.3.4")
+        );
+        assert_eq!(mock.execute(&["TTL", "Session-s"]), RespValue::Integer(60));
+    }
+
+    #[tokio::test]
+    async fn replace_session() {
+        let mock = MockRedis::start().await;
+        let redis = Redis::new(&mock.url()).unwrap();
+        let ttl = Duration::from_secs(60);
+        let current = Session::new("alice", Vec::new(), "1.2.3.4", "test");
+        assert!(redis.create_session("s", &current, ttl).await.unwrap());
+        let replaced = Session {
+            replaced: true,
+            ..current.clone()
+        };
+        let short = Duration::from_secs(5);
+        assert!(
+            redis
+                .replace_session("s", &current, &replaced, short)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !redis
+                .replace_session("s", &current, &replaced, short)
+                .await
+                .unwrap()
+        );
+        assert_eq!(redis.session("s").await.unwrap(), Some(replaced));
+        assert_eq!(mock.execute(&["TTL", "Session-s"]), RespValue::Integer(5));
+    }
+
+    #[tokio::test]
+    async fn scripts() {
+        let mock = MockRedis::start().await;
+        let redis = Redis::new(&mock.url()).unwrap();
+        redis.set_password_hash("alice", "h1").await.unwrap();
+        // 未加载时 EVALSHA 得到 NOSCRIPT，退回 EVAL
+        assert!(
+            redis
+                .replace_password_hash("alice", "h1", "h2")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !redis
+                .replace_password_hash("alice", "h1", "h3")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            redis.password_hash("alice").await.unwrap().as_deref(),
+            Some("h2")
+        );
+
+        assert_eq!(mock.execute(&["SCRIPT", "FLUSH"]), ok());
+        let sha = REPLACE_HASH.sha();
+        let args = ["EVALSHA", sha, "1", "usr-pwd", "alice", "h2", "h3"];
+        assert!(matches!(mock.execute(&args), RespValue::Error(e) if e.starts_with("NOSCRIPT")));
+        REPLACE_HASH.load(&redis).await.unwrap();
+        assert_eq!(mock.execute(&args), RespValue::Integer(1));
+
+        // 脚本缓存被清空后依然可用
+        assert_eq!(mock.execute(&["SCRIPT", "FLUSH"]), ok());
+        assert!(
+            redis
+                .replace_password_hash("alice", "h3", "h4")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            redis.password_hash("alice").await.unwrap().as_deref(),
+            Some("h4")
+        );
+    }
+}
@@ -1,108 +1,368 @@
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
 
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use tokio::time::{sleep, timeout};
 
-use crate::{protocol::resp::RespValue, server::SyncError};
+use crate::{
+    protocol::{
+        breaker::CircuitBreaker,
+        cluster::{Cluster, Redirect, command_slot, parse_redirect},
+        commands::SetOptions,
+        config::{RedisAddr, RedisConfig},
+        conn::{Connection, command},
+        error::RedisError,
+        resp::RespValue,
+        sentinel::Sentinel,
+    },
+    server::SyncError,
+};
 
+pub mod breaker;
+pub mod cluster;
+pub mod commands;
+pub mod config;
+pub mod conn;
+pub mod error;
+#[cfg(test)]
+pub mod mock;
+pub mod pubsub;
 pub mod resp;
+pub mod script;
+pub mod sentinel;
+pub mod types;
+
+// 每个节点最多保留的空闲连接数
+const MAX_IDLE_CONNECTIONS: usize = 16;
+// 集群模式下单条命令最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 5;
+// 重试的指数退避区间
+const BACKOFF_BASE: Duration = Duration::from_millis(50);
+const BACKOFF_MAX: Duration = Duration::from_secs(1);
+// 连续失败多少次后熔断，以及熔断持续时间
+const BREAKER_THRESHOLD: u32 = 5;
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(5);
 
 pub struct Redis {
-    addr: SocketAddr,
+    config: RedisConfig,
+    // 按节点地址分组的空闲连接
+    idle: Mutex<HashMap<RedisAddr, Vec<Connection>>>,
+    breaker: CircuitBreaker,
+    // 哨兵模式下用于发现主节点
+    sentinel: Option<Sentinel>,
+    // 集群模式下的槽位路由表
+    cluster: Option<Cluster>,
 }
 
 impl Redis {
-    pub fn new(db_addr: &str) -> Result<Self, Box<SyncError>> {
-        let mut addr = db_addr.to_socket_addrs()?;
-        match addr.next() {
-            Some(addr) => Ok(Self { addr }),
-            None => Err("Err: Redis Addr Parse Error".into()),
-        }
+    pub fn new(db_url: &str) -> Result<Self, Box<SyncError>> {
+        let config = RedisConfig::parse(db_url)?;
+        Ok(Self {
+            sentinel: Sentinel::new(&config),
+            cluster: Cluster::new(&config),
+            config,
+            idle: Mutex::new(HashMap::new()),
+            breaker: CircuitBreaker::new(BREAKER_THRESHOLD, BREAKER_COOLDOWN),
+        })
     }
 
-    pub fn addr(&self) -> SocketAddr {
-        self.addr
+    pub fn addr(&self) -> &RedisConfig {
+        &self.config
     }
 
-    pub async fn redis_cmd(&self, cmd: Vec<String>) -> Result<Option<RespValue>, Box<SyncError>> {
-        let mut stream = TcpStream::connect(self.addr).await?;
-        let resp_cmd = RespValue::Array(
-            cmd.into_iter()
-                .map(|s| RespValue::BulkString(Some(s)))
-                .collect::<Vec<_>>(),
-        );
-        let resp_raw = resp::RespParser::serializer(resp_cmd);
-        stream.write_all(&resp_raw).await?;
-        stream.flush().await?;
-        let mut buf = [0u8; 128];
-        let n = stream.read(&mut buf).await?;
-        // 假定 Redis 返回数据包大小不超过 128
-        if n <= 128 {
-            let mut bytes = bytes::BytesMut::from(&buf[..n]);
-            return resp::RespParser::parse(&mut bytes);
+    // 熔断冷却时间，用于 503 响应的 Retry-After
+    pub fn retry_after(&self) -> Duration {
+        self.breaker.cooldown()
+    }
+
+    // 当前应连接的节点：单机模式为配置的地址，哨兵模式为发现的主节点，集群模式为任一主节点
+    async fn target(&self) -> Result<RedisAddr, RedisError> {
+        self.route(&[]).await
+    }
+
+    // 集群模式下按命令的键所在槽位选择节点
+    async fn route(&self, cmd: &[String]) -> Result<RedisAddr, RedisError> {
+        if let Some(cluster) = &self.cluster {
+            return cluster
+                .node_for(command_slot(cmd))
+                .await
+                .map_err(RedisError::Connection);
+        }
+        match &self.sentinel {
+            Some(sentinel) => sentinel.master().await.map_err(RedisError::Connection),
+            None => Ok(self.config.nodes[0].clone()),
         }
-        // 最可能的错误是 Redis 返回数据包过大，
-        // 也可能是与 Redis 通信失败
-        Err("Err:Redis_Cmd".into())
     }
 
-    pub async fn test_user_password(&self, usr: &str, pwd: &str) -> Result<bool, Box<SyncError>> {
-        if let Some(RespValue::BulkString(Some(password))) = self
-            .redis_cmd(vec!["HGET".into(), "usr-pwd".into(), usr.into()])
-            .await?
-        {
-            Ok(password.eq(pwd))
-        } else {
-            Ok(false)
+    // 主节点可能已切换，丢弃缓存的主节点地址（或槽位表）与空闲连接
+    fn on_failover(&self) {
+        if let Some(sentinel) = &self.sentinel {
+            sentinel.invalidate();
+            self.idle.lock().unwrap().clear();
+        }
+        if let Some(cluster) = &self.cluster {
+            cluster.mark_stale();
+            self.idle.lock().unwrap().clear();
         }
     }
 
-    pub async fn unique_key(&self, key: &str) -> Result<bool, Box<SyncError>> {
-        if let Some(RespValue::Integer(0)) =
-            self.redis_cmd(vec!["EXISTS".into(), key.into()]).await?
+    async fn connect(&self, addr: &RedisAddr) -> Result<Connection, RedisError> {
+        let result = match timeout(
+            self.config.connect_timeout,
+            Connection::connect(addr, &self.config),
+        )
+        .await
         {
-            Ok(true)
-        } else {
-            Ok(false)
+            Ok(Ok(conn)) => return Ok(conn),
+            Ok(Err(e)) => RedisError::Connection(e),
+            Err(_) => RedisError::Timeout("connect"),
+        };
+        self.on_failover();
+        Err(result)
+    }
+
+    // 复用空闲连接，新建的连接会先完成 AUTH/SELECT 等握手
+    async fn get_conn(&self, addr: &RedisAddr) -> Result<(Connection, bool), RedisError> {
+        let pooled = self
+            .idle
+            .lock()
+            .unwrap()
+            .get_mut(addr)
+            .and_then(|conns| conns.pop());
+        if let Some(conn) = pooled {
+            return Ok((conn, true));
+        }
+        Ok((self.connect(addr).await?, false))
+    }
+
+    fn put_conn(&self, addr: RedisAddr, conn: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.entry(addr).or_default();
+        if conns.len() < MAX_IDLE_CONNECTIONS {
+            conns.push(conn);
         }
     }
 
-    pub async fn set_session_key(
+    async fn request(
         &self,
-        key: &str,
-        bind_ip: String,
-        live_seconds: usize,
-    ) -> Result<bool, Box<SyncError>> {
-        if let Some(RespValue::SimpleString(_)) = self
-            .redis_cmd(vec![
-                "SET".into(),
-                format!("Session-{}", key),
-                bind_ip,
-                "EX".into(),
-                live_seconds.to_string(),
-            ])
-            .await?
+        conn: &mut Connection,
+        cmd: RespValue,
+    ) -> Result<RespValue, RedisError> {
+        match timeout(self.config.command_timeout, conn.request(cmd)).await {
+            Ok(Ok(reply)) => Ok(reply),
+            Ok(Err(e)) => Err(RedisError::Connection(e)),
+            Err(_) => Err(RedisError::Timeout("command")),
+        }
+    }
+
+    // ASK 重定向要求在同一连接上先发送 ASKING
+    async fn request_on(
+        &self,
+        conn: &mut Connection,
+        cmd: &RespValue,
+        asking: bool,
+    ) -> Result<RespValue, RedisError> {
+        if asking {
+            let reply = self.request(conn, command(["ASKING"])).await?;
+            if let RespValue::Error(_) = reply {
+                return Ok(reply);
+            }
+        }
+        self.request(conn, cmd.clone()).await
+    }
+
+    async fn try_cmd(
+        &self,
+        args: &[String],
+        cmd: &RespValue,
+        read_only: bool,
+    ) -> Result<RespValue, RedisError> {
+        let mut addr = self.route(args).await?;
+        let mut asking = false;
+        let mut redirects = 0;
+        loop {
+            let reply = self.try_node(&addr, cmd, asking, read_only).await?;
+            if let Some(cluster) = &self.cluster
+                && let RespValue::Error(e) = &reply
+                && let Some(redirect) = parse_redirect(e)
+                && redirects < MAX_REDIRECTS
+            {
+                redirects += 1;
+                match redirect {
+                    Redirect::Moved(slot, to) => {
+                        cluster.moved(slot, to.clone());
+                        addr = to;
+                        asking = false;
+                    }
+                    Redirect::Ask(_, to) => {
+                        addr = to;
+                        asking = true;
+                    }
+                }
+                continue;
+            }
+            return Ok(reply);
+        }
+    }
+
+    async fn try_node(
+        &self,
+        addr: &RedisAddr,
+        cmd: &RespValue,
+        asking: bool,
+        read_only: bool,
+    ) -> Result<RespValue, RedisError> {
+        let (mut conn, reused) = self.get_conn(addr).await?;
+        let reply = match self.request_on(&mut conn, cmd, asking).await {
+            Ok(reply) => reply,
+            // 空闲连接可能已被 Redis 关闭，只读命令换一条新连接重试一次
+            Err(RedisError::Connection(_)) if reused && read_only => {
+                conn = self.connect(addr).await?;
+                self.request_on(&mut conn, cmd, asking).await?
+            }
+            // 超时的连接上可能还有未读回复，直接丢弃
+            Err(e) => return Err(e),
+        };
+        // 主从切换后旧主节点变为只读从节点，命令未被执行
+        if let RespValue::Error(e) = &reply
+            && e.starts_with("READONLY")
         {
-            Ok(true)
-        } else {
-            Ok(false)
+            self.on_failover();
+            return Err(RedisError::ReadOnly(e.clone()));
+        }
+        self.put_conn(addr.clone(), conn);
+        Ok(reply)
+    }
+
+    pub async fn redis_cmd(&self, cmd: Vec<String>) -> Result<Option<RespValue>, Box<SyncError>> {
+        // 写命令出错时无法确定是否已被执行，只有只读命令才能安全地重试
+        let read_only = is_read_only(&cmd);
+        let attempts = self.config.retries + 1;
+        let resp_cmd = command(cmd.iter().map(String::as_str));
+        let mut backoff = BACKOFF_BASE;
+        let mut attempt = 1;
+        loop {
+            if !self.breaker.allow() {
+                return Err(Box::new(RedisError::CircuitOpen));
+            }
+            match self.try_cmd(&cmd, &resp_cmd, read_only).await {
+                Ok(reply) => {
+                    self.breaker.record_success();
+                    return Ok(Some(reply));
+                }
+                // 被只读节点拒绝的命令没有执行，可以重试
+                Err(e @ RedisError::ReadOnly(_)) => {
+                    if attempt >= attempts {
+                        return Err(Box::new(e));
+                    }
+                }
+                Err(e) => {
+                    self.breaker.record_failure();
+                    if !read_only || attempt >= attempts {
+                        return Err(Box::new(e));
+                    }
+                }
+            }
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+            attempt += 1;
         }
     }
 
-    pub async fn judge_session_key(
+    // 仅当会话键未被占用时写入，SET NX EX 本身即是原子的
+    pub async fn create_session_key(
         &self,
         key: &str,
-        bind_ip: String,
+        value: String,
+        live_seconds: usize,
     ) -> Result<bool, Box<SyncError>> {
-        
-        let session_key = format!("Session-{}", key);
-        if let Some(RespValue::BulkString(Some(trust_ip))) = self.redis_cmd(vec!["GET".into(), session_key]).await?{
-            Ok(trust_ip==bind_ip)
-        }else {
-            Ok(false)
+        self.set(
+            &format!("Session-{}", key),
+            &value,
+            SetOptions::default().ex(live_seconds as u64).nx(),
+        )
+        .await
+    }
+}
+
+fn is_read_only(cmd: &[String]) -> bool {
+    let Some(name) = cmd.first() else {
+        return false;
+    };
+    matches!(
+        name.to_ascii_uppercase().as_str(),
+        "GET"
+            | "MGET"
+            | "EXISTS"
+            | "TTL"
+            | "PTTL"
+            | "TYPE"
+            | "STRLEN"
+            | "HGET"
+            | "HGETALL"
+            | "HEXISTS"
+            | "SMEMBERS"
+            | "SISMEMBER"
+            | "SCARD"
+            | "SCAN"
+            | "PING"
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn cmd(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn read_only_commands() {
+        assert!(is_read_only(&cmd(&["get", "k"])));
+        assert!(is_read_only(&cmd(&["SMEMBERS", "k"])));
+        assert!(!is_read_only(&cmd(&["SET", "k", "v", "EX", "10"])));
+        assert!(!is_read_only(&cmd(&["SADD", "k", "m"])));
+        assert!(!is_read_only(&cmd(&["DEL", "k"])));
+        assert!(!is_read_only(&cmd(&["INCR", "k"])));
+        assert!(!is_read_only(&[]));
+    }
+
+    #[tokio::test]
+    async fn command_timeout() {
+        // 只接受连接但从不回复
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+        let redis = Redis::new(&format!("redis://{}?timeout_ms=50&retries=0", addr)).unwrap();
+        let err = redis.redis_cmd(cmd(&["GET", "k"])).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RedisError>(),
+            Some(RedisError::Timeout("command"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn unreachable_opens_circuit() {
+        // 先占用再释放一个端口，保证连接被拒绝
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let redis = Redis::new(&format!("redis://{}?retries=1", addr)).unwrap();
+        for _ in 0..3 {
+            let err = redis.redis_cmd(cmd(&["GET", "k"])).await.unwrap_err();
+            assert!(RedisError::is_unavailable(err.as_ref()));
         }
-        
+        let err = redis.redis_cmd(cmd(&["GET", "k"])).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<RedisError>(),
+            Some(RedisError::CircuitOpen)
+        ));
     }
 }
@@ -0,0 +1,336 @@
+// 发布订阅：订阅连接独占一条 TCP 连接，不进入连接池
+
+use std::collections::{HashSet, VecDeque};
+
+use futures_util::{Stream, stream};
+use tokio::time::timeout;
+
+use crate::{
+    protocol::{
+        Redis,
+        config::{RedisAddr, RedisConfig},
+        conn::{Connection, command},
+        error::RedisError,
+        resp::RespValue,
+        types::FromResp,
+    },
+    server::SyncError,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub channel: String,
+    // 通过 PSUBSCRIBE 收到的消息会带上匹配的模式
+    pub pattern: Option<String>,
+    pub payload: String,
+}
+
+enum Frame {
+    Message(Message),
+    // subscribe/unsubscribe/psubscribe/punsubscribe 的确认，以及当前订阅总数
+    Confirm {
+        kind: String,
+        name: Option<String>,
+        count: i64,
+    },
+}
+
+impl Frame {
+    fn parse(value: RespValue) -> Result<Frame, Box<SyncError>> {
+        let parts = Vec::<RespValue>::from_resp(value)?;
+        let mut parts = parts.into_iter();
+        let kind = String::from_resp(parts.next().ok_or("Err: empty pub/sub frame")?)?;
+        let rest = parts.collect::<Vec<_>>();
+        match (kind.as_str(), rest.len()) {
+            ("message", 2) => {
+                let [channel, payload] = <[RespValue; 2]>::try_from(rest).unwrap();
+                Ok(Frame::Message(Message {
+                    channel: String::from_resp(channel)?,
+                    pattern: None,
+                    payload: String::from_resp(payload)?,
+                }))
+            }
+            ("pmessage", 3) => {
+                let [pattern, channel, payload] = <[RespValue; 3]>::try_from(rest).unwrap();
+                Ok(Frame::Message(Message {
+                    channel: String::from_resp(channel)?,
+                    pattern: Some(String::from_resp(pattern)?),
+                    payload: String::from_resp(payload)?,
+                }))
+            }
+            ("subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe", 2) => {
+                let [name, count] = <[RespValue; 2]>::try_from(rest).unwrap();
+                Ok(Frame::Confirm {
+                    kind,
+                    name: Option::<String>::from_resp(name)?,
+                    count: i64::from_resp(count)?,
+                })
+            }
+            _ => Err(format!("Err: unexpected pub/sub frame: {}", kind).into()),
+        }
+    }
+}
+
+pub struct Subscriber {
+    config: RedisConfig,
+    conn: Connection,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    // 等待确认期间收到的消息
+    pending: VecDeque<Message>,
+}
+
+// 订阅与发布是对外提供的接口，服务端本身尚未使用
+impl Redis {
+    #[allow(dead_code)]
+    pub async fn subscriber(&self) -> Result<Subscriber, Box<SyncError>> {
+        Subscriber::connect(&self.target().await?, self.addr().clone()).await
+    }
+
+    // 返回收到消息的订阅者数量
+    #[allow(dead_code)]
+    pub async fn publish(&self, channel: &str, message: &str) -> Result<i64, Box<SyncError>> {
+        self.query(vec!["PUBLISH".into(), channel.into(), message.into()])
+            .await
+    }
+}
+
+#[allow(dead_code)]
+impl Subscriber {
+    async fn connect(addr: &RedisAddr, config: RedisConfig) -> Result<Subscriber, Box<SyncError>> {
+        let conn = match timeout(config.connect_timeout, Connection::connect(addr, &config)).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => return Err(Box::new(RedisError::Connection(e))),
+            Err(_) => return Err(Box::new(RedisError::Timeout("connect"))),
+        };
+        Ok(Subscriber {
+            config,
+            conn,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    pub async fn subscribe(&mut self, channels: &[&str]) -> Result<(), Box<SyncError>> {
+        self.request("SUBSCRIBE", channels).await
+    }
+
+    pub async fn psubscribe(&mut self, patterns: &[&str]) -> Result<(), Box<SyncError>> {
+        self.request("PSUBSCRIBE", patterns).await
+    }
+
+    // 参数为空时退订全部频道
+    pub async fn unsubscribe(&mut self, channels: &[&str]) -> Result<(), Box<SyncError>> {
+        self.request("UNSUBSCRIBE", channels).await
+    }
+
+    pub async fn punsubscribe(&mut self, patterns: &[&str]) -> Result<(), Box<SyncError>> {
+        self.request("PUNSUBSCRIBE", patterns).await
+    }
+
+    async fn request(&mut self, cmd: &str, names: &[&str]) -> Result<(), Box<SyncError>> {
+        let is_pattern = cmd.starts_with('P');
+        let is_unsubscribe = cmd.contains("UNSUBSCRIBE");
+        let mut args = vec![cmd.to_string()];
+        args.extend(names.iter().map(|s| s.to_string()));
+        self.conn.send(command(args)).await?;
+
+        let mut waiting: HashSet<String> = names.iter().map(|s| s.to_string()).collect();
+        let wait_all = waiting.is_empty();
+        let command_timeout = self.config.command_timeout;
+        loop {
+            let frame = match timeout(command_timeout, self.conn.read()).await {
+                Ok(frame) => frame?,
+                Err(_) => return Err(Box::new(RedisError::Timeout("command"))),
+            };
+            if let RespValue::Error(e) = frame {
+                return Err(format!("Err: Redis {}: {}", cmd, e).into());
+            }
+            match Frame::parse(frame)? {
+                Frame::Message(msg) => self.pending.push_back(msg),
+                Frame::Confirm { kind, name, count } => {
+                    let set = if kind.starts_with('p') {
+                        &mut self.patterns
+                    } else {
+                        &mut self.channels
+                    };
+                    if let Some(name) = name {
+                        if kind.ends_with("unsubscribe") {
+                            set.remove(&name);
+                        } else {
+                            set.insert(name.clone());
+                        }
+                        waiting.remove(&name);
+                    }
+                    let done = if wait_all && is_unsubscribe {
+                        // 全部退订：剩余订阅数只包含另一类订阅时完成
+                        let other = if is_pattern {
+                            self.channels.len()
+                        } else {
+                            self.patterns.len()
+                        };
+                        count as usize <= other
+                    } else {
+                        waiting.is_empty()
+                    };
+                    if done {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    // 阻塞等待下一条消息
+    pub async fn next_message(&mut self) -> Result<Message, Box<SyncError>> {
+        if let Some(msg) = self.pending.pop_front() {
+            return Ok(msg);
+        }
+        loop {
+            let frame = self.conn.read().await?;
+            if let Frame::Message(msg) = Frame::parse(frame)? {
+                return Ok(msg);
+            }
+        }
+    }
+
+    // 连接断开后重新连接并恢复之前的订阅
+    pub async fn reconnect(&mut self, redis: &Redis) -> Result<(), Box<SyncError>> {
+        let mut fresh = redis.subscriber().await?;
+        let channels = self.channels.iter().cloned().collect::<Vec<_>>();
+        let patterns = self.patterns.iter().cloned().collect::<Vec<_>>();
+        if !channels.is_empty() {
+            fresh
+                .subscribe(&channels.iter().map(String::as_str).collect::<Vec<_>>())
+                .await?;
+        }
+        if !patterns.is_empty() {
+            fresh
+                .psubscribe(&patterns.iter().map(String::as_str).collect::<Vec<_>>())
+                .await?;
+        }
+        *self = fresh;
+        Ok(())
+    }
+
+    // 连接断开等错误只产出一次，随后流结束，需要时由调用方 reconnect 后重新获取
+    pub fn into_stream(self) -> impl Stream<Item = Result<Message, Box<SyncError>>> + Send {
+        stream::unfold(Some(self), |sub| async move {
+            let mut sub = sub?;
+            match sub.next_message().await {
+                Ok(msg) => Some((Ok(msg), Some(sub))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::resp::RespCodec;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    fn bulk(s: &str) -> RespValue {
+        RespValue::BulkString(Some(s.into()))
+    }
+
+    fn push(parts: &[&str], count: Option<i64>) -> RespValue {
+        let mut values: Vec<RespValue> = parts.iter().map(|s| bulk(s)).collect();
+        values.extend(count.map(RespValue::Integer));
+        RespValue::Array(values)
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_receive() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, RespCodec);
+            // SUBSCRIBE a b：确认之间夹带一条消息
+            framed.next().await.unwrap().unwrap();
+            framed
+                .send(push(&["subscribe", "a"], Some(1)))
+                .await
+                .unwrap();
+            framed
+                .send(push(&["message", "a", "early"], None))
+                .await
+                .unwrap();
+            framed
+                .send(push(&["subscribe", "b"], Some(2)))
+                .await
+                .unwrap();
+            // PSUBSCRIBE s*
+            framed.next().await.unwrap().unwrap();
+            framed
+                .send(push(&["psubscribe", "s*"], Some(3)))
+                .await
+                .unwrap();
+            framed
+                .send(push(&["pmessage", "s*", "session", "revoke:1"], None))
+                .await
+                .unwrap();
+            // UNSUBSCRIBE（全部频道）
+            framed.next().await.unwrap().unwrap();
+            framed
+                .send(push(&["unsubscribe", "a"], Some(2)))
+                .await
+                .unwrap();
+            framed
+                .send(push(&["unsubscribe", "b"], Some(1)))
+                .await
+                .unwrap();
+            framed
+                .send(push(&["message", "b", "late"], None))
+                .await
+                .unwrap();
+        });
+
+        let redis = Redis::new(&addr.to_string()).unwrap();
+        let mut sub = redis.subscriber().await.unwrap();
+        sub.subscribe(&["a", "b"]).await.unwrap();
+        sub.psubscribe(&["s*"]).await.unwrap();
+        sub.unsubscribe(&[]).await.unwrap();
+        assert!(sub.channels.is_empty());
+        assert_eq!(sub.patterns.len(), 1);
+
+        let messages = sub.into_stream().take(3).collect::<Vec<_>>().await;
+        let messages = messages.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(messages[0].payload, "early");
+        assert_eq!(
+            messages[1],
+            Message {
+                channel: "session".into(),
+                pattern: Some("s*".into()),
+                payload: "revoke:1".into(),
+            }
+        );
+        assert_eq!(messages[2].channel, "b");
+    }
+
+    #[tokio::test]
+    async fn stream_ends_after_disconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(stream, RespCodec);
+            framed
+                .send(push(&["message", "a", "last"], None))
+                .await
+                .unwrap();
+        });
+
+        let redis = Redis::new(&addr.to_string()).unwrap();
+        let sub = redis.subscriber().await.unwrap();
+        let messages = sub.into_stream().collect::<Vec<_>>().await;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].as_ref().unwrap().payload, "last");
+        assert!(messages[1].is_err());
+    }
+}
@@ -1,6 +1,7 @@
-use bytes::{Buf, BytesMut};
+use std::{fmt, io::Cursor};
 
-use crate::SyncError;
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RespValue {
@@ -12,228 +13,312 @@ pub enum RespValue {
     Null,
 }
 
-pub struct RespParser;
-
-impl RespParser {
-    pub fn parse(buf: &mut BytesMut) -> Result<Option<RespValue>, Box<SyncError>> {
-        if buf.is_empty() {
-            return Ok(None);
-        }
+#[derive(Debug)]
+pub enum RespError {
+    // 数据不完整，需要继续读取，缓冲区未被消费
+    Incomplete,
+    // 数据格式错误，流已无法继续同步
+    Invalid(String),
+    Io(std::io::Error),
+}
 
-        let first_byte = buf[0] as char;
-        match first_byte {
-            '+' => Self::parse_simple_string(buf),
-            '-' => Self::parse_error(buf),
-            ':' => Self::parse_integer(buf),
-            '$' => Self::parse_bulk_string(buf),
-            '*' => Self::parse_array(buf),
-            '_' => Self::parse_null(buf),
-            _ => Err("Invalid RESP type".into()),
+impl fmt::Display for RespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RespError::Incomplete => write!(f, "incomplete RESP frame"),
+            RespError::Invalid(msg) => write!(f, "invalid RESP frame: {}", msg),
+            RespError::Io(e) => write!(f, "RESP io error: {}", e),
         }
     }
+}
 
-    fn parse_null(buf: &mut BytesMut) -> Result<Option<RespValue>, Box<SyncError>> {
-        if buf.as_ref() == b"_\r\n" {
-            Ok(Some(RespValue::Null))
-        } else {
-            Ok(None)
-        }
+impl std::error::Error for RespError {}
+
+impl From<std::io::Error> for RespError {
+    fn from(e: std::io::Error) -> Self {
+        RespError::Io(e)
     }
+}
+
+pub struct RespParser;
+
+// 数组嵌套层数上限，避免构造的深层嵌套耗尽栈空间
+const MAX_DEPTH: usize = 32;
 
-    fn parse_simple_string(buf: &mut BytesMut) -> Result<Option<RespValue>, Box<SyncError>> {
-        // 查找 \r\n
-        if let Some(pos) = buf.iter().position(|&b| b == b'\r') {
-            // 确保下一个字节是 \n
-            if pos + 1 < buf.len() && buf[pos + 1] == b'\n' {
-                // 提取字符串内容（跳过 '+' 和 \r\n）
-                let content = String::from_utf8(buf[1..pos].to_vec())?;
-                // 移除已解析的数据
-                buf.advance(pos + 2);
-                return Ok(Some(RespValue::SimpleString(content)));
+impl RespParser {
+    // 基于游标解析：仅当缓冲区开头是一个完整的帧时才消费数据，
+    // 数据不完整时返回 Ok(None) 且缓冲区保持不变
+    pub fn parse(buf: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
+        let mut cursor = Cursor::new(&buf[..]);
+        match Self::parse_frame(&mut cursor, 0) {
+            Ok(value) => {
+                let len = cursor.position() as usize;
+                buf.advance(len);
+                Ok(Some(value))
             }
+            Err(RespError::Incomplete) => Ok(None),
+            Err(e) => Err(e),
         }
-        Ok(None) // 数据不完整
     }
 
-    fn parse_error(buf: &mut BytesMut) -> Result<Option<RespValue>, Box<SyncError>> {
-        // 查找 \r\n
-        if let Some(pos) = buf.iter().position(|&b| b == b'\r') {
-            // 确保下一个字节是 \n
-            if pos + 1 < buf.len() && buf[pos + 1] == b'\n' {
-                // 提取错误内容（跳过 '-' 和 \r\n）
-                let content = String::from_utf8(buf[1..pos].to_vec())?;
-                // 移除已解析的数据
-                buf.advance(pos + 2);
-                return Ok(Some(RespValue::Error(content)));
+    fn parse_frame(cursor: &mut Cursor<&[u8]>, depth: usize) -> Result<RespValue, RespError> {
+        if !cursor.has_remaining() {
+            return Err(RespError::Incomplete);
+        }
+        match cursor.get_u8() {
+            b'+' => Ok(RespValue::SimpleString(Self::read_string(cursor)?)),
+            b'-' => Ok(RespValue::Error(Self::read_string(cursor)?)),
+            b':' => Ok(RespValue::Integer(Self::read_integer(cursor)?)),
+            b'$' => Self::parse_bulk_string(cursor),
+            b'*' => Self::parse_array(cursor, depth),
+            b'_' => {
+                if !Self::read_line(cursor)?.is_empty() {
+                    return Err(RespError::Invalid("null with payload".into()));
+                }
+                Ok(RespValue::Null)
             }
+            b => Err(RespError::Invalid(format!("unknown type byte 0x{:02x}", b))),
         }
-        Ok(None) // 数据不完整
     }
 
-    fn parse_integer(buf: &mut BytesMut) -> Result<Option<RespValue>, Box<SyncError>> {
-        // 查找 \r\n
-        if let Some(pos) = buf.iter().position(|&b| b == b'\r') {
-            // 确保下一个字节是 \n
-            if pos + 1 < buf.len() && buf[pos + 1] == b'\n' {
-                // 提取整数内容（跳过 ':' 和 \r\n）
-                let content = std::str::from_utf8(&buf[1..pos])?;
-                let number = content.parse::<i64>()?;
-                // 移除已解析的数据
-                buf.advance(pos + 2);
-                return Ok(Some(RespValue::Integer(number)));
+    // 读取到 \r\n 为止的一行（不含 \r\n）
+    fn read_line<'a>(cursor: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], RespError> {
+        let start = cursor.position() as usize;
+        let buf: &'a [u8] = cursor.get_ref();
+        match buf[start..].windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => {
+                cursor.set_position((start + pos + 2) as u64);
+                Ok(&buf[start..start + pos])
             }
+            None => Err(RespError::Incomplete),
         }
-        Ok(None) // 数据不完整
     }
 
-    fn parse_bulk_string(buf: &mut BytesMut) -> Result<Option<RespValue>, Box<SyncError>> {
-        // 查找第一个 \r\n 来获取长度
-        if let Some(pos) = buf.iter().position(|&b| b == b'\r') {
-            // 确保下一个字节是 \n
-            if pos + 1 < buf.len() && buf[pos + 1] == b'\n' {
-                // 提取长度（跳过 '$' 和 \r\n）
-                let length_str = std::str::from_utf8(&buf[1..pos])?;
-
-                if length_str == "-1" {
-                    // Null bulk string
-                    buf.advance(pos + 2);
-                    return Ok(Some(RespValue::Null));
-                }
-
-                let length = length_str.parse::<usize>()?;
-
-                // 检查是否有足够的数据（长度 + \r\n + 数据 + \r\n）
-                let total_needed = pos + 2 + length + 2;
-                if buf.len() < total_needed {
-                    return Ok(None); // 数据不完整
-                }
+    fn read_string(cursor: &mut Cursor<&[u8]>) -> Result<String, RespError> {
+        let line = Self::read_line(cursor)?;
+        String::from_utf8(line.to_vec()).map_err(|e| RespError::Invalid(e.to_string()))
+    }
 
-                // 提取字符串数据
-                let data_start = pos + 2;
-                let data_end = data_start + length;
+    fn read_integer(cursor: &mut Cursor<&[u8]>) -> Result<i64, RespError> {
+        let line = Self::read_line(cursor)?;
+        std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .ok_or_else(|| RespError::Invalid("malformed integer".into()))
+    }
 
-                // 验证后面是否有 \r\n
-                if data_end + 1 < buf.len() && buf[data_end] == b'\r' && buf[data_end + 1] == b'\n'
-                {
-                    let content = String::from_utf8(buf[data_start..data_end].to_vec())?;
-                    buf.advance(total_needed);
-                    return Ok(Some(RespValue::BulkString(Some(content))));
-                }
-            }
+    fn parse_bulk_string(cursor: &mut Cursor<&[u8]>) -> Result<RespValue, RespError> {
+        let length = Self::read_integer(cursor)?;
+        if length == -1 {
+            // Null bulk string，与序列化保持对称
+            return Ok(RespValue::BulkString(None));
+        }
+        if length < 0 {
+            return Err(RespError::Invalid(format!("bulk string length {}", length)));
         }
-        Ok(None) // 数据不完整
+        let length = length as usize;
+        // 检查是否有足够的数据（数据 + \r\n）
+        if cursor.remaining() < length + 2 {
+            return Err(RespError::Incomplete);
+        }
+        let start = cursor.position() as usize;
+        let data = &cursor.get_ref()[start..start + length];
+        if &cursor.get_ref()[start + length..start + length + 2] != b"\r\n" {
+            return Err(RespError::Invalid(
+                "bulk string not terminated by CRLF".into(),
+            ));
+        }
+        let content =
+            String::from_utf8(data.to_vec()).map_err(|e| RespError::Invalid(e.to_string()))?;
+        cursor.set_position((start + length + 2) as u64);
+        Ok(RespValue::BulkString(Some(content)))
     }
 
-    fn parse_array(buf: &mut BytesMut) -> Result<Option<RespValue>, Box<SyncError>> {
-        // 查找第一个 \r\n 来获取数组长度
-        if let Some(pos) = buf.iter().position(|&b| b == b'\r') {
-            // 确保下一个字节是 \n
-            if pos + 1 < buf.len() && buf[pos + 1] == b'\n' {
-                // 提取数组长度（跳过 '*' 和 \r\n）
-                let length_str = std::str::from_utf8(&buf[1..pos])?;
-                let length = length_str.parse::<i64>()?;
-
-                // 移除长度行
-                buf.advance(pos + 2);
-
-                if length == -1 {
-                    // Null array
-                    return Ok(Some(RespValue::Null));
-                }
-
-                let length = length as usize;
-                let mut elements = Vec::with_capacity(length);
-                let mut parsed_count = 0;
-
-                // 临时缓冲区用于回滚
-                // let original_len = buf.len();
-
-                // 解析数组元素
-                while parsed_count < length {
-                    match Self::parse(buf)? {
-                        Some(value) => {
-                            elements.push(value);
-                            parsed_count += 1;
-                        }
-                        None => {
-                            // 数据不完整，回滚并返回 None
-                            return Ok(None);
-                        }
-                    }
-                }
-
-                return Ok(Some(RespValue::Array(elements)));
-            }
+    fn parse_array(cursor: &mut Cursor<&[u8]>, depth: usize) -> Result<RespValue, RespError> {
+        if depth >= MAX_DEPTH {
+            return Err(RespError::Invalid("array nested too deeply".into()));
+        }
+        let length = Self::read_integer(cursor)?;
+        if length == -1 {
+            // Null array
+            return Ok(RespValue::Null);
+        }
+        if length < 0 {
+            return Err(RespError::Invalid(format!("array length {}", length)));
         }
-        Ok(None) // 数据不完整
+        // 元素解析失败时游标不会被提交，缓冲区自然保持原样
+        let length = length as usize;
+        let mut elements = Vec::with_capacity(length.min(1024));
+        for _ in 0..length {
+            elements.push(Self::parse_frame(cursor, depth + 1)?);
+        }
+        Ok(RespValue::Array(elements))
     }
 
     pub fn serializer(response: RespValue) -> Vec<u8> {
+        let mut result = Vec::new();
+        Self::serialize_into(&response, &mut result);
+        result
+    }
+
+    fn serialize_into(response: &RespValue, result: &mut Vec<u8>) {
         match response {
             RespValue::SimpleString(s) => {
-                let mut result = Vec::with_capacity(s.len() + 4);
                 result.push(b'+');
                 result.extend_from_slice(s.as_bytes());
-                result.push(b'\r');
-                result.push(b'\n');
-                result
+                result.extend_from_slice(b"\r\n");
             }
 
             RespValue::Error(s) => {
-                let mut result = Vec::with_capacity(s.len() + 4);
                 result.push(b'-');
                 result.extend_from_slice(s.as_bytes());
-                result.push(b'\r');
-                result.push(b'\n');
-                result
+                result.extend_from_slice(b"\r\n");
             }
 
             RespValue::Integer(n) => {
-                let mut result = Vec::new();
                 result.push(b':');
                 result.extend_from_slice(n.to_string().as_bytes());
-                result.push(b'\r');
-                result.push(b'\n');
-                result
+                result.extend_from_slice(b"\r\n");
             }
 
             RespValue::BulkString(None) => {
                 // Null bulk string
-                b"$-1\r\n".to_vec()
+                result.extend_from_slice(b"$-1\r\n");
             }
 
             RespValue::BulkString(Some(data)) => {
-                let mut result = Vec::new();
                 result.push(b'$');
                 result.extend_from_slice(data.len().to_string().as_bytes());
-                result.push(b'\r');
-                result.push(b'\n');
+                result.extend_from_slice(b"\r\n");
                 result.extend_from_slice(data.as_bytes());
-                result.push(b'\r');
-                result.push(b'\n');
-                result
+                result.extend_from_slice(b"\r\n");
             }
 
-            /*RespValue::Array(None) => {
-                // Null array
-                b"*-1\r\n".to_vec()
-            }*/
             RespValue::Array(values) => {
-                let mut result = Vec::new();
                 result.push(b'*');
                 result.extend_from_slice(values.len().to_string().as_bytes());
-                result.push(b'\r');
-                result.push(b'\n');
-
+                result.extend_from_slice(b"\r\n");
                 for value in values {
-                    let serialized = Self::serializer(value);
-                    result.extend_from_slice(&serialized);
+                    Self::serialize_into(value, result);
                 }
-                result
             }
 
-            RespValue::Null => b"_\r\n".to_vec(),
+            RespValue::Null => result.extend_from_slice(b"_\r\n"),
         }
     }
 }
 
+// 用于 tokio_util::codec::Framed 的编解码器
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RespCodec;
+
+impl Decoder for RespCodec {
+    type Item = RespValue;
+    type Error = RespError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RespValue>, RespError> {
+        RespParser::parse(src)
+    }
+}
+
+impl Encoder<RespValue> for RespCodec {
+    type Error = RespError;
+
+    fn encode(&mut self, item: RespValue, dst: &mut BytesMut) -> Result<(), RespError> {
+        dst.extend_from_slice(&RespParser::serializer(item));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn resp_value() -> impl Strategy<Value = RespValue> {
+        let leaf = prop_oneof![
+            "[a-zA-Z0-9 ]{0,16}".prop_map(RespValue::SimpleString),
+            "[a-zA-Z0-9 ]{0,16}".prop_map(RespValue::Error),
+            any::<i64>().prop_map(RespValue::Integer),
+            ".{0,24}".prop_map(|s| RespValue::BulkString(Some(s))),
+            Just(RespValue::BulkString(None)),
+            Just(RespValue::Null),
+        ];
+        leaf.prop_recursive(3, 32, 6, |inner| {
+            prop::collection::vec(inner, 0..6).prop_map(RespValue::Array)
+        })
+    }
+
+    #[test]
+    fn parse_examples() {
+        let mut buf = BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$-1\r\n:42\r\n"[..]);
+        assert_eq!(
+            RespParser::parse(&mut buf).unwrap(),
+            Some(RespValue::Array(vec![
+                RespValue::BulkString(Some("GET".into())),
+                RespValue::BulkString(None)
+            ]))
+        );
+        assert_eq!(
+            RespParser::parse(&mut buf).unwrap(),
+            Some(RespValue::Integer(42))
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn incomplete_array_is_not_consumed() {
+        let raw = b"*2\r\n$3\r\nGET\r\n$3\r\nke";
+        let mut buf = BytesMut::from(&raw[..]);
+        assert_eq!(RespParser::parse(&mut buf).unwrap(), None);
+        assert_eq!(&buf[..], &raw[..]);
+        buf.extend_from_slice(b"y\r\n");
+        assert!(RespParser::parse(&mut buf).unwrap().is_some());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn invalid_frames_are_errors() {
+        for raw in [
+            &b"?\r\n"[..],
+            b":abc\r\n",
+            b"$-2\r\n",
+            b"$3\r\nabcd\r\n",
+            b"_x\r\n",
+            "*1\r\n".repeat(MAX_DEPTH + 1).as_bytes(),
+        ] {
+            let mut buf = BytesMut::from(raw);
+            assert!(matches!(
+                RespParser::parse(&mut buf),
+                Err(RespError::Invalid(_))
+            ));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn split_at_every_boundary(value in resp_value()) {
+            let raw = RespParser::serializer(value.clone());
+            for split in 0..raw.len() {
+                let mut buf = BytesMut::from(&raw[..split]);
+                prop_assert_eq!(RespCodec.decode(&mut buf).unwrap(), None);
+                prop_assert_eq!(&buf[..], &raw[..split]);
+                buf.extend_from_slice(&raw[split..]);
+                prop_assert_eq!(RespCodec.decode(&mut buf).unwrap(), Some(value.clone()));
+                prop_assert!(buf.is_empty());
+            }
+        }
+
+        #[test]
+        fn encode_then_decode_pipeline(values in prop::collection::vec(resp_value(), 1..4)) {
+            let mut buf = BytesMut::new();
+            for value in &values {
+                RespCodec.encode(value.clone(), &mut buf).unwrap();
+            }
+            for value in values {
+                prop_assert_eq!(RespCodec.decode(&mut buf).unwrap(), Some(value));
+            }
+            prop_assert!(buf.is_empty());
+        }
+    }
+}
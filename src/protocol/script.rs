@@ -0,0 +1,127 @@
+use sha1::{Digest, Sha1};
+
+use crate::{
+    protocol::{Redis, resp::RespValue, types::FromResp},
+    server::SyncError,
+};
+
+// Lua 脚本，按 SHA1 调用；集群中各节点的脚本缓存互不相同，不在本地记录是否已加载
+pub struct Script {
+    source: &'static str,
+    sha: String,
+}
+
+impl Script {
+    pub fn new(source: &'static str) -> Self {
+        let digest = Sha1::digest(source.as_bytes());
+        let sha = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        Self { source, sha }
+    }
+
+    pub fn sha(&self) -> &str {
+        &self.sha
+    }
+
+    // 通过 SCRIPT LOAD 缓存脚本，返回的 SHA1 须与本地计算的一致；
+    // 集群中只加载到一个节点，其余节点在首次调用时由 EVAL 缓存
+    pub async fn load(&self, redis: &Redis) -> Result<(), Box<SyncError>> {
+        let sha: String = redis
+            .query(vec!["SCRIPT".into(), "LOAD".into(), self.source.into()])
+            .await?;
+        if sha != self.sha {
+            return Err(format!("Err: SCRIPT LOAD returned unexpected sha {}", sha).into());
+        }
+        Ok(())
+    }
+}
+
+impl Redis {
+    // EVALSHA 调用脚本；目标节点没有缓存该脚本（首次调用、重启、SCRIPT FLUSH、主从切换）时退回 EVAL，
+    // EVAL 同时会把脚本缓存到该节点
+    pub async fn invoke_script<T: FromResp>(
+        &self,
+        script: &Script,
+        keys: &[&str],
+        args: &[&str],
+    ) -> Result<T, Box<SyncError>> {
+        let mut params = vec![keys.len().to_string()];
+        params.extend(keys.iter().map(|k| k.to_string()));
+        params.extend(args.iter().map(|a| a.to_string()));
+
+        let mut cmd = vec!["EVALSHA".to_string(), script.sha().to_string()];
+        cmd.extend(params.iter().cloned());
+        let reply = match self.redis_cmd(cmd).await? {
+            Some(RespValue::Error(e)) if e.starts_with("NOSCRIPT") => {
+                let mut cmd = vec!["EVAL".to_string(), script.source.to_string()];
+                cmd.extend(params);
+                self.redis_cmd(cmd).await?
+            }
+            reply => reply,
+        };
+        Ok(T::from_resp(reply.unwrap_or(RespValue::Null))?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::conn::{command, test::fake_redis};
+
+    #[test]
+    fn script_sha() {
+        assert_eq!(
+            Script::new("return 1").sha(),
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db"
+        );
+    }
+
+    #[tokio::test]
+    async fn load_checks_sha() {
+        let script = Script::new("return 1");
+        let (addr, server) = fake_redis(vec![
+            RespValue::BulkString(Some(script.sha().into())),
+            RespValue::BulkString(Some("0".repeat(40))),
+        ])
+        .await;
+        let redis = Redis::new(&addr).unwrap();
+        script.load(&redis).await.unwrap();
+        assert!(script.load(&redis).await.is_err());
+        assert_eq!(
+            server.await.unwrap(),
+            vec![command(["SCRIPT", "LOAD", "return 1"]); 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_eval_on_noscript() {
+        let script = Script::new("return 1");
+        let sha = script.sha().to_string();
+        let (addr, server) = fake_redis(vec![
+            RespValue::Error("NOSCRIPT No matching script".into()),
+            RespValue::Integer(1),
+            RespValue::Integer(1),
+        ])
+        .await;
+        let redis = Redis::new(&addr).unwrap();
+        assert!(
+            redis
+                .invoke_script::<bool>(&script, &["k"], &["v"])
+                .await
+                .unwrap()
+        );
+        assert!(
+            redis
+                .invoke_script::<bool>(&script, &["k"], &["v"])
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            server.await.unwrap(),
+            vec![
+                command(["EVALSHA", &sha, "1", "k", "v"]),
+                command(["EVAL", "return 1", "1", "k", "v"]),
+                command(["EVALSHA", &sha, "1", "k", "v"]),
+            ]
+        );
+    }
+}
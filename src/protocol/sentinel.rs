@@ -0,0 +1,178 @@
+use std::sync::Mutex;
+
+use tokio::time::timeout;
+
+use crate::{
+    protocol::{
+        config::{RedisAddr, RedisConfig, RedisMode},
+        conn::{Connection, command},
+        types::FromResp,
+    },
+    server::SyncError,
+};
+
+// 通过哨兵发现当前主节点，并缓存结果直到发生故障切换
+pub struct Sentinel {
+    master_name: String,
+    // 连接哨兵使用的参数
+    config: RedisConfig,
+    // 最近一次应答成功的哨兵排在最前面
+    sentinels: Mutex<Vec<RedisAddr>>,
+    master: Mutex<Option<RedisAddr>>,
+}
+
+impl Sentinel {
+    pub fn new(config: &RedisConfig) -> Option<Self> {
+        let RedisMode::Sentinel { master_name, .. } = &config.mode else {
+            return None;
+        };
+        let sentinel_config = config.sentinel_config()?;
+        Some(Self {
+            master_name: master_name.clone(),
+            sentinels: Mutex::new(sentinel_config.nodes.clone()),
+            config: sentinel_config,
+            master: Mutex::new(None),
+        })
+    }
+
+    pub async fn master(&self) -> Result<RedisAddr, Box<SyncError>> {
+        if let Some(addr) = self.master.lock().unwrap().clone() {
+            return Ok(addr);
+        }
+        let addr = self.discover().await?;
+        *self.master.lock().unwrap() = Some(addr.clone());
+        Ok(addr)
+    }
+
+    // 主节点不可达或已降级为从节点时调用，下次使用前重新发现
+    pub fn invalidate(&self) {
+        if let Some(addr) = self.master.lock().unwrap().take() {
+            eprintln!("Redis master {} of {} invalidated", addr, self.master_name);
+        }
+    }
+
+    async fn discover(&self) -> Result<RedisAddr, Box<SyncError>> {
+        let sentinels = self.sentinels.lock().unwrap().clone();
+        let mut errors = Vec::new();
+        for (i, sentinel) in sentinels.iter().enumerate() {
+            match self.ask(sentinel).await {
+                Ok(addr) => {
+                    if i > 0 {
+                        let mut list = self.sentinels.lock().unwrap();
+                        if let Some(pos) = list.iter().position(|s| s == sentinel) {
+                            let found = list.remove(pos);
+                            list.insert(0, found);
+                        }
+                    }
+                    return Ok(addr);
+                }
+                Err(e) => errors.push(format!("{}: {}", sentinel, e)),
+            }
+        }
+        Err(format!(
+            "Err: No sentinel could resolve master {} ({})",
+            self.master_name,
+            errors.join("; ")
+        )
+        .into())
+    }
+
+    async fn ask(&self, sentinel: &RedisAddr) -> Result<RedisAddr, Box<SyncError>> {
+        let mut conn = timeout(
+            self.config.connect_timeout,
+            Connection::connect(sentinel, &self.config),
+        )
+        .await
+        .map_err(|_| "connect timed out")??;
+        let reply = timeout(
+            self.config.command_timeout,
+            conn.request(command([
+                "SENTINEL",
+                "get-master-addr-by-name",
+                &self.master_name,
+            ])),
+        )
+        .await
+        .map_err(|_| "command timed out")??;
+        match Option::<(String, String)>::from_resp(reply)? {
+            Some((host, port)) => Ok(RedisAddr::Tcp {
+                host,
+                port: port.parse::<u16>()?,
+            }),
+            None => Err("master name unknown".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::protocol::{Redis, commands::SetOptions, conn::test::fake_server, resp::RespValue};
+
+    fn bulk(s: &str) -> RespValue {
+        RespValue::BulkString(Some(s.into()))
+    }
+
+    #[tokio::test]
+    async fn discovers_master_and_follows_failover() {
+        // 哨兵当前报告的主节点
+        let master = Arc::new(Mutex::new(String::new()));
+
+        let new_master = fake_server(|cmd| match cmd[0].as_str() {
+            "GET" => bulk("from-new"),
+            _ => RespValue::SimpleString("OK".into()),
+        })
+        .await;
+        let promoted = master.clone();
+        let old_master = fake_server(move |cmd| match cmd[0].as_str() {
+            "GET" => bulk("from-old"),
+            // 写入时发现自己已被降级，同时哨兵完成切换
+            _ => {
+                *promoted.lock().unwrap() = new_master.clone();
+                RespValue::Error("READONLY You can't write against a read only replica.".into())
+            }
+        })
+        .await;
+        *master.lock().unwrap() = old_master;
+
+        let reported = master.clone();
+        let sentinel = fake_server(move |cmd| {
+            assert_eq!(cmd, ["SENTINEL", "get-master-addr-by-name", "mymaster"]);
+            let addr = reported.lock().unwrap().clone();
+            let (host, port) = addr.rsplit_once(':').unwrap();
+            RespValue::Array(vec![bulk(host), bulk(port)])
+        })
+        .await;
+        // 第一个哨兵不可达
+        let dead = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let redis = Redis::new(&format!(
+            "redis+sentinel://{},{}/mymaster?retries=1",
+            dead, sentinel
+        ))
+        .unwrap();
+        assert_eq!(redis.get("k").await.unwrap().as_deref(), Some("from-old"));
+        assert!(
+            redis
+                .set("k", "v", SetOptions::default().nx())
+                .await
+                .unwrap()
+        );
+        assert_eq!(redis.get("k").await.unwrap().as_deref(), Some("from-new"));
+        // 应答成功的哨兵被提到列表最前
+        let sentinels = redis
+            .sentinel
+            .as_ref()
+            .unwrap()
+            .sentinels
+            .lock()
+            .unwrap()
+            .clone();
+        assert_eq!(sentinels[0].to_string(), sentinel);
+    }
+}
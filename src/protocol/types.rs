@@ -0,0 +1,227 @@
+use std::{collections::HashMap, fmt, hash::Hash};
+
+use crate::protocol::resp::RespValue;
+
+// 将 Redis 回复转换为 Rust 类型
+pub trait FromResp: Sized {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FromRespError {
+    // Redis 返回了错误回复
+    Reply(String),
+    // 回复类型与期望不符
+    TypeMismatch { expected: &'static str, got: String },
+}
+
+impl FromRespError {
+    fn mismatch(expected: &'static str, value: &RespValue) -> Self {
+        if let RespValue::Error(e) = value {
+            return FromRespError::Reply(e.clone());
+        }
+        FromRespError::TypeMismatch {
+            expected,
+            got: describe(value),
+        }
+    }
+}
+
+impl fmt::Display for FromRespError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FromRespError::Reply(e) => write!(f, "Err: Redis replied: {}", e),
+            FromRespError::TypeMismatch { expected, got } => {
+                write!(
+                    f,
+                    "Err: Redis reply type mismatch: expected {}, got {}",
+                    expected, got
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FromRespError {}
+
+// 错误信息中展示的回复摘要，过长的内容会被截断
+fn describe(value: &RespValue) -> String {
+    fn short(s: &str) -> String {
+        match s.char_indices().nth(32) {
+            Some((pos, _)) => format!("{:?}...", &s[..pos]),
+            None => format!("{:?}", s),
+        }
+    }
+    match value {
+        RespValue::SimpleString(s) => format!("simple string {}", short(s)),
+        RespValue::Error(e) => format!("error {}", short(e)),
+        RespValue::Integer(n) => format!("integer {}", n),
+        RespValue::BulkString(Some(s)) => format!("bulk string {}", short(s)),
+        RespValue::BulkString(None) | RespValue::Null => "nil".to_string(),
+        RespValue::Array(values) => format!("array of {} elements", values.len()),
+    }
+}
+
+impl FromResp for RespValue {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::Error(e) => Err(FromRespError::Reply(e)),
+            value => Ok(value),
+        }
+    }
+}
+
+// 只关心命令是否成功
+impl FromResp for () {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        RespValue::from_resp(value).map(|_| ())
+    }
+}
+
+impl FromResp for String {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::SimpleString(s) | RespValue::BulkString(Some(s)) => Ok(s),
+            RespValue::Integer(n) => Ok(n.to_string()),
+            value => Err(FromRespError::mismatch("string", &value)),
+        }
+    }
+}
+
+impl FromResp for i64 {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::Integer(n) => Ok(n),
+            RespValue::SimpleString(ref s) | RespValue::BulkString(Some(ref s)) => s
+                .parse::<i64>()
+                .map_err(|_| FromRespError::mismatch("integer", &value)),
+            value => Err(FromRespError::mismatch("integer", &value)),
+        }
+    }
+}
+
+impl FromResp for u64 {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::Integer(n) if n >= 0 => Ok(n as u64),
+            RespValue::SimpleString(ref s) | RespValue::BulkString(Some(ref s)) => s
+                .parse::<u64>()
+                .map_err(|_| FromRespError::mismatch("unsigned integer", &value)),
+            value => Err(FromRespError::mismatch("unsigned integer", &value)),
+        }
+    }
+}
+
+// :1 / :0 以及 +OK 与 nil（如 SET NX 未生效）
+impl FromResp for bool {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::Integer(0) => Ok(false),
+            RespValue::Integer(1) => Ok(true),
+            RespValue::SimpleString(ref s) if s == "OK" => Ok(true),
+            RespValue::BulkString(None) | RespValue::Null => Ok(false),
+            value => Err(FromRespError::mismatch("boolean", &value)),
+        }
+    }
+}
+
+impl<T: FromResp> FromResp for Option<T> {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::BulkString(None) | RespValue::Null => Ok(None),
+            value => T::from_resp(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromResp> FromResp for Vec<T> {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::Array(values) => values.into_iter().map(T::from_resp).collect(),
+            RespValue::BulkString(None) | RespValue::Null => Ok(Vec::new()),
+            value => Err(FromRespError::mismatch("array", &value)),
+        }
+    }
+}
+
+impl<A: FromResp, B: FromResp> FromResp for (A, B) {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::Array(values) if values.len() == 2 => {
+                let mut values = values.into_iter();
+                let a = A::from_resp(values.next().unwrap())?;
+                let b = B::from_resp(values.next().unwrap())?;
+                Ok((a, b))
+            }
+            value => Err(FromRespError::mismatch("array of 2 elements", &value)),
+        }
+    }
+}
+
+// HGETALL 之类的回复：键值交替排列的数组
+impl<K: FromResp + Eq + Hash, V: FromResp> FromResp for HashMap<K, V> {
+    fn from_resp(value: RespValue) -> Result<Self, FromRespError> {
+        match value {
+            RespValue::Array(values) if values.len() % 2 == 0 => {
+                let mut map = HashMap::with_capacity(values.len() / 2);
+                let mut values = values.into_iter();
+                while let (Some(k), Some(v)) = (values.next(), values.next()) {
+                    map.insert(K::from_resp(k)?, V::from_resp(v)?);
+                }
+                Ok(map)
+            }
+            RespValue::BulkString(None) | RespValue::Null => Ok(HashMap::new()),
+            value => Err(FromRespError::mismatch("array of key-value pairs", &value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bulk(s: &str) -> RespValue {
+        RespValue::BulkString(Some(s.into()))
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(String::from_resp(bulk("v")), Ok("v".to_string()));
+        assert_eq!(i64::from_resp(RespValue::Integer(-3)), Ok(-3));
+        assert_eq!(i64::from_resp(bulk("42")), Ok(42));
+        assert_eq!(
+            bool::from_resp(RespValue::SimpleString("OK".into())),
+            Ok(true)
+        );
+        assert_eq!(bool::from_resp(RespValue::Null), Ok(false));
+        assert_eq!(Option::<String>::from_resp(RespValue::Null), Ok(None));
+        assert_eq!(
+            Vec::<String>::from_resp(RespValue::Array(vec![bulk("a"), bulk("b")])),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+        let map = HashMap::<String, i64>::from_resp(RespValue::Array(vec![bulk("a"), bulk("1")]))
+            .unwrap();
+        assert_eq!(map.get("a"), Some(&1));
+        assert_eq!(
+            <(u64, Vec<String>)>::from_resp(RespValue::Array(vec![
+                bulk("0"),
+                RespValue::Array(vec![])
+            ])),
+            Ok((0, vec![]))
+        );
+    }
+
+    #[test]
+    fn mismatch_errors() {
+        assert_eq!(
+            i64::from_resp(bulk("abc")).unwrap_err().to_string(),
+            "Err: Redis reply type mismatch: expected integer, got bulk string \"abc\""
+        );
+        assert_eq!(
+            String::from_resp(RespValue::Error("WRONGTYPE".into())),
+            Err(FromRespError::Reply("WRONGTYPE".into()))
+        );
+        assert!(HashMap::<String, String>::from_resp(RespValue::Array(vec![bulk("a")])).is_err());
+        assert!(bool::from_resp(RespValue::Integer(2)).is_err());
+    }
+}
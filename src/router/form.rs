@@ -0,0 +1,223 @@
+use std::fmt;
+
+use httparse::Request;
+
+// 表单大小与字段数的上限
+#[derive(Debug, Clone, Copy)]
+pub struct FormLimits {
+    pub max_bytes: usize,
+    pub max_fields: usize,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        FormLimits {
+            max_bytes: 16 * 1024,
+            max_fields: 64,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FormError {
+    UnsupportedMediaType,
+    TooLarge,
+    TooManyFields,
+    MissingField(&'static str),
+    // 字段存在但取值不合法
+    InvalidField(&'static str),
+}
+
+impl FormError {
+    // 对应的 HTTP 状态行
+    pub fn status(&self) -> &'static str {
+        match self {
+            FormError::UnsupportedMediaType => "415 Unsupported Media Type",
+            FormError::TooLarge => "413 Payload Too Large",
+            FormError::TooManyFields | FormError::MissingField(_) | FormError::InvalidField(_) => {
+                "400 Bad Request"
+            }
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::UnsupportedMediaType => {
+                write!(f, "expected application/x-www-form-urlencoded")
+            }
+            FormError::TooLarge => write!(f, "form body too large"),
+            FormError::TooManyFields => write!(f, "too many form fields"),
+            FormError::MissingField(name) => write!(f, "missing form field '{}'", name),
+            FormError::InvalidField(name) => write!(f, "invalid form field '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+// application/x-www-form-urlencoded 解析结果，保留字段顺序，同名字段可出现多次
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FormData {
+    fields: Vec<(String, String)>,
+}
+
+impl FormData {
+    pub fn parse(body: &[u8], limits: FormLimits) -> Result<FormData, FormError> {
+        if body.len() > limits.max_bytes {
+            return Err(FormError::TooLarge);
+        }
+        let mut fields = Vec::new();
+        for pair in body.split(|b| *b == b'&').filter(|p| !p.is_empty()) {
+            if fields.len() == limits.max_fields {
+                return Err(FormError::TooManyFields);
+            }
+            let (name, value) = match pair.iter().position(|b| *b == b'=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, &[][..]),
+            };
+            fields.push((decode(name), decode(value)));
+        }
+        Ok(FormData { fields })
+    }
+
+    // 同名字段取第一个
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    // 多选框等同名字段
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn require(&self, name: &'static str) -> Result<&str, FormError> {
+        self.get(name).ok_or(FormError::MissingField(name))
+    }
+}
+
+// `+` 表示空格，其余按百分号编码解码，非法 UTF-8 以替换字符代替
+fn decode(raw: &[u8]) -> String {
+    let raw = raw
+        .iter()
+        .map(|b| if *b == b'+' { b' ' } else { *b })
+        .collect::<Vec<_>>();
+    percent_encoding::percent_decode(&raw)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+// 从表单字段构造处理函数需要的结构
+pub trait FromForm: Sized {
+    fn from_form(form: &FormData) -> Result<Self, FormError>;
+}
+
+pub struct Form<T>(pub T);
+
+impl<T: FromForm> Form<T> {
+    pub fn extract(req: &Request<'_, '_>, body: &[u8]) -> Result<Self, FormError> {
+        Self::extract_with(req, body, FormLimits::default())
+    }
+
+    // 未携带 Content-Type 时按表单处理
+    pub fn extract_with(
+        req: &Request<'_, '_>,
+        body: &[u8],
+        limits: FormLimits,
+    ) -> Result<Self, FormError> {
+        let content_type = req
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Content-Type"))
+            .and_then(|h| str::from_utf8(h.value).ok());
+        if let Some(content_type) = content_type {
+            let mime = content_type.split(';').next().unwrap_or_default().trim();
+            if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+                return Err(FormError::UnsupportedMediaType);
+            }
+        }
+        let form = FormData::parse(body, limits)?;
+        Ok(Form(T::from_form(&form)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_decode() {
+        let form = FormData::parse(
+            b"user=%E5%BC%A0+san&password=a%26b%3Dc&tag=x&tag=y&flag&=empty",
+            FormLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(form.get("user"), Some("张 san"));
+        assert_eq!(form.get("password"), Some("a&b=c"));
+        assert_eq!(form.get_all("tag").collect::<Vec<_>>(), ["x", "y"]);
+        assert_eq!(form.get("flag"), Some(""));
+        assert_eq!(form.get(""), Some("empty"));
+        assert_eq!(form.require("nope"), Err(FormError::MissingField("nope")));
+        // 非法的百分号编码原样保留
+        let form = FormData::parse(b"a=%zz%", FormLimits::default()).unwrap();
+        assert_eq!(form.get("a"), Some("%zz%"));
+    }
+
+    #[test]
+    fn limits() {
+        let limits = FormLimits {
+            max_bytes: 16,
+            max_fields: 2,
+        };
+        assert_eq!(
+            FormData::parse(b"a=1&b=2&c=3", limits),
+            Err(FormError::TooManyFields)
+        );
+        assert_eq!(
+            FormData::parse(b"a=0123456789abcdef", limits),
+            Err(FormError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn extract_checks_content_type() {
+        struct Login {
+            user: String,
+        }
+        impl FromForm for Login {
+            fn from_form(form: &FormData) -> Result<Self, FormError> {
+                Ok(Login {
+                    user: form.require("user")?.to_string(),
+                })
+            }
+        }
+        let mut headers = [httparse::Header {
+            name: "Content-Type",
+            value: b"application/x-www-form-urlencoded; charset=UTF-8",
+        }];
+        let req = Request::new(&mut headers);
+        let Form(login) = Form::<Login>::extract(&req, b"user=bob").unwrap();
+        assert_eq!(login.user, "bob");
+        assert!(matches!(
+            Form::<Login>::extract(&req, b"name=bob"),
+            Err(FormError::MissingField("user"))
+        ));
+
+        let mut headers = [httparse::Header {
+            name: "content-type",
+            value: b"application/json",
+        }];
+        let req = Request::new(&mut headers);
+        assert!(matches!(
+            Form::<Login>::extract(&req, b"user=bob"),
+            Err(FormError::UnsupportedMediaType)
+        ));
+    }
+}
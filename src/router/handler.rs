@@ -1,11 +1,29 @@
-use crate::{protocol::Redis, router::prelude::*};
+use crate::{
+    middleware::{
+        auth::{Credential, Identity, session_cookie, unauthorized, user_roles},
+        log::security_event,
+    },
+    router::{
+        Context, content_length,
+        form::{Form, FormData, FormError, FormLimits, FromForm},
+        header,
+        json::{Json, is_json, send_error, send_json, wants_json},
+        multipart::{self, Multipart},
+        prelude::*,
+    },
+    security::{access::is_permission, password, tokens},
+    server::AppState,
+    store::{ApiToken, Session, now, session_id, validate_role, validate_username},
+};
 use bytes::BytesMut;
-use std::{ffi::OsStr, sync::Arc};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use std::{ffi::OsStr, path::Path, sync::Arc, time::Duration};
 
 pub struct Handler;
 
 impl Handler {
-    pub async fn f1(stream: &mut TcpStream, _req: &Request<'_, '_>) -> Result<(), Box<SyncError>> {
+    pub async fn f1(stream: &mut Conn, _req: &Request<'_, '_>) -> Result<(), Box<SyncError>> {
         let body = "Hello, World!";
         let response = format!(
             "HTTP/1.1 200 OK\r\n\
@@ -16,14 +34,14 @@ impl Handler {
             body.len(),
             body
         );
-        stream.write(response.as_bytes()).await?;
+        stream.write_all(response.as_bytes()).await?;
         stream.flush().await?;
         stream.shutdown().await?;
         Ok(())
     }
 
     pub async fn echo_method(
-        stream: &mut TcpStream,
+        stream: &mut Conn,
         req: &Request<'_, '_>,
     ) -> Result<(), Box<SyncError>> {
         let method = req.method.unwrap_or_default();
@@ -36,16 +54,13 @@ impl Handler {
             method.len(),
             method
         );
-        stream.write(response.as_bytes()).await?;
+        stream.write_all(response.as_bytes()).await?;
         stream.flush().await?;
         stream.shutdown().await?;
         Ok(())
     }
 
-    pub async fn echo_ip(
-        stream: &mut TcpStream,
-        _req: &Request<'_, '_>,
-    ) -> Result<(), Box<SyncError>> {
+    pub async fn echo_ip(stream: &mut Conn, _req: &Request<'_, '_>) -> Result<(), Box<SyncError>> {
         let addr = stream.peer_addr()?.to_string();
         let response = format!(
             "HTTP/1.1 200 OK\r\n\
@@ -56,57 +71,119 @@ impl Handler {
             addr.len(),
             addr
         );
-        stream.write(response.as_bytes()).await?;
+        stream.write_all(response.as_bytes()).await?;
         stream.flush().await?;
         stream.shutdown().await?;
         Ok(())
     }
 
     pub async fn login(
-        stream: &mut TcpStream,
-        db: Arc<Redis>,
+        stream: &mut Conn,
+        state: Arc<AppState>,
         req: &Request<'_, '_>,
         body: BytesMut,
     ) -> Result<(), Box<SyncError>> {
         match req.method.unwrap_or_default() {
             // "GET" => {}
             "POST" => {
-                if let Ok(s) = str::from_utf8(&body) {
-                    let post = s.split('&').collect::<Vec<_>>();
-                    // 实际认证使用另一个简易redis项目, 这里放简单示例
-                    if post.len() == 3 {
-                        //if &post[0][5..] == "usr" && &post[1][9..] == "pwd" {
-                        if db.test_user_password(&post[0][5..], &post[1][9..]).await? {
-                            let peer_addr = stream.peer_addr()?;
-                            println!("验证通过,来自 {}", peer_addr);
+                let login = match extract_body::<LoginForm>(req, &body) {
+                    Ok(login) => login,
+                    Err((status, reason)) => {
+                        return Self::failed(stream, req, status, &reason).await;
+                    }
+                };
+                if state
+                    .db
+                    .users
+                    .verify_password(&login.user, &login.password)
+                    .await?
+                {
+                    let peer_addr = stream.peer_addr()?;
+                    println!("验证通过,来自 {}", peer_addr);
+                    let roles = user_roles(&state, &login.user).await?;
+                    let (cookie, ttl, csrf_token, token) = match &state.config.jwt {
+                        // 令牌本身即为会话，不写入会话存储
+                        Some(jwt) => {
+                            let (token, claims) = jwt.issue(&login.user, roles)?;
+                            let cookie = state.config.cookie.set_cookie(&token, jwt.ttl);
+                            (cookie, jwt.ttl, claims.csrf, Some(token))
+                        }
+                        None => {
+                            let session = Session::new(
+                                &login.user,
+                                roles,
+                                &peer_addr.ip().to_string(),
+                                header(req, "User-Agent").unwrap_or_default(),
+                            );
+                            let ttl = state
+                                .config
+                                .session
+                                .ttl(session.created_at, session.created_at);
                             let mut key;
                             loop {
                                 key = gen_uuid();
-                                if db.unique_key(&key).await? {
-                                    db.set_session_key(&key, peer_addr.ip().to_string(), 3600 * 24)
-                                        .await?;
+                                if state
+                                    .db
+                                    .sessions
+                                    .create_session(&key, &session, ttl)
+                                    .await?
+                                {
                                     #[cfg(debug_assertions)]
-                                    println!("  Session-{}",key);
+                                    println!("  Session-{}", key);
                                     break;
                                 }
                             }
-                            let notice = "success";
-                            let response = format!(
-                                "HTTP/1.1 200 OK\r\n\
-                                Content-Type: text/plain\r\n\
-                                Set-Cookie: key=\"{}\"; path=/\r\n\
-                                Content-Length: {}\r\n\
-                                Connection: close\r\n\r\n{}",
-                                key,
-                                notice.len(),
-                                notice
-                            );
-                            stream.write_all(response.as_bytes()).await?;
-                            stream.flush().await?;
-                            stream.shutdown().await?;
-                            return Ok(());
+                            let cookie = session_cookie(&state.config, &key, &session);
+                            (cookie, ttl, session.csrf_token, None)
+                        }
+                    };
+                    if wants_json(req) {
+                        let mut body = json!({
+                            "ok": true,
+                            "expires_in": ttl.as_secs(),
+                            "csrf_token": csrf_token,
+                        });
+                        // JWT 模式下 API 客户端可改用 Authorization: Bearer 携带令牌
+                        if let Some(token) = token {
+                            body["token"] = json!(token);
                         }
+                        return send_json(stream, "200 OK", &[cookie], &body).await;
+                    }
+                    // 从登录跳转页带来的原地址
+                    if let Some(next) = login.next.as_deref().filter(|next| is_local_path(next)) {
+                        let response = format!(
+                            "HTTP/1.1 303 See Other\r\n\
+                            Location: {}\r\n\
+                            {}\r\n\
+                            Content-Length: 0\r\n\
+                            Connection: close\r\n\r\n",
+                            next, cookie
+                        );
+                        stream.write_all(response.as_bytes()).await?;
+                        stream.flush().await?;
+                        stream.shutdown().await?;
+                        return Ok(());
                     }
+                    let notice = "success";
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\n\
+                        Content-Type: text/plain\r\n\
+                        {}\r\n\
+                        Content-Length: {}\r\n\
+                        Connection: close\r\n\r\n{}",
+                        cookie,
+                        notice.len(),
+                        notice
+                    );
+                    stream.write_all(response.as_bytes()).await?;
+                    stream.flush().await?;
+                    stream.shutdown().await?;
+                    return Ok(());
+                }
+                // 表单登录页依赖 200 + "failed" 的旧行为，JSON 客户端得到 401
+                if wants_json(req) {
+                    let body = json!({"ok": false, "error": "invalid username or password"});
+                    return send_json(stream, "401 Unauthorized", &[], &body).await;
                 }
                 let notice = "failed";
                 let response = format!(
@@ -126,7 +203,565 @@ impl Handler {
         }
     }
 
-    pub async fn file(stream: &mut TcpStream, req: &Request<'_, '_>) -> Result<(), Box<SyncError>> {
+    pub async fn register(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        req: &Request<'_, '_>,
+        body: BytesMut,
+    ) -> Result<(), Box<SyncError>> {
+        if req.method != Some("POST") {
+            return Self::f_404(stream, req).await;
+        }
+        let form = match extract_body::<RegisterForm>(req, &body) {
+            Ok(form) => form,
+            Err((status, reason)) => return Self::failed(stream, req, status, &reason).await,
+        };
+        let (user, password) = (form.user.as_str(), form.password.as_str());
+        let checked = validate_username(user)
+            .and_then(|_| match form.password == form.re_password {
+                true => Ok(()),
+                false => Err("passwords do not match"),
+            })
+            .and_then(|_| password::check_strength(user, password));
+        if let Err(reason) = checked {
+            return Self::failed(stream, req, "400 Bad Request", reason).await;
+        }
+        if !state.db.users.create_user(user, password).await? {
+            return Self::failed(stream, req, "409 Conflict", "user already exists").await;
+        }
+        println!("新用户注册: {}", user);
+        if wants_json(req) {
+            return send_json(
+                stream,
+                "201 Created",
+                &[],
+                &json!({"ok": true, "user": user}),
+            )
+            .await;
+        }
+        // 注册成功后跳转到登录页，正文与失败时的 "failed: ..." 对应
+        let notice = "success";
+        let response = format!(
+            "HTTP/1.1 303 See Other\r\n\
+            Location: /srs/LoginInterface.html\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{}",
+            notice.len(),
+            notice
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    // 注销当前会话（JWT 模式下把令牌加入注销列表）并清除 Cookie
+    pub async fn logout(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+    ) -> Result<(), Box<SyncError>> {
+        if req.method != Some("POST") {
+            return Self::f_404(stream, req).await;
+        }
+        if let Some(identity) = &ctx.identity
+            && !identity.check_csrf(req)
+        {
+            return Self::failed(stream, req, "403 Forbidden", "invalid csrf token").await;
+        }
+        if let Some(key) = state.config.cookie.session_key(req) {
+            state.db.sessions.delete_session(&key).await?;
+        }
+        // 本次请求中更换出的新会话键
+        if let Some(identity) = &ctx.identity {
+            state.db.sessions.delete_session(&identity.key).await?;
+            // 注销记录保留到令牌过期，之后令牌本身已无法通过校验
+            if let Credential::Jwt {
+                jti, expires_at, ..
+            } = &identity.credential
+            {
+                // 校验时容许 leeway 的时钟偏差，过期后的这段时间内令牌仍可通过校验
+                let leeway = state
+                    .config
+                    .jwt
+                    .as_ref()
+                    .map_or(Duration::ZERO, |jwt| jwt.leeway);
+                let ttl = Duration::from_secs(expires_at.saturating_sub(now())) + leeway;
+                state.db.sessions.deny_token(jti, ttl).await?;
+            }
+        }
+        let cookie = state.config.cookie.clear_cookie();
+        if wants_json(req) {
+            return send_json(stream, "200 OK", &[cookie], &json!({"ok": true})).await;
+        }
+        let response = format!(
+            "HTTP/1.1 303 See Other\r\n\
+            Location: /srs/LoginInterface.html\r\n\
+            {}\r\n\
+            Content-Length: 0\r\n\
+            Connection: close\r\n\r\n",
+            cookie
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    // 当前登录的身份，前端据此取得 CSRF 令牌
+    pub async fn current_session(
+        stream: &mut Conn,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+    ) -> Result<(), Box<SyncError>> {
+        if req.method != Some("GET") {
+            return Self::f_404(stream, req).await;
+        }
+        let Some(identity) = &ctx.identity else {
+            return send_error(stream, "401 Unauthorized", "not logged in").await;
+        };
+        let session = &identity.session;
+        let body = json!({
+            "ok": true,
+            "user": session.user,
+            "roles": session.roles,
+            "csrf_token": session.csrf_token,
+            "created_at": session.created_at,
+            "last_seen": session.last_seen,
+        });
+        send_json(stream, "200 OK", &[], &body).await
+    }
+
+    // 列出有效会话，管理员可以通过 ?user= 查看其他用户的会话
+    pub async fn sessions(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+    ) -> Result<(), Box<SyncError>> {
+        if req.method != Some("GET") {
+            return Self::f_404(stream, req).await;
+        }
+        if state.config.jwt.is_some() {
+            return send_error(stream, "501 Not Implemented", "not supported in jwt mode").await;
+        }
+        let query = req
+            .path
+            .and_then(|p| p.split_once('?'))
+            .map_or("", |(_, query)| query);
+        let requested = match FormData::parse(query.as_bytes(), FormLimits::default()) {
+            Ok(query) => query.get("user").map(String::from),
+            Err(e) => return send_error(stream, e.status(), &e.to_string()).await,
+        };
+        let Some(identity) = &ctx.identity else {
+            return send_error(stream, "401 Unauthorized", "not logged in").await;
+        };
+        let Some(target) = target_user(&state, identity, requested, "sessions.manage") else {
+            return send_error(stream, "403 Forbidden", "not allowed").await;
+        };
+        let sessions = state.db.sessions.list_sessions(&target).await?;
+        let sessions = sessions
+            .iter()
+            .map(|info| {
+                json!({
+                    "id": session_id(&info.key),
+                    "ip": info.session.ip,
+                    "user_agent": info.session.user_agent,
+                    "created_at": info.session.created_at,
+                    "last_seen": info.session.last_seen,
+                    "expires_in": info.expires_in.as_secs(),
+                    "current": info.key == identity.key,
+                })
+            })
+            .collect::<Vec<Value>>();
+        let body = json!({"ok": true, "user": target, "sessions": sessions});
+        send_json(stream, "200 OK", &[], &body).await
+    }
+
+    // 注销指定 id 的会话；未指定时注销用户的全部会话（注销自己的会话时保留当前会话）
+    pub async fn revoke_sessions(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+        body: BytesMut,
+    ) -> Result<(), Box<SyncError>> {
+        if req.method != Some("POST") {
+            return Self::f_404(stream, req).await;
+        }
+        // JWT 模式下签发的令牌无法逐个找回，只能在注销时加入注销列表
+        if state.config.jwt.is_some() {
+            return send_error(stream, "501 Not Implemented", "not supported in jwt mode").await;
+        }
+        let form = match extract_body::<RevokeForm>(req, &body) {
+            Ok(form) => form,
+            Err((status, reason)) => return send_error(stream, status, &reason).await,
+        };
+        let Some(identity) = &ctx.identity else {
+            return send_error(stream, "401 Unauthorized", "not logged in").await;
+        };
+        if !identity.check_csrf(req) {
+            return send_error(stream, "403 Forbidden", "invalid csrf token").await;
+        }
+        let Some(target) = target_user(&state, identity, form.user, "sessions.manage") else {
+            return send_error(stream, "403 Forbidden", "not allowed").await;
+        };
+        let user = &identity.session.user;
+        let sessions = &state.db.sessions;
+        let revoked = match form.id {
+            Some(id) => {
+                let mut revoked = 0;
+                for info in sessions.list_sessions(&target).await? {
+                    if session_id(&info.key) == id && sessions.delete_session(&info.key).await? {
+                        revoked += 1;
+                    }
+                }
+                if revoked == 0 {
+                    return send_error(stream, "404 Not Found", "session not found").await;
+                }
+                revoked
+            }
+            None => {
+                let keep = (&target == user).then_some(identity.key.as_str());
+                sessions.revoke_sessions(&target, keep).await?
+            }
+        };
+        println!("{} 注销了 {} 的 {} 个会话", user, target, revoked);
+        send_json(
+            stream,
+            "200 OK",
+            &[],
+            &json!({"ok": true, "revoked": revoked}),
+        )
+        .await
+    }
+
+    // 列出（GET ?user=）与创建（POST）API 令牌，完整令牌只在创建时返回一次
+    pub async fn tokens(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+        body: BytesMut,
+    ) -> Result<(), Box<SyncError>> {
+        let Some(identity) = &ctx.identity else {
+            return send_error(stream, "401 Unauthorized", "not logged in").await;
+        };
+        match req.method.unwrap_or_default() {
+            "GET" => {
+                let query = req
+                    .path
+                    .and_then(|p| p.split_once('?'))
+                    .map_or("", |(_, query)| query);
+                let requested = match FormData::parse(query.as_bytes(), FormLimits::default()) {
+                    Ok(query) => query.get("user").map(String::from),
+                    Err(e) => return send_error(stream, e.status(), &e.to_string()).await,
+                };
+                let Some(target) = target_user(&state, identity, requested, "tokens.manage") else {
+                    return send_error(stream, "403 Forbidden", "not allowed").await;
+                };
+                let tokens = state.db.tokens.list_tokens(&target).await?;
+                let tokens = tokens.iter().map(token_info).collect::<Vec<Value>>();
+                let body = json!({"ok": true, "user": target, "tokens": tokens});
+                send_json(stream, "200 OK", &[], &body).await
+            }
+            "POST" => {
+                if !identity.check_csrf(req) {
+                    return send_error(stream, "403 Forbidden", "invalid csrf token").await;
+                }
+                // 令牌不能再签发令牌，泄露的令牌无法借此长期留存
+                if matches!(identity.credential, Credential::Token { .. }) {
+                    return send_error(stream, "403 Forbidden", "tokens cannot create tokens")
+                        .await;
+                }
+                let form = match extract_body::<TokenForm>(req, &body) {
+                    Ok(form) => form,
+                    Err((status, reason)) => return send_error(stream, status, &reason).await,
+                };
+                let name = form.name.trim();
+                if !(1..=64).contains(&name.chars().count()) || name.chars().any(char::is_control) {
+                    return send_error(
+                        stream,
+                        "400 Bad Request",
+                        "name must be 1 to 64 characters",
+                    )
+                    .await;
+                }
+                if !form.scopes.iter().all(|scope| is_permission(scope)) {
+                    return send_error(stream, "400 Bad Request", "invalid scope").await;
+                }
+                let created_at = now();
+                let (token, new) = loop {
+                    let new = tokens::generate();
+                    let token = ApiToken {
+                        id: new.id.clone(),
+                        user: identity.session.user.clone(),
+                        name: name.to_string(),
+                        scopes: form.scopes.clone(),
+                        hash: new.hash.clone(),
+                        created_at,
+                        last_used: None,
+                        expires_at: form.expires_in.map(|secs| created_at.saturating_add(secs)),
+                    };
+                    if state.db.tokens.create_token(&token).await? {
+                        break (token, new);
+                    }
+                };
+                security_event(
+                    "token_created",
+                    &identity.key,
+                    &identity.session,
+                    json!({"token_id": token.id, "scopes": token.scopes}),
+                );
+                let mut body = token_info(&token);
+                body["ok"] = json!(true);
+                body["token"] = json!(new.token);
+                send_json(stream, "201 Created", &[], &body).await
+            }
+            _ => Self::f_404(stream, req).await,
+        }
+    }
+
+    // 吊销 API 令牌：自己的令牌，或拥有 tokens.manage 权限时任意用户的令牌
+    pub async fn revoke_token(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+        body: BytesMut,
+    ) -> Result<(), Box<SyncError>> {
+        if req.method != Some("POST") {
+            return Self::f_404(stream, req).await;
+        }
+        let Some(identity) = &ctx.identity else {
+            return send_error(stream, "401 Unauthorized", "not logged in").await;
+        };
+        if !identity.check_csrf(req) {
+            return send_error(stream, "403 Forbidden", "invalid csrf token").await;
+        }
+        let form = match extract_body::<RevokeTokenForm>(req, &body) {
+            Ok(form) => form,
+            Err((status, reason)) => return send_error(stream, status, &reason).await,
+        };
+        let tokens = &state.db.tokens;
+        // 无权操作的令牌与不存在的令牌同样回复 404
+        let owned = tokens.token(&form.id).await?.is_some_and(|token| {
+            target_user(&state, identity, Some(token.user), "tokens.manage").is_some()
+        });
+        if !owned || !tokens.delete_token(&form.id).await? {
+            return send_error(stream, "404 Not Found", "token not found").await;
+        }
+        security_event(
+            "token_revoked",
+            &identity.key,
+            &identity.session,
+            json!({"token_id": form.id}),
+        );
+        send_json(stream, "200 OK", &[], &json!({"ok": true})).await
+    }
+
+    // 查看（GET ?user=）与设置（POST）用户在用户存储中的角色，访问规则限定为有 roles.manage 权限的会话
+    pub async fn roles(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+        body: BytesMut,
+    ) -> Result<(), Box<SyncError>> {
+        let Some(identity) = &ctx.identity else {
+            return send_error(stream, "401 Unauthorized", "not logged in").await;
+        };
+        let form = match req.method.unwrap_or_default() {
+            "GET" => {
+                let query = req
+                    .path
+                    .and_then(|p| p.split_once('?'))
+                    .map_or("", |(_, query)| query);
+                match FormData::parse(query.as_bytes(), FormLimits::default()) {
+                    Ok(query) => RolesForm {
+                        user: query.get("user").unwrap_or_default().to_string(),
+                        roles: None,
+                    },
+                    Err(e) => return send_error(stream, e.status(), &e.to_string()).await,
+                }
+            }
+            "POST" => {
+                if !identity.check_csrf(req) {
+                    return send_error(stream, "403 Forbidden", "invalid csrf token").await;
+                }
+                match extract_body::<RolesForm>(req, &body) {
+                    Ok(form) if form.roles.is_some() => form,
+                    Ok(_) => return send_error(stream, "400 Bad Request", "missing roles").await,
+                    Err((status, reason)) => return send_error(stream, status, &reason).await,
+                }
+            }
+            _ => return Self::f_404(stream, req).await,
+        };
+        let users = &state.db.users;
+        if users.password_hash(&form.user).await?.is_none() {
+            return send_error(stream, "404 Not Found", "user not found").await;
+        }
+        let roles = match form.roles {
+            None => users.roles(&form.user).await?,
+            Some(mut roles) => {
+                roles.sort();
+                roles.dedup();
+                // 内置角色不需要也不能写入用户存储
+                roles.retain(|role| role != "user");
+                if let Err(reason) = roles.iter().try_for_each(|role| validate_role(role)) {
+                    return send_error(stream, "400 Bad Request", reason).await;
+                }
+                // 只能授予或收回自己拥有其全部权限的角色，避免借此提升权限；
+                // 没有映射权限的角色仍可满足 role: 规则，只有自己拥有该角色或拥有全部权限时才能变更
+                let existing = users.roles(&form.user).await?;
+                let config = &state.config;
+                let allowed = |role: &String| {
+                    let granted = config.permissions.granted(role);
+                    identity.can(config, "*")
+                        || ((!granted.is_empty() || identity.session.roles.contains(role))
+                            && granted.iter().all(|p| identity.can(config, p)))
+                };
+                if let Some(role) = roles
+                    .iter()
+                    .filter(|role| !existing.contains(role))
+                    .chain(existing.iter().filter(|role| !roles.contains(role)))
+                    .find(|role| !allowed(role))
+                {
+                    let reason = format!("not allowed to change role {}", role);
+                    return send_error(stream, "403 Forbidden", &reason).await;
+                }
+                users.set_roles(&form.user, &roles).await?;
+                security_event(
+                    "roles_changed",
+                    &identity.key,
+                    &identity.session,
+                    json!({"target": form.user, "roles": roles}),
+                );
+                roles
+            }
+        };
+        let body = json!({"ok": true, "user": form.user, "roles": roles});
+        send_json(stream, "200 OK", &[], &body).await
+    }
+
+    // 流式接收 multipart/form-data 中的文件，保存到上传目录
+    pub async fn upload(
+        stream: &mut Conn,
+        state: Arc<AppState>,
+        ctx: &Context,
+        req: &Request<'_, '_>,
+        prefix: BytesMut,
+    ) -> Result<(), Box<SyncError>> {
+        if req.method != Some("POST") {
+            return Self::f_404(stream, req).await;
+        }
+        // 不依赖访问规则，上传始终需要登录
+        let Some(identity) = &ctx.identity else {
+            return unauthorized(stream, req, None).await;
+        };
+        if !identity.check_csrf(req) {
+            return Self::failed(stream, req, "403 Forbidden", "invalid csrf token").await;
+        }
+        let Some(boundary) = header(req, "Content-Type").and_then(multipart::boundary) else {
+            return Self::failed(
+                stream,
+                req,
+                "415 Unsupported Media Type",
+                "expected multipart/form-data",
+            )
+            .await;
+        };
+        let config = &state.config;
+        // 单个文件的上限加上表单字段与分隔符的余量
+        let length = content_length(req).unwrap_or_default();
+        if length > config.max_upload_size + UPLOAD_OVERHEAD {
+            return Self::failed(stream, req, "413 Payload Too Large", "upload too large").await;
+        }
+        tokio::fs::create_dir_all(&config.upload_dir).await?;
+
+        let mut saved = Vec::new();
+        let result = {
+            let mut form = Multipart::new(stream, prefix, length, &boundary);
+            loop {
+                let part = match form.next_part().await {
+                    Ok(Some(part)) => part,
+                    Ok(None) => break Ok(()),
+                    Err(e) => break Err(e),
+                };
+                let result = match part.safe_filename() {
+                    Some(name) => form
+                        .save(&config.upload_dir, &name, config.max_upload_size)
+                        .await
+                        .map(|file| saved.push(file)),
+                    // 普通表单字段不需要保存
+                    None => form.bytes(UPLOAD_OVERHEAD).await.map(|_| ()),
+                };
+                if let Err(e) = result {
+                    break Err(e);
+                }
+            }
+        };
+        if let Err(e) = result {
+            // 已保存的文件属于失败的请求，一并删除
+            for (path, _) in &saved {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            return Self::failed(stream, req, e.status(), &e.to_string()).await;
+        }
+
+        let mut body = String::new();
+        for (path, size) in &saved {
+            println!("----> upload:{} ({} bytes)", path.display(), size);
+            body.push_str(&public_url(path, Path::new(STATIC_ROOT)).await);
+            body.push('\n');
+        }
+        let response = format!(
+            "HTTP/1.1 201 Created\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    // 返回失败原因，按客户端的偏好使用 JSON 或纯文本
+    async fn failed(
+        stream: &mut Conn,
+        req: &Request<'_, '_>,
+        status: &str,
+        reason: &str,
+    ) -> Result<(), Box<SyncError>> {
+        if wants_json(req) {
+            return send_error(stream, status, reason).await;
+        }
+        let notice = format!("failed: {}", reason);
+        let response = format!(
+            "HTTP/1.1 {}\r\n\
+            Content-Type: text/plain; charset=utf-8\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{}",
+            status,
+            notice.len(),
+            notice
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    pub async fn file(
+        stream: &mut Conn,
+        state: &AppState,
+        req: &Request<'_, '_>,
+    ) -> Result<(), Box<SyncError>> {
         let http_path = req.path.unwrap_or_default();
         // 过滤掉可能越权访问上级目录的情况
         let decoded_path = percent_encoding::percent_decode(http_path.as_bytes())
@@ -136,13 +771,22 @@ impl Handler {
         if !decoded_path.starts_with('/') || decoded_path.contains("..") {
             return Self::f_404(stream, req).await;
         }
-        let file_path = format!("static{}", decoded_path);
+        let file_path = format!("{}{}", STATIC_ROOT, decoded_path);
         let file_path = std::path::Path::new(&file_path);
         println!("----> file:{}", file_path.display());
         if !file_path.exists() {
             return Self::f_404(stream, req).await;
         }
-        let file_type = guess_file_mime(file_path);
+        // 用户上传的文件一律作为附件下载，不按扩展名渲染，避免存储型 XSS
+        let uploaded = is_upload(file_path, &state.config.upload_dir).await;
+        let file_type = match uploaded {
+            true => "application/octet-stream",
+            false => guess_file_mime(file_path),
+        };
+        let extra = match uploaded {
+            true => "Content-Disposition: attachment\r\nX-Content-Type-Options: nosniff\r\n",
+            false => "",
+        };
 
         let mut file = File::open(file_path).await?;
         let file_len = file.metadata().await?.len();
@@ -150,20 +794,18 @@ impl Handler {
             "HTTP/1.1 200 OK\r\n\
             Content-Length: {}\r\n\
             Content-Type: {}\r\n\
+            {}\
             Connection: close\r\n\r\n",
-            file_len, file_type
+            file_len, file_type, extra
         );
-        stream.write(header.as_bytes()).await?;
+        stream.write_all(header.as_bytes()).await?;
         tokio::io::copy(&mut file, stream).await?;
         stream.flush().await?;
         stream.shutdown().await?;
         Ok(())
     }
 
-    pub async fn f_404(
-        stream: &mut TcpStream,
-        req: &Request<'_, '_>,
-    ) -> Result<(), Box<SyncError>> {
+    pub async fn f_404(stream: &mut Conn, req: &Request<'_, '_>) -> Result<(), Box<SyncError>> {
         let body = format!("Not Found Path: {}", req.path.unwrap_or_default());
         let response = format!(
             "HTTP/1.1 404 NOT FOUND\r\n\
@@ -225,12 +867,226 @@ fn guess_file_mime(file_path: &std::path::Path) -> &str {
     }
 }
 
+// 请求操作的目标用户：默认是自己，拥有 permission 权限时可以指定其他用户
+fn target_user(
+    state: &AppState,
+    identity: &Identity,
+    requested: Option<String>,
+    permission: &str,
+) -> Option<String> {
+    let user = &identity.session.user;
+    match requested {
+        None => Some(user.clone()),
+        Some(target) if &target == user || identity.can(&state.config, permission) => Some(target),
+        Some(_) => None,
+    }
+}
+
+// 按 Content-Type 解析 JSON 或 urlencoded 请求体，失败时返回状态与原因
+fn extract_body<T: FromForm + DeserializeOwned>(
+    req: &Request<'_, '_>,
+    body: &[u8],
+) -> Result<T, (&'static str, String)> {
+    if is_json(req) {
+        return Json::<T>::extract(req, body)
+            .map(|Json(value)| value)
+            .map_err(|e| (e.status(), e.to_string()));
+    }
+    Form::<T>::extract(req, body)
+        .map(|Form(value)| value)
+        .map_err(|e| (e.status(), e.to_string()))
+}
+
+// 上传请求中文件以外的部分（表单字段、分隔符）允许占用的字节数
+const UPLOAD_OVERHEAD: usize = 64 * 1024;
+
+// 文件路由的根目录
+const STATIC_ROOT: &str = "static";
+
+async fn is_upload(path: &Path, upload_dir: &Path) -> bool {
+    match (
+        tokio::fs::canonicalize(path).await,
+        tokio::fs::canonicalize(upload_dir).await,
+    ) {
+        (Ok(path), Ok(dir)) => path.starts_with(dir),
+        _ => false,
+    }
+}
+
+// 上传目录位于 static 下时返回可通过文件路由访问的路径，否则只返回文件名；
+// 两者都按实际路径比较，./static 与绝对路径同样有效
+async fn public_url(path: &Path, root: &Path) -> String {
+    if let (Ok(path), Ok(root)) = (
+        tokio::fs::canonicalize(path).await,
+        tokio::fs::canonicalize(root).await,
+    ) && let Ok(rel) = path.strip_prefix(root)
+    {
+        let rel = rel.iter().map(OsStr::to_string_lossy).collect::<Vec<_>>();
+        return format!("/{}", rel.join("/"));
+    }
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    user: String,
+    password: String,
+    #[serde(default)]
+    next: Option<String>,
+}
+
+impl FromForm for LoginForm {
+    fn from_form(form: &FormData) -> Result<Self, FormError> {
+        Ok(LoginForm {
+            user: form.require("user")?.to_string(),
+            password: form.require("password")?.to_string(),
+            next: form.get("next").map(String::from),
+        })
+    }
+}
+
+// 只允许跳转到本站的路径，拒绝 //host 与 /\host 这类会被浏览器当作其他站点的地址
+fn is_local_path(path: &str) -> bool {
+    path.starts_with('/')
+        && !path.starts_with("//")
+        && !path.contains('\\')
+        && !path.chars().any(char::is_control)
+}
+
+#[derive(Deserialize)]
+struct RegisterForm {
+    user: String,
+    password: String,
+    #[serde(rename = "re-password")]
+    re_password: String,
+}
+
+impl FromForm for RegisterForm {
+    fn from_form(form: &FormData) -> Result<Self, FormError> {
+        Ok(RegisterForm {
+            user: form.require("user")?.to_string(),
+            password: form.require("password")?.to_string(),
+            re_password: form.require("re-password")?.to_string(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct RevokeForm {
+    user: Option<String>,
+    id: Option<String>,
+}
+
+impl FromForm for RevokeForm {
+    fn from_form(form: &FormData) -> Result<Self, FormError> {
+        Ok(RevokeForm {
+            user: form.get("user").map(String::from),
+            id: form.get("id").map(String::from),
+        })
+    }
+}
+
+// 令牌的公开信息，不含哈希
+fn token_info(token: &ApiToken) -> Value {
+    json!({
+        "id": token.id,
+        "name": token.name,
+        "scopes": token.scopes,
+        "created_at": token.created_at,
+        "last_used": token.last_used,
+        "expires_at": token.expires_at,
+    })
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    name: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+    // 有效期（秒），不提供时长期有效
+    expires_in: Option<u64>,
+}
+
+impl FromForm for TokenForm {
+    fn from_form(form: &FormData) -> Result<Self, FormError> {
+        let expires_in = match form.get("expires_in") {
+            Some(secs) => Some(
+                secs.parse()
+                    .map_err(|_| FormError::InvalidField("expires_in"))?,
+            ),
+            None => None,
+        };
+        Ok(TokenForm {
+            name: form.require("name")?.to_string(),
+            scopes: form.get_all("scope").map(String::from).collect(),
+            expires_in,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct RevokeTokenForm {
+    id: String,
+}
+
+impl FromForm for RevokeTokenForm {
+    fn from_form(form: &FormData) -> Result<Self, FormError> {
+        Ok(RevokeTokenForm {
+            id: form.require("id")?.to_string(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct RolesForm {
+    user: String,
+    // 仅设置角色时提供，表单中以多个 role 字段给出
+    roles: Option<Vec<String>>,
+}
+
+impl FromForm for RolesForm {
+    fn from_form(form: &FormData) -> Result<Self, FormError> {
+        // 没有 role 字段时视为缺少角色；清空角色需要提交一个空的 role 字段
+        let roles: Vec<_> = form.get_all("role").collect();
+        Ok(RolesForm {
+            user: form.require("user")?.to_string(),
+            roles: (!roles.is_empty()).then(|| {
+                roles
+                    .into_iter()
+                    .filter(|role| !role.is_empty())
+                    .map(String::from)
+                    .collect()
+            }),
+        })
+    }
+}
+
 fn gen_uuid() -> String {
     uuid::Uuid::new_v4().to_string()
 }
 
 #[cfg(test)]
 mod test {
-    #[test]
-    fn f() {}
+    use super::*;
+
+    #[tokio::test]
+    async fn upload_urls() {
+        let dir = format!("./static/uploads-{}", uuid::Uuid::new_v4().simple());
+        let file = Path::new(&dir).join("a.txt");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&file, "a").unwrap();
+        let name = dir.trim_start_matches("./static/");
+        let root = Path::new(STATIC_ROOT);
+        assert_eq!(public_url(&file, root).await, format!("/{}/a.txt", name));
+        let absolute = std::fs::canonicalize(&file).unwrap();
+        assert_eq!(
+            public_url(&absolute, root).await,
+            format!("/{}/a.txt", name)
+        );
+        assert_eq!(public_url(&file, Path::new("src")).await, "a.txt");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use httparse::Request;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    router::header,
+    server::{SyncError, conn::Conn},
+};
+
+// JSON 请求体的默认大小上限
+const MAX_JSON_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum JsonError {
+    UnsupportedMediaType,
+    TooLarge,
+    Invalid(serde_json::Error),
+}
+
+impl JsonError {
+    pub fn status(&self) -> &'static str {
+        match self {
+            JsonError::UnsupportedMediaType => "415 Unsupported Media Type",
+            JsonError::TooLarge => "413 Payload Too Large",
+            JsonError::Invalid(_) => "400 Bad Request",
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::UnsupportedMediaType => write!(f, "expected application/json"),
+            JsonError::TooLarge => write!(f, "json body too large"),
+            JsonError::Invalid(e) => write!(f, "invalid json body: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+// application/json 以及 application/problem+json 等 +json 类型
+pub fn is_json(req: &Request<'_, '_>) -> bool {
+    header(req, "Content-Type")
+        .and_then(|ct| ct.split(';').next())
+        .map(|mime| mime.trim().to_ascii_lowercase())
+        .is_some_and(|mime| mime == "application/json" || mime.ends_with("+json"))
+}
+
+// 客户端以 JSON 提交，或在 Accept 中要求 JSON
+pub fn wants_json(req: &Request<'_, '_>) -> bool {
+    is_json(req)
+        || header(req, "Accept").is_some_and(|accept| {
+            accept.split(',').any(|t| {
+                t.trim()
+                    .to_ascii_lowercase()
+                    .starts_with("application/json")
+            })
+        })
+}
+
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> Json<T> {
+    pub fn extract(req: &Request<'_, '_>, body: &[u8]) -> Result<Self, JsonError> {
+        Self::extract_with(req, body, MAX_JSON_SIZE)
+    }
+
+    pub fn extract_with(
+        req: &Request<'_, '_>,
+        body: &[u8],
+        max_bytes: usize,
+    ) -> Result<Self, JsonError> {
+        if !is_json(req) {
+            return Err(JsonError::UnsupportedMediaType);
+        }
+        if body.len() > max_bytes {
+            return Err(JsonError::TooLarge);
+        }
+        serde_json::from_slice(body)
+            .map(Json)
+            .map_err(JsonError::Invalid)
+    }
+}
+
+// 以 JSON 回复并关闭连接，extra_headers 为完整的头部行，如 Set-Cookie
+pub async fn send_json<T: Serialize>(
+    stream: &mut Conn,
+    status: &str,
+    extra_headers: &[String],
+    value: &T,
+) -> Result<(), Box<SyncError>> {
+    let body = serde_json::to_string(value)?;
+    let mut response = format!(
+        "HTTP/1.1 {}\r\n\
+        Content-Type: application/json\r\n\
+        Cache-Control: no-store\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n",
+        status,
+        body.len()
+    );
+    for header in extra_headers {
+        response.push_str(header);
+        response.push_str("\r\n");
+    }
+    response.push_str("\r\n");
+    response.push_str(&body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// 统一的错误格式 {"ok": false, "error": ...}
+pub async fn send_error(
+    stream: &mut Conn,
+    status: &str,
+    reason: &str,
+) -> Result<(), Box<SyncError>> {
+    send_json(stream, status, &[], &json!({"ok": false, "error": reason})).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Login {
+        user: String,
+    }
+
+    fn request<'h, 'b>(headers: &'h mut [httparse::Header<'b>]) -> Request<'h, 'b> {
+        Request::new(headers)
+    }
+
+    #[test]
+    fn extract() {
+        let mut headers = [httparse::Header {
+            name: "Content-Type",
+            value: b"application/json; charset=utf-8",
+        }];
+        let req = request(&mut headers);
+        let Json(login) = Json::<Login>::extract(&req, br#"{"user":"bob","extra":1}"#).unwrap();
+        assert_eq!(login.user, "bob");
+        assert!(matches!(
+            Json::<Login>::extract(&req, br#"{"name":"bob"}"#),
+            Err(JsonError::Invalid(_))
+        ));
+        assert!(matches!(
+            Json::<Login>::extract_with(&req, br#"{"user":"bob"}"#, 8),
+            Err(JsonError::TooLarge)
+        ));
+
+        let mut headers = [httparse::Header {
+            name: "Content-Type",
+            value: b"text/plain",
+        }];
+        let req = request(&mut headers);
+        assert!(matches!(
+            Json::<Login>::extract(&req, br#"{"user":"bob"}"#),
+            Err(JsonError::UnsupportedMediaType)
+        ));
+    }
+
+    #[test]
+    fn negotiation() {
+        let mut headers = [httparse::Header {
+            name: "accept",
+            value: b"text/html;q=0.9, Application/JSON",
+        }];
+        let req = request(&mut headers);
+        assert!(!is_json(&req));
+        assert!(wants_json(&req));
+        let mut headers = [httparse::Header {
+            name: "Content-Type",
+            value: b"application/merge-patch+json",
+        }];
+        assert!(is_json(&request(&mut headers)));
+    }
+}
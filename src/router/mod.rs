@@ -1,27 +1,76 @@
+pub mod form;
 mod handler;
+pub mod json;
+pub mod multipart;
 use std::sync::Arc;
 
 use handler::Handler;
 mod prelude;
-use crate::{protocol::Redis, server::SyncError};
+use crate::{
+    middleware::auth::Identity,
+    server::{AppState, SyncError, conn::Conn},
+};
 use bytes::BytesMut;
 use httparse::Request;
-use tokio::net::TcpStream;
+
+// 中间件附加到请求上的信息
+#[derive(Default)]
+pub struct Context {
+    pub identity: Option<Identity>,
+}
 
 pub async fn route(
-    stream: &mut TcpStream,
-    db: Arc<Redis>,
+    stream: &mut Conn,
+    state: Arc<AppState>,
+    ctx: &Context,
     req_headers: &Request<'_, '_>,
     body: BytesMut,
 ) -> Result<(), Box<SyncError>> {
-    match &req_headers.path {
+    // 查询参数由处理函数自行解析
+    let path = req_headers
+        .path
+        .map(|p| p.split_once('?').map_or(p, |(path, _)| path));
+    match path {
         Some("/") => Handler::f1(stream, req_headers).await?,
         Some("/method") => Handler::echo_method(stream, req_headers).await?,
         Some("/ip") => Handler::echo_ip(stream, req_headers).await?,
         Some("/404") => Handler::f_404(stream, req_headers).await?,
-        Some("/srs/login") => Handler::login(stream, db, req_headers, body).await?,
-        _ => Handler::file(stream, req_headers).await?,
+        Some("/srs/login") => Handler::login(stream, state, req_headers, body).await?,
+        Some("/srs/register") => Handler::register(stream, state, req_headers, body).await?,
+        Some("/srs/logout") => Handler::logout(stream, state, ctx, req_headers).await?,
+        Some("/srs/session") => Handler::current_session(stream, ctx, req_headers).await?,
+        Some("/srs/sessions") => Handler::sessions(stream, state, ctx, req_headers).await?,
+        Some("/srs/sessions/revoke") => {
+            Handler::revoke_sessions(stream, state, ctx, req_headers, body).await?
+        }
+        Some("/srs/tokens") => Handler::tokens(stream, state, ctx, req_headers, body).await?,
+        Some("/srs/tokens/revoke") => {
+            Handler::revoke_token(stream, state, ctx, req_headers, body).await?
+        }
+        Some("/srs/admin/roles") => Handler::roles(stream, state, ctx, req_headers, body).await?,
+        Some("/upload") => Handler::upload(stream, state, ctx, req_headers, body).await?,
+        _ => Handler::file(stream, &state, req_headers).await?,
     };
 
     Ok(())
 }
+
+// 这些路由的请求体由处理函数从连接中流式读取，route 收到的只是已读到的部分
+pub fn is_streaming(req: &Request<'_, '_>) -> bool {
+    req.path == Some("/upload")
+}
+
+pub fn header<'a>(req: &Request<'_, 'a>, name: &str) -> Option<&'a str> {
+    req.headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .and_then(|h| str::from_utf8(h.value).ok())
+}
+
+// 没有请求体时为 0，取值非法时为 None
+pub fn content_length(req: &Request<'_, '_>) -> Option<usize> {
+    match header(req, "Content-Length") {
+        Some(value) => value.trim().parse().ok(),
+        None => Some(0),
+    }
+}
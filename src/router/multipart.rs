@@ -0,0 +1,402 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+};
+
+// 单个部分的请求头与部分数量的上限
+const MAX_PART_HEADER_SIZE: usize = 8 * 1024;
+const MAX_PARTS: usize = 64;
+
+#[derive(Debug)]
+pub enum MultipartError {
+    // 请求体在结束分隔符之前就结束了
+    Incomplete,
+    InvalidHeaders,
+    TooManyParts,
+    TooLarge,
+    Io(io::Error),
+}
+
+impl MultipartError {
+    pub fn status(&self) -> &'static str {
+        match self {
+            MultipartError::TooLarge => "413 Payload Too Large",
+            MultipartError::Io(_) => "500 Internal Server Error",
+            _ => "400 Bad Request",
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Incomplete => write!(f, "multipart body ended unexpectedly"),
+            MultipartError::InvalidHeaders => write!(f, "invalid multipart part headers"),
+            MultipartError::TooManyParts => write!(f, "too many multipart parts"),
+            MultipartError::TooLarge => write!(f, "multipart part too large"),
+            MultipartError::Io(e) => write!(f, "multipart io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {}
+
+impl From<io::Error> for MultipartError {
+    fn from(e: io::Error) -> Self {
+        MultipartError::Io(e)
+    }
+}
+
+// 从 Content-Type 中取出 boundary 参数
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, v)| v.trim().trim_matches('"').to_string())
+        .filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub name: String,
+    // 客户端提供的原始文件名，写入磁盘前需经过 safe_filename
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl Part {
+    pub fn safe_filename(&self) -> Option<String> {
+        let name = sanitize_filename::sanitize(self.filename.as_deref()?);
+        (!name.is_empty() && name != "." && name != "..").then_some(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    // 第一个分隔符之前
+    Preamble,
+    // 刚读过分隔符，接下来是 CRLF（下一部分）或 `--`（结束）
+    Delimiter,
+    Body,
+    Done,
+}
+
+// 流式解析 multipart/form-data：按需从连接读取，不会把整个请求体读入内存
+pub struct Multipart<'a, R> {
+    reader: &'a mut R,
+    buf: BytesMut,
+    // "\r\n--" + boundary
+    delimiter: Vec<u8>,
+    // 请求体中尚未从连接读取的字节数
+    remaining: usize,
+    state: State,
+    parts: usize,
+}
+
+impl<'a, R: AsyncRead + Unpin> Multipart<'a, R> {
+    // prefix 是读取请求头时已经收到的请求体部分，content_length 为整个请求体长度
+    pub fn new(reader: &'a mut R, prefix: BytesMut, content_length: usize, boundary: &str) -> Self {
+        // 在开头补上 CRLF，使第一个分隔符与其余分隔符形式一致
+        let mut buf = BytesMut::from(&b"\r\n"[..]);
+        let remaining = content_length.saturating_sub(prefix.len());
+        buf.extend_from_slice(&prefix[..prefix.len().min(content_length)]);
+        Multipart {
+            reader,
+            buf,
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            remaining,
+            state: State::Preamble,
+            parts: 0,
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), MultipartError> {
+        if self.remaining == 0 {
+            return Err(MultipartError::Incomplete);
+        }
+        let mut limited = (&mut *self.reader).take(self.remaining as u64);
+        let n = limited.read_buf(&mut self.buf).await?;
+        if n == 0 {
+            return Err(MultipartError::Incomplete);
+        }
+        self.remaining -= n;
+        Ok(())
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        self.buf.windows(needle.len()).position(|w| w == needle)
+    }
+
+    // 跳到下一部分并解析其头部，没有更多部分时返回 None
+    pub async fn next_part(&mut self) -> Result<Option<Part>, MultipartError> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Body => while self.chunk().await?.is_some() {},
+                State::Preamble => match self.find(&self.delimiter) {
+                    Some(i) => {
+                        let _ = self.buf.split_to(i + self.delimiter.len());
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        // 保留可能是分隔符开头的尾部
+                        let keep = self.delimiter.len() - 1;
+                        if self.buf.len() > keep {
+                            let _ = self.buf.split_to(self.buf.len() - keep);
+                        }
+                        self.fill().await?;
+                    }
+                },
+                State::Delimiter => {
+                    while self.buf.len() < 2 {
+                        self.fill().await?;
+                    }
+                    if self.buf.starts_with(b"--") {
+                        self.state = State::Done;
+                        return Ok(None);
+                    }
+                    if !self.buf.starts_with(b"\r\n") {
+                        return Err(MultipartError::InvalidHeaders);
+                    }
+                    if self.parts == MAX_PARTS {
+                        return Err(MultipartError::TooManyParts);
+                    }
+                    return self.read_headers().await.map(Some);
+                }
+            }
+        }
+    }
+
+    async fn read_headers(&mut self) -> Result<Part, MultipartError> {
+        // buf 以分隔符后的 CRLF 开头，头部为空时紧跟着另一个 CRLF
+        let end = loop {
+            if self.buf.starts_with(b"\r\n\r\n") {
+                break 4;
+            }
+            if let Some(i) = self.find(b"\r\n\r\n") {
+                break i + 4;
+            }
+            if self.buf.len() > MAX_PART_HEADER_SIZE {
+                return Err(MultipartError::InvalidHeaders);
+            }
+            self.fill().await?;
+        };
+        let raw = self.buf.split_to(end);
+        let mut headers = [httparse::EMPTY_HEADER; 16];
+        let parsed = httparse::parse_headers(&raw[2..], &mut headers)
+            .map_err(|_| MultipartError::InvalidHeaders)?;
+        let httparse::Status::Complete((_, headers)) = parsed else {
+            return Err(MultipartError::InvalidHeaders);
+        };
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .and_then(|h| str::from_utf8(h.value).ok())
+        };
+        let disposition = header("Content-Disposition").ok_or(MultipartError::InvalidHeaders)?;
+        let mut name = None;
+        let mut filename = None;
+        for param in disposition.split(';').skip(1) {
+            let Some((key, value)) = param.split_once('=') else {
+                continue;
+            };
+            let value = value.trim().trim_matches('"').to_string();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "name" => name = Some(value),
+                "filename" => filename = Some(value),
+                _ => {}
+            }
+        }
+        self.parts += 1;
+        self.state = State::Body;
+        Ok(Part {
+            name: name.ok_or(MultipartError::InvalidHeaders)?,
+            filename,
+            content_type: header("Content-Type").map(str::to_string),
+        })
+    }
+
+    // 读取当前部分的下一段数据，当前部分结束时返回 None
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        if self.state != State::Body {
+            return Ok(None);
+        }
+        loop {
+            match self.find(&self.delimiter) {
+                Some(0) => {
+                    let _ = self.buf.split_to(self.delimiter.len());
+                    self.state = State::Delimiter;
+                    return Ok(None);
+                }
+                Some(i) => return Ok(Some(self.buf.split_to(i).freeze())),
+                None => {
+                    // 末尾可能是被截断的分隔符，先不返回
+                    let safe = self.buf.len().saturating_sub(self.delimiter.len() - 1);
+                    if safe > 0 {
+                        return Ok(Some(self.buf.split_to(safe).freeze()));
+                    }
+                    self.fill().await?;
+                }
+            }
+        }
+    }
+
+    // 把当前部分读入内存
+    pub async fn bytes(&mut self, limit: usize) -> Result<BytesMut, MultipartError> {
+        let mut data = BytesMut::new();
+        while let Some(chunk) = self.chunk().await? {
+            if data.len() + chunk.len() > limit {
+                return Err(MultipartError::TooLarge);
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    // 把当前部分保存到 dir 下，同名文件已存在时加上随机前缀，不会覆盖已有文件；
+    // 超过大小上限时删除已写入的内容。返回实际保存的路径与大小
+    pub async fn save(
+        &mut self,
+        dir: &Path,
+        name: &str,
+        limit: usize,
+    ) -> Result<(PathBuf, usize), MultipartError> {
+        // 临时文件名随机且独占创建，并发上传互不干扰，也无法被猜到
+        let tmp = dir.join(format!(".{}.part", uuid::Uuid::new_v4().simple()));
+        let result = match self.write_file(&tmp, limit).await {
+            Ok(size) => claim(&tmp, dir, name).await.map(|path| (path, size)),
+            Err(e) => Err(e),
+        };
+        let _ = fs::remove_file(&tmp).await;
+        result
+    }
+
+    async fn write_file(&mut self, path: &Path, limit: usize) -> Result<usize, MultipartError> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await?;
+        let mut size = 0;
+        while let Some(chunk) = self.chunk().await? {
+            size += chunk.len();
+            if size > limit {
+                return Err(MultipartError::TooLarge);
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(size)
+    }
+}
+
+// 以硬链接占用最终文件名，目标已存在时失败而不是覆盖，换一个名字重试
+async fn claim(tmp: &Path, dir: &Path, name: &str) -> Result<PathBuf, MultipartError> {
+    let mut path = dir.join(name);
+    loop {
+        match fs::hard_link(tmp, &path).await {
+            Ok(()) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let prefix = uuid::Uuid::new_v4().simple().to_string();
+                path = dir.join(format!("{}-{}", &prefix[..8], name));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    const BODY: &str = "preamble\r\n--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        hello\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"../../etc/pa:ss.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        line1\r\n--Xy not a boundary\r\n\
+        --XyZ--\r\nepilogue";
+
+    async fn collect(body: &[u8], split: usize) -> Vec<(Part, Vec<u8>)> {
+        let (prefix, rest) = body.split_at(split);
+        let mut reader = rest;
+        let mut multipart = Multipart::new(&mut reader, BytesMut::from(prefix), body.len(), "XyZ");
+        let mut parts = Vec::new();
+        while let Some(part) = multipart.next_part().await.unwrap() {
+            let data = multipart.bytes(1024).await.unwrap();
+            parts.push((part, data.to_vec()));
+        }
+        parts
+    }
+
+    #[test]
+    fn parse_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data;charset=utf-8;BOUNDARY=x").as_deref(),
+            Some("x")
+        );
+        assert_eq!(boundary("text/plain; boundary=x"), None);
+        assert_eq!(boundary("multipart/form-data"), None);
+    }
+
+    #[tokio::test]
+    async fn parse_parts() {
+        let parts = collect(BODY.as_bytes(), 0).await;
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].0.name, "title");
+        assert_eq!(parts[0].1, b"hello");
+        assert_eq!(parts[1].0.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(
+            parts[1].0.safe_filename().as_deref(),
+            Some("....etcpass.txt")
+        );
+        assert_eq!(parts[1].1, b"line1\r\n--Xy not a boundary");
+    }
+
+    #[tokio::test]
+    async fn limits_and_truncation() {
+        let mut reader = &BODY.as_bytes()[..70];
+        let mut multipart = Multipart::new(&mut reader, BytesMut::new(), BODY.len(), "XyZ");
+        multipart.next_part().await.unwrap();
+        assert!(matches!(
+            multipart.bytes(1024).await,
+            Err(MultipartError::Incomplete)
+        ));
+
+        let mut reader = BODY.as_bytes();
+        let mut multipart = Multipart::new(&mut reader, BytesMut::new(), BODY.len(), "XyZ");
+        multipart.next_part().await.unwrap();
+        assert!(matches!(
+            multipart.bytes(4).await,
+            Err(MultipartError::TooLarge)
+        ));
+    }
+
+    proptest! {
+        // 无论请求头之后已经读到多少字节，结果都相同
+        #[test]
+        fn any_prefix_split(split in 0..BODY.len()) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let parts = runtime.block_on(collect(BODY.as_bytes(), split));
+            prop_assert_eq!(parts, runtime.block_on(collect(BODY.as_bytes(), 0)));
+        }
+    }
+}
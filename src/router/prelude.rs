@@ -2,8 +2,7 @@ pub(super) use httparse::Request;
 pub(super) use tokio::{
     fs::File,
     io::AsyncWriteExt,
-    net::TcpStream,
 };
 
-pub(super) use crate::server::SyncError;
+pub(super) use crate::server::{SyncError, conn::Conn};
 
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use crate::server::SyncError;
+
+// 访问路径所需的权限
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    Public,
+    Authenticated,
+    // 已登录且会话拥有该角色
+    Role(String),
+    // 已登录且会话的某个角色拥有该权限
+    Permission(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AccessRule {
+    // None 表示任意方法
+    methods: Option<Vec<String>>,
+    // 按 `/` 拆分的路径模式，`*` 匹配段内任意字符，`**` 匹配任意多段
+    pattern: Vec<String>,
+    access: Access,
+}
+
+// 按顺序匹配，第一条命中的规则生效；没有命中时需要登录
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessRules {
+    rules: Vec<AccessRule>,
+}
+
+const DEFAULT_RULES: &str = "\
+    * / public
+    * /ip public
+    * /method public
+    * /404 public
+    * /srs/admin/** permission:roles.manage
+    * /srs/** public
+    * /** authenticated";
+
+impl Default for AccessRules {
+    fn default() -> Self {
+        Self::parse(DEFAULT_RULES).expect("default access rules")
+    }
+}
+
+impl AccessRules {
+    // 每行或以 `;` 分隔的一条规则：方法 路径模式 权限，如 "GET,HEAD /srs/** public"、
+    // "* /admin/** role:admin"、"POST /upload permission:upload"；方法为 `*` 时匹配任意方法，`#` 开头的行是注释
+    pub fn parse(text: &str) -> Result<Self, Box<SyncError>> {
+        let mut rules = Vec::new();
+        for line in text.split(['\n', ';']).map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("Err: Invalid access rule {}", line);
+            let [methods, pattern, access] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(invalid().into());
+            };
+            let methods = match methods {
+                "*" => None,
+                methods => Some(methods.split(',').map(str::to_ascii_uppercase).collect()),
+            };
+            let Some(pattern) = pattern.strip_prefix('/') else {
+                return Err(invalid().into());
+            };
+            let access = match access {
+                "public" => Access::Public,
+                "authenticated" => Access::Authenticated,
+                access => match access.split_once(':') {
+                    Some(("role", role)) if !role.is_empty() => Access::Role(role.to_string()),
+                    Some(("permission", permission)) if is_permission(permission) => {
+                        Access::Permission(permission.to_string())
+                    }
+                    _ => return Err(invalid().into()),
+                },
+            };
+            rules.push(AccessRule {
+                methods,
+                pattern: pattern.split('/').map(String::from).collect(),
+                access,
+            });
+        }
+        Ok(AccessRules { rules })
+    }
+
+    // path 不含查询参数，匹配前先做百分号解码，路径大小写不敏感
+    pub fn access(&self, method: &str, path: &str) -> &Access {
+        let Some(path) = percent_encoding::percent_decode_str(path)
+            .decode_utf8()
+            .ok()
+            .filter(|path| path.starts_with('/'))
+        else {
+            return &Access::Authenticated;
+        };
+        let segments: Vec<&str> = path[1..].split('/').collect();
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.methods
+                    .as_ref()
+                    .is_none_or(|methods| methods.iter().any(|m| m == method))
+                    && matches(&rule.pattern, &segments)
+            })
+            .map_or(&Access::Authenticated, |rule| &rule.access)
+    }
+}
+
+// 角色到权限的映射，`*` 表示全部权限；admin 角色始终拥有全部权限
+#[derive(Debug, Clone, PartialEq)]
+pub struct RolePermissions {
+    roles: HashMap<String, Vec<String>>,
+}
+
+impl Default for RolePermissions {
+    fn default() -> Self {
+        let mut roles = HashMap::new();
+        roles.insert("admin".to_string(), vec!["*".to_string()]);
+        RolePermissions { roles }
+    }
+}
+
+impl RolePermissions {
+    // 以 `;` 分隔的 角色=权限,权限，如 "editor=upload,files.write;auditor=sessions.manage"
+    pub fn parse(text: &str) -> Result<Self, Box<SyncError>> {
+        let mut permissions = Self::default();
+        for entry in text.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let invalid = || format!("Err: Invalid role permissions {}", entry);
+            let Some((role, granted)) = entry.split_once('=') else {
+                return Err(invalid().into());
+            };
+            let role = role.trim();
+            let granted: Vec<String> = granted
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(String::from)
+                .collect();
+            if role.is_empty() || role == "admin" || !granted.iter().all(|p| is_permission(p)) {
+                return Err(invalid().into());
+            }
+            permissions.roles.insert(role.to_string(), granted);
+        }
+        Ok(permissions)
+    }
+
+    // 角色被授予的权限，未配置的角色没有任何权限
+    pub fn granted(&self, role: &str) -> &[String] {
+        self.roles.get(role).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn allows(&self, roles: &[String], permission: &str) -> bool {
+        roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .flatten()
+            .any(|granted| granted == "*" || granted == permission)
+    }
+}
+
+// 权限名与令牌范围：小写字母、数字、`.`、`_`、`-`，或表示全部权限的 `*`
+pub fn is_permission(permission: &str) -> bool {
+    permission == "*"
+        || !permission.is_empty()
+            && permission.chars().all(|c| {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '.' | '_' | '-')
+            })
+}
+
+fn matches(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => (0..=path.len())
+            .take_while(|&i| i == 0 || !is_parent(path[i - 1]))
+            .any(|i| matches(rest, &path[i..])),
+        Some((first, rest)) => path
+            .split_first()
+            .is_some_and(|(segment, tail)| glob(first, segment) && matches(rest, tail)),
+    }
+}
+
+// 上级目录不能被通配符匹配，避免 /srs/../secret 命中 /srs/** 的规则
+fn is_parent(segment: &str) -> bool {
+    segment == ".."
+}
+
+fn glob(pattern: &str, segment: &str) -> bool {
+    if is_parent(segment) {
+        return pattern == segment;
+    }
+    match pattern.split_once('*') {
+        None => pattern.eq_ignore_ascii_case(segment),
+        Some((prefix, rest)) => {
+            let Some(head) = segment.get(..prefix.len()) else {
+                return false;
+            };
+            head.eq_ignore_ascii_case(prefix)
+                && (prefix.len()..=segment.len())
+                    .filter(|&i| segment.is_char_boundary(i))
+                    .any(|i| glob(rest, &segment[i..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_rules() {
+        let rules = AccessRules::default();
+        for path in [
+            "/",
+            "/ip",
+            "/SRS/LoginInterface.html",
+            "/srs",
+            "/srs/sessions",
+        ] {
+            assert_eq!(rules.access("GET", path), &Access::Public, "{}", path);
+        }
+        assert_eq!(
+            rules.access("POST", "/srs/admin/roles"),
+            &Access::Permission("roles.manage".into())
+        );
+        for path in [
+            "/1.txt",
+            "/upload",
+            "/srs/../1.txt",
+            "/srs/%2e%2e/1.txt",
+            "/srs/a/../../x",
+        ] {
+            assert_eq!(
+                rules.access("GET", path),
+                &Access::Authenticated,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn custom_rules() {
+        let rules = AccessRules::parse(
+            "# 管理接口\n\
+            * /admin/** role:admin; GET,head /docs/*.html public\n\
+            POST /docs/** authenticated",
+        )
+        .unwrap();
+        assert_eq!(
+            rules.access("GET", "/admin/users"),
+            &Access::Role("admin".into())
+        );
+        assert_eq!(rules.access("HEAD", "/docs/Index.HTML"), &Access::Public);
+        assert_eq!(
+            rules.access("GET", "/docs/a/index.html"),
+            &Access::Authenticated
+        );
+        assert_eq!(
+            rules.access("POST", "/docs/index.html"),
+            &Access::Authenticated
+        );
+        // 没有命中任何规则
+        assert_eq!(rules.access("GET", "/"), &Access::Authenticated);
+
+        assert!(AccessRules::parse("* /x").is_err());
+        assert!(AccessRules::parse("* x public").is_err());
+        assert!(AccessRules::parse("* /x role:").is_err());
+        assert!(AccessRules::parse("* /x anyone").is_err());
+        assert!(AccessRules::parse("* /x permission:Upload").is_err());
+    }
+
+    #[test]
+    fn permissions() {
+        let permissions = RolePermissions::parse("editor=upload, files.write; viewer=").unwrap();
+        let roles = |roles: &[&str]| roles.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert!(permissions.allows(&roles(&["user", "editor"]), "files.write"));
+        assert!(!permissions.allows(&roles(&["user", "viewer"]), "upload"));
+        assert!(permissions.allows(&roles(&["admin"]), "roles.manage"));
+        assert!(!permissions.allows(&roles(&["user"]), "roles.manage"));
+
+        assert!(RolePermissions::parse("admin=upload").is_err());
+        assert!(RolePermissions::parse("editor").is_err());
+        assert!(RolePermissions::parse("editor=Upload").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use sha2::{Digest, Sha256};
+
+// 校验通过的凭据的缓存时间，修改密码后旧密码最多还能使用这么久
+const VERIFIED_TTL: Duration = Duration::from_secs(30);
+// 同一 IP 在窗口内允许的失败次数，超过后直接拒绝，不再计算哈希
+const MAX_FAILURES: u32 = 5;
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+// 记录数超过此值时顺带清理过期的条目
+const PRUNE_THRESHOLD: usize = 1024;
+
+// Basic 认证的每个请求都带着密码：缓存成功的校验并限制失败的频率，避免每个请求都计算 Argon2
+pub struct BasicGuard {
+    // 缓存键加入进程内的随机值，内存中不保留可离线破解的密码摘要
+    secret: [u8; 16],
+    verified: Mutex<HashMap<[u8; 32], Instant>>,
+    failures: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl BasicGuard {
+    pub fn new() -> Self {
+        BasicGuard {
+            secret: *uuid::Uuid::new_v4().as_bytes(),
+            verified: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn key(&self, user: &str, password: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.secret);
+        hasher.update(user.as_bytes());
+        hasher.update([0]);
+        hasher.update(password.as_bytes());
+        hasher.finalize().into()
+    }
+
+    pub fn is_verified(&self, user: &str, password: &str) -> bool {
+        let key = self.key(user, password);
+        let verified = self.verified.lock().unwrap();
+        verified
+            .get(&key)
+            .is_some_and(|expires_at| *expires_at > Instant::now())
+    }
+
+    pub fn verified(&self, user: &str, password: &str) {
+        let key = self.key(user, password);
+        let now = Instant::now();
+        let mut verified = self.verified.lock().unwrap();
+        if verified.len() >= PRUNE_THRESHOLD {
+            verified.retain(|_, expires_at| *expires_at > now);
+        }
+        verified.insert(key, now + VERIFIED_TTL);
+    }
+
+    pub fn is_throttled(&self, ip: IpAddr) -> bool {
+        let failures = self.failures.lock().unwrap();
+        failures.get(&ip).is_some_and(|(count, since)| {
+            *count >= MAX_FAILURES && since.elapsed() < FAILURE_WINDOW
+        })
+    }
+
+    pub fn failed(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() >= PRUNE_THRESHOLD {
+            failures.retain(|_, (_, since)| now - *since < FAILURE_WINDOW);
+        }
+        let (count, since) = failures.entry(ip).or_insert((0, now));
+        if now - *since >= FAILURE_WINDOW {
+            (*count, *since) = (0, now);
+        }
+        *count += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cache_and_throttle() {
+        let guard = BasicGuard::new();
+        assert!(!guard.is_verified("alice", "secret"));
+        guard.verified("alice", "secret");
+        assert!(guard.is_verified("alice", "secret"));
+        assert!(!guard.is_verified("alice", "wrong"));
+        assert!(!guard.is_verified("alicesecret", ""));
+
+        let ip = "10.0.0.1".parse().unwrap();
+        for _ in 0..MAX_FAILURES {
+            assert!(!guard.is_throttled(ip));
+            guard.failed(ip);
+        }
+        assert!(guard.is_throttled(ip));
+        assert!(!guard.is_throttled("10.0.0.2".parse().unwrap()));
+    }
+}
@@ -0,0 +1,192 @@
+use std::net::IpAddr;
+
+use crate::{server::SyncError, store::Session};
+
+// 会话与登录时客户端的绑定方式，IP 与 User-Agent 可以组合
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BindingPolicy {
+    pub ip: IpBinding,
+    pub user_agent: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpBinding {
+    None,
+    Exact,
+    // 同一网段即可，前缀长度分别用于 IPv4 与 IPv6
+    Prefix { v4: u8, v6: u8 },
+}
+
+// 请求不满足绑定时的原因
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mismatch {
+    Ip,
+    UserAgent,
+}
+
+impl Mismatch {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mismatch::Ip => "ip",
+            Mismatch::UserAgent => "user_agent",
+        }
+    }
+}
+
+impl Default for BindingPolicy {
+    fn default() -> Self {
+        BindingPolicy {
+            ip: IpBinding::Exact,
+            user_agent: false,
+        }
+    }
+}
+
+impl BindingPolicy {
+    // none，或 ip、prefix、ua 以逗号组合，如 "prefix,ua"
+    pub fn parse(value: &str, v4: u8, v6: u8) -> Result<Self, Box<SyncError>> {
+        if v4 > 32 || v6 > 128 {
+            return Err(format!("Err: Invalid binding prefix /{} /{}", v4, v6).into());
+        }
+        let mut policy = BindingPolicy {
+            ip: IpBinding::None,
+            user_agent: false,
+        };
+        for item in value.split(',').map(str::trim) {
+            match item {
+                "none" if value.trim() == "none" => {}
+                "ip" | "prefix" if policy.ip != IpBinding::None => {
+                    return Err("Err: Only one of ip and prefix binding can be used".into());
+                }
+                "ip" => policy.ip = IpBinding::Exact,
+                "prefix" => policy.ip = IpBinding::Prefix { v4, v6 },
+                "ua" => policy.user_agent = true,
+                _ => return Err(format!("Err: Invalid session binding {}", value).into()),
+            }
+        }
+        Ok(policy)
+    }
+
+    // 检查请求是否来自会话绑定的客户端
+    pub fn check(&self, session: &Session, ip: IpAddr, user_agent: &str) -> Result<(), Mismatch> {
+        let ip_matches = match self.ip {
+            IpBinding::None => true,
+            IpBinding::Exact => canonical(&session.ip) == Some(ip.to_canonical()),
+            IpBinding::Prefix { v4, v6 } => session
+                .ip
+                .parse()
+                .is_ok_and(|bound| same_network(bound, ip, v4, v6)),
+        };
+        if !ip_matches {
+            return Err(Mismatch::Ip);
+        }
+        if self.user_agent && fingerprint(&session.user_agent) != fingerprint(user_agent) {
+            return Err(Mismatch::UserAgent);
+        }
+        Ok(())
+    }
+
+    // 请求的客户端在绑定的维度上与会话记录不同（但仍满足绑定），需要重新绑定；
+    // 未绑定的维度不做比较
+    pub fn changed(&self, session: &Session, ip: &str, user_agent: &str) -> bool {
+        (self.ip != IpBinding::None && !same_ip(&session.ip, ip))
+            || (self.user_agent && session.user_agent != user_agent)
+    }
+}
+
+// IPv4 映射的 IPv6 地址（双栈监听时的 IPv4 客户端）按 IPv4 处理
+fn canonical(ip: &str) -> Option<IpAddr> {
+    ip.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+}
+
+fn same_ip(a: &str, b: &str) -> bool {
+    a == b || canonical(a).is_some_and(|a| Some(a) == canonical(b))
+}
+
+fn same_network(a: IpAddr, b: IpAddr, v4: u8, v6: u8) -> bool {
+    // IPv4 映射的 IPv6 地址按 IPv4 比较
+    match (a.to_canonical(), b.to_canonical()) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => {
+            let mask = u32::MAX.checked_shl(32 - v4 as u32).unwrap_or(0);
+            u32::from(a) & mask == u32::from(b) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(b)) => {
+            let mask = u128::MAX.checked_shl(128 - v6 as u32).unwrap_or(0);
+            u128::from(a) & mask == u128::from(b) & mask
+        }
+        _ => false,
+    }
+}
+
+// 去掉版本号后的 User-Agent，浏览器自动更新不会导致会话失效
+fn fingerprint(user_agent: &str) -> String {
+    user_agent
+        .chars()
+        .filter(|c| !c.is_ascii_digit() && *c != '.' && *c != '_')
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CHROME: &str = "Mozilla/5.0 (X11; Linux x86_64) Chrome/120.0.6099.109 Safari/537.36";
+
+    fn check(policy: &str, ip: &str, user_agent: &str) -> Result<(), Mismatch> {
+        let session = Session::new("alice", Vec::new(), "10.1.2.3", CHROME);
+        BindingPolicy::parse(policy, 24, 64).unwrap().check(
+            &session,
+            ip.parse().unwrap(),
+            user_agent,
+        )
+    }
+
+    #[test]
+    fn policies() {
+        let updated = CHROME.replace("120.0.6099.109", "121.0.6167.85");
+        assert_eq!(check("none", "192.168.0.1", "curl/8.0"), Ok(()));
+        assert_eq!(check("ip", "10.1.2.3", "curl/8.0"), Ok(()));
+        assert_eq!(check("ip", "10.1.2.4", CHROME), Err(Mismatch::Ip));
+        assert_eq!(check("ip", "::ffff:10.1.2.3", CHROME), Ok(()));
+        assert_eq!(check("prefix", "10.1.2.200", CHROME), Ok(()));
+        assert_eq!(check("prefix", "::ffff:10.1.2.9", CHROME), Ok(()));
+        assert_eq!(check("prefix", "10.1.3.3", CHROME), Err(Mismatch::Ip));
+        assert_eq!(check("ua", "192.168.0.1", &updated), Ok(()));
+        assert_eq!(
+            check("ua", "10.1.2.3", "curl/8.0"),
+            Err(Mismatch::UserAgent)
+        );
+        assert_eq!(
+            check("prefix,ua", "10.1.2.3", "curl/8.0"),
+            Err(Mismatch::UserAgent)
+        );
+
+        let session = Session::new("alice", Vec::new(), "10.1.2.3", CHROME);
+        let none = BindingPolicy::parse("none", 24, 64).unwrap();
+        assert!(!none.changed(&session, "192.168.0.1", "curl/8.0"));
+        let prefix = BindingPolicy::parse("prefix", 24, 64).unwrap();
+        assert!(prefix.changed(&session, "10.1.2.200", CHROME));
+        assert!(!prefix.changed(&session, "10.1.2.3", &updated));
+        let exact = BindingPolicy::parse("ip", 24, 64).unwrap();
+        assert!(!exact.changed(&session, "::ffff:10.1.2.3", CHROME));
+
+        assert!(BindingPolicy::parse("ip,prefix", 24, 64).is_err());
+        assert!(BindingPolicy::parse("none,ua", 24, 64).is_err());
+        assert!(BindingPolicy::parse("mac", 24, 64).is_err());
+        assert!(BindingPolicy::parse("prefix", 33, 64).is_err());
+    }
+
+    #[test]
+    fn ipv6_prefix() {
+        let a = "2001:db8:1:2::1".parse().unwrap();
+        assert!(same_network(
+            a,
+            "2001:db8:1:2:ffff::1".parse().unwrap(),
+            24,
+            64
+        ));
+        assert!(!same_network(a, "2001:db8:1:3::1".parse().unwrap(), 24, 64));
+        assert!(!same_network(a, "10.1.2.3".parse().unwrap(), 0, 0));
+        assert!(same_network(a, "2001:db9::1".parse().unwrap(), 24, 0));
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use cookie::{Cookie, CookieJar, Key, SameSite};
+use httparse::Request;
+
+use crate::{router::header, server::SyncError};
+
+// 会话 Cookie 的保护方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CookieMode {
+    Plain,
+    // 附带 HMAC 签名，值可见但无法伪造
+    Signed,
+    // 加密并认证，值对客户端不可见
+    Private,
+}
+
+#[derive(Clone)]
+pub struct CookieConfig {
+    pub name: String,
+    pub domain: Option<String>,
+    pub path: String,
+    pub same_site: SameSite,
+    pub secure: bool,
+    pub http_only: bool,
+    pub mode: CookieMode,
+    // 第一个密钥用于签发，其余只用于校验轮换前签发的 Cookie
+    pub keys: Vec<Key>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            name: "key".into(),
+            domain: None,
+            path: "/".into(),
+            same_site: SameSite::Lax,
+            secure: false,
+            http_only: true,
+            mode: CookieMode::Plain,
+            keys: Vec::new(),
+        }
+    }
+}
+
+// 由配置中的密钥串派生签名与加密密钥，至少 32 字节
+pub fn derive_key(secret: &str) -> Result<Key, Box<SyncError>> {
+    if secret.len() < 32 {
+        return Err("Err: Cookie key must be at least 32 bytes".into());
+    }
+    Ok(Key::derive_from(secret.as_bytes()))
+}
+
+impl CookieConfig {
+    // 登录与更换会话键时下发的 Set-Cookie 头
+    pub fn set_cookie(&self, value: &str, max_age: Duration) -> String {
+        let mut cookie = self.build(value.to_string());
+        cookie.set_max_age(cookie::time::Duration::seconds(max_age.as_secs() as i64));
+        format!("Set-Cookie: {}", self.protect(cookie))
+    }
+
+    // 让浏览器删除会话 Cookie
+    pub fn clear_cookie(&self) -> String {
+        let mut cookie = self.build(String::new());
+        cookie.set_max_age(cookie::time::Duration::ZERO);
+        format!("Set-Cookie: {}", cookie)
+    }
+
+    // 请求中的会话键，签名或解密失败时视为没有
+    pub fn session_key(&self, req: &Request<'_, '_>) -> Option<String> {
+        let cookie = Cookie::split_parse(header(req, "Cookie")?)
+            .filter_map(Result::ok)
+            .find(|cookie| cookie.name() == self.name)?
+            .into_owned();
+        let jar = CookieJar::new();
+        let value = match self.mode {
+            // 兼容旧版本下发的带引号的值
+            CookieMode::Plain => Some(cookie.value_trimmed().to_string()),
+            CookieMode::Signed => self
+                .keys
+                .iter()
+                .find_map(|key| jar.signed(key).verify(cookie.clone()))
+                .map(|cookie| cookie.value().to_string()),
+            CookieMode::Private => self
+                .keys
+                .iter()
+                .find_map(|key| jar.private(key).decrypt(cookie.clone()))
+                .map(|cookie| cookie.value().to_string()),
+        };
+        value.filter(|value| !value.is_empty())
+    }
+
+    fn build(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build((self.name.clone(), value))
+            .path(self.path.clone())
+            .same_site(self.same_site)
+            .secure(self.secure)
+            .http_only(self.http_only)
+            .build();
+        if let Some(domain) = &self.domain {
+            cookie.set_domain(domain.clone());
+        }
+        cookie
+    }
+
+    fn protect(&self, cookie: Cookie<'static>) -> Cookie<'static> {
+        let Some(key) = self.keys.first() else {
+            return cookie;
+        };
+        let mut jar = CookieJar::new();
+        match self.mode {
+            CookieMode::Plain => return cookie,
+            CookieMode::Signed => jar.signed_mut(key).add(cookie),
+            CookieMode::Private => jar.private_mut(key).add(cookie),
+        }
+        jar.get(&self.name).cloned().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request_cookie(config: &CookieConfig, set_cookie: &str) -> Option<String> {
+        // 只取 name=value 部分，模拟浏览器回传
+        let pair = set_cookie
+            .strip_prefix("Set-Cookie: ")
+            .and_then(|c| c.split(';').next())
+            .unwrap();
+        let mut headers = [httparse::Header {
+            name: "Cookie",
+            value: pair.as_bytes(),
+        }];
+        config.session_key(&Request::new(&mut headers))
+    }
+
+    #[test]
+    fn attributes() {
+        let config = CookieConfig {
+            domain: Some("example.com".into()),
+            secure: true,
+            same_site: SameSite::Strict,
+            ..CookieConfig::default()
+        };
+        let set = config.set_cookie("abc", Duration::from_secs(60));
+        assert!(set.starts_with("Set-Cookie: key=abc;"));
+        for attr in [
+            "HttpOnly",
+            "SameSite=Strict",
+            "Secure",
+            "Path=/",
+            "Domain=example.com",
+            "Max-Age=60",
+        ] {
+            assert!(set.contains(attr), "{} missing in {}", attr, set);
+        }
+        assert_eq!(request_cookie(&config, &set).as_deref(), Some("abc"));
+        assert!(config.clear_cookie().contains("Max-Age=0"));
+        assert_eq!(request_cookie(&config, &config.clear_cookie()), None);
+    }
+
+    #[test]
+    fn signed_and_private() {
+        let old = derive_key(&"o".repeat(32)).unwrap();
+        let new = derive_key(&"n".repeat(32)).unwrap();
+        assert!(derive_key("short").is_err());
+        for mode in [CookieMode::Signed, CookieMode::Private] {
+            let before = CookieConfig {
+                mode,
+                keys: vec![old.clone()],
+                ..CookieConfig::default()
+            };
+            let set = before.set_cookie("session-key", Duration::from_secs(60));
+            assert_eq!(mode == CookieMode::Signed, set.contains("session-key"));
+            assert_eq!(
+                request_cookie(&before, &set).as_deref(),
+                Some("session-key")
+            );
+            // 轮换后旧密钥签发的 Cookie 依然有效，去掉旧密钥后失效
+            let rotated = CookieConfig {
+                keys: vec![new.clone(), old.clone()],
+                ..before.clone()
+            };
+            assert_eq!(
+                request_cookie(&rotated, &set).as_deref(),
+                Some("session-key")
+            );
+            let retired = CookieConfig {
+                keys: vec![new.clone()],
+                ..before.clone()
+            };
+            assert_eq!(request_cookie(&retired, &set), None);
+            let tampered = set.replacen("key=", "key=x", 1);
+            assert_eq!(request_cookie(&rotated, &tampered), None);
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod access;
+pub mod basic;
+pub mod binding;
+pub mod cookies;
+pub mod jwt;
+pub mod password;
+pub mod tokens;
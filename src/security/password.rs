@@ -0,0 +1,144 @@
+use std::sync::LazyLock;
+
+use argon2::{
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use subtle::ConstantTimeEq;
+
+use crate::server::SyncError;
+
+// Argon2id 参数（OWASP 推荐的最低配置）：内存 KiB、迭代次数、并行度
+const M_COST: u32 = 19 * 1024;
+const T_COST: u32 = 2;
+const P_COST: u32 = 1;
+
+// 用户不存在时也做一次完整校验，避免通过响应时间枚举用户名
+static DUMMY_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_password("dummy password").expect("hash dummy password"));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verification {
+    Invalid,
+    Valid,
+    // 密码正确，但存储的是明文或旧参数的哈希，应重新计算后写回
+    NeedsRehash,
+}
+
+fn hasher() -> Argon2<'static> {
+    let params = Params::new(M_COST, T_COST, P_COST, None).expect("valid argon2 params");
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+// 返回 PHC 格式字符串，如 $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+pub fn hash_password(password: &str) -> Result<String, Box<SyncError>> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = hasher()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| format!("Err: Failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(stored: &str, password: &str) -> Verification {
+    // 历史数据以明文保存，校验通过后迁移为哈希；明文本身也可能以 `$` 开头
+    if !stored.starts_with("$argon2") {
+        return match bool::from(stored.as_bytes().ct_eq(password.as_bytes())) {
+            true => Verification::NeedsRehash,
+            false => Verification::Invalid,
+        };
+    }
+    let Ok(hash) = PasswordHash::new(stored) else {
+        return Verification::Invalid;
+    };
+    // 不同参数的哈希也能校验，Argon2 会按哈希中记录的参数计算
+    if hasher()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Verification::Invalid;
+    }
+    if needs_rehash(&hash) {
+        Verification::NeedsRehash
+    } else {
+        Verification::Valid
+    }
+}
+
+// 密码强度：8 到 128 个字符，至少包含字母、数字、其他符号中的两类，且不能包含用户名
+pub fn check_strength(user: &str, password: &str) -> Result<(), &'static str> {
+    if !(8..=128).contains(&password.chars().count()) {
+        return Err("password must be 8 to 128 characters");
+    }
+    let classes = [
+        password.chars().any(char::is_alphabetic),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    if classes.iter().filter(|c| **c).count() < 2 {
+        return Err("password must mix at least two of letters, digits and symbols");
+    }
+    if !user.is_empty() && password.to_lowercase().contains(&user.to_lowercase()) {
+        return Err("password must not contain the username");
+    }
+    Ok(())
+}
+
+// 消耗与一次正常校验相同的时间，结果总是失败
+pub fn verify_dummy(password: &str) {
+    let _ = verify_password(&DUMMY_HASH, password);
+}
+
+fn needs_rehash(hash: &PasswordHash) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(hash) {
+        Ok(params) => {
+            params.m_cost() != M_COST || params.t_cost() != T_COST || params.p_cost() != P_COST
+        }
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hash_and_verify() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+        assert_eq!(verify_password(&hash, "correct horse"), Verification::Valid);
+        assert_eq!(verify_password(&hash, "wrong"), Verification::Invalid);
+        assert_eq!(
+            verify_password("$argon2id$garbage", "$argon2id$garbage"),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn strength() {
+        assert!(check_strength("alice", "short1").is_err());
+        assert!(check_strength("alice", "onlyletters").is_err());
+        assert!(check_strength("alice", "xAlice2024x").is_err());
+        assert!(check_strength("alice", "horse battery").is_ok());
+        assert!(check_strength("alice", "密码很长很长123").is_ok());
+    }
+
+    #[test]
+    fn rehash_plaintext_and_old_params() {
+        assert_eq!(verify_password("pwd", "pwd"), Verification::NeedsRehash);
+        assert_eq!(verify_password("pwd", "pwd2"), Verification::Invalid);
+        assert_eq!(verify_password("$pwd", "$pwd"), Verification::NeedsRehash);
+
+        let params = Params::new(8 * 1024, 1, 1, None).unwrap();
+        let weak = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password(b"pwd", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+        assert_eq!(verify_password(&weak, "pwd"), Verification::NeedsRehash);
+        assert_eq!(verify_password(&weak, "nope"), Verification::Invalid);
+    }
+}
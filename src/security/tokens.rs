@@ -0,0 +1,65 @@
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+// 令牌格式为 srs_<id>_<secret>，id 用于查找记录，secret 只以哈希形式保存
+const PREFIX: &str = "srs_";
+const ID_LEN: usize = 16;
+const SECRET_LEN: usize = 32;
+
+pub struct NewToken {
+    pub id: String,
+    pub hash: String,
+    // 完整令牌，只在创建时返回给用户一次
+    pub token: String,
+}
+
+pub fn generate() -> NewToken {
+    let id = uuid::Uuid::new_v4().simple().to_string()[..ID_LEN].to_string();
+    let secret = uuid::Uuid::new_v4().simple().to_string();
+    NewToken {
+        token: format!("{}{}_{}", PREFIX, id, secret),
+        hash: hash_secret(&secret),
+        id,
+    }
+}
+
+// 拆出 id 与 secret，格式不符时返回 None
+pub fn parse(token: &str) -> Option<(&str, &str)> {
+    let (id, secret) = token.strip_prefix(PREFIX)?.split_once('_')?;
+    let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    (hex(id, ID_LEN) && hex(secret, SECRET_LEN)).then_some((id, secret))
+}
+
+// 令牌随机生成、熵足够高，不需要慢哈希
+pub fn hash_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn verify_secret(secret: &str, hash: &str) -> bool {
+    hash_secret(secret).as_bytes().ct_eq(hash.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn generate_and_verify() {
+        let new = generate();
+        let (id, secret) = parse(&new.token).unwrap();
+        assert_eq!(id, new.id);
+        assert!(verify_secret(secret, &new.hash));
+        assert!(!new.hash.contains(secret));
+
+        let other = generate();
+        assert_ne!(other.id, new.id);
+        assert!(!verify_secret(parse(&other.token).unwrap().1, &new.hash));
+
+        assert!(parse("srs_short_secret").is_none());
+        assert!(parse(&new.token.replacen("srs_", "api_", 1)).is_none());
+        assert!(parse(&format!("{}x", new.token)).is_none());
+    }
+}
@@ -1,52 +1,70 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
-use httparse::Status;
+use bytes::BytesMut;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
-    //sync::Mutex,
 };
 
+pub mod conn;
+
 pub(crate) type SyncError = dyn std::error::Error + Send + Sync;
 
-use crate::{middleware::auth::auth, protocol::Redis, router::route};
+// 请求头与请求体的大小上限
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+const MAX_BODY_SIZE: usize = 1024 * 1024;
 
-/* struct DataBase;
+use crate::{
+    config::Config,
+    middleware::auth::auth,
+    protocol::error::RedisError,
+    router::{content_length, header, is_streaming, route},
+    security::basic::BasicGuard,
+    server::conn::Conn,
+    store::DataBase,
+};
 
-impl DataBase {
-    pub fn new() -> DataBase {
-        DataBase
-    }
-}*/
+// 所有连接共享的存储与配置
+pub struct AppState {
+    pub db: DataBase,
+    pub config: Config,
+    pub basic: BasicGuard,
+}
 
 pub struct Server {
     listener: TcpListener,
-    //inner_db: Arc<Mutex<DataBase>>,
-    outer_db: Arc<Redis>,
+    state: Arc<AppState>,
 }
 
 impl Server {
-    pub async fn new(listen_addr: &str, db_addr: &str) -> Result<Self, Box<SyncError>> {
-        let listener = TcpListener::bind(listen_addr).await?;
+    pub async fn new(config: Config, db: DataBase) -> Result<Self, Box<SyncError>> {
+        let listener = TcpListener::bind(&config.listen_addr).await?;
         let server = Server {
             listener,
-            //inner_db: Arc::new(Mutex::new(DataBase::new())),
-            outer_db: Arc::new(Redis::new(db_addr)?),
+            state: Arc::new(AppState {
+                db,
+                config,
+                basic: BasicGuard::new(),
+            }),
         };
         Ok(server)
     }
 
+    // 监听端口为 0 时由系统分配，可通过此方法获得实际地址
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
     pub async fn run(&mut self) -> Result<(), Box<SyncError>> {
         let env_path = std::env::current_dir().expect("无法获取程序运行环境路径");
         println!("Server running Env: {}", env_path.display());
-        println!("Server running on http://{}", self.listener.local_addr()?);
-        println!("Server using outer_db: {}", self.outer_db.addr());
+        println!("Server running on http://{}", self.local_addr()?);
+        println!("Server using db: {}", self.state.db);
         loop {
             let (stream, client_addr) = self.listener.accept().await?;
-            //let db = self.inner_db.clone();
-            let db = self.outer_db.clone();
-            let _ = tokio::spawn(async move {
-                if let Err(err) = Self::handle_connection(stream, db).await {
+            let state = self.state.clone();
+            tokio::spawn(async move {
+                if let Err(err) = Self::handle_connection(stream, state).await {
                     eprintln!("Error to handle connection from {}:{}", client_addr, err);
                 }
             });
@@ -56,42 +74,1017 @@ impl Server {
     }
 
     async fn handle_connection(
-        mut stream: TcpStream,
-        db: Arc<Redis>,
+        stream: TcpStream,
+        state: Arc<AppState>,
     ) -> Result<(), Box<SyncError>> {
-        let mut tmpbuff = [0u8; 4096]; // 认为数据包大小不超过 4096
-
-        match stream.read(&mut tmpbuff).await {
-            Ok(0) => {
+        let mut stream = Conn::new(stream);
+        let mut buf = BytesMut::with_capacity(4096);
+        // 读取直到请求头完整
+        let offset = loop {
+            if stream.read_buf(&mut buf).await? == 0 {
                 // 这是对方主动关闭了连接，不需要进一步解析
+                return Ok(());
             }
-            Ok(n) => {
-                #[cfg(debug_assertions)]
-                println!("http package size:{}", n);
-                // 解析 HTTP 请求
-                let mut headers = [httparse::EMPTY_HEADER; 24];
-                let mut req_headers = httparse::Request::new(&mut headers);
-                if let Status::Complete(offset) = req_headers.parse(&tmpbuff)? {
-                    let body = bytes::BytesMut::from(&tmpbuff[offset..n]);
-                    // 接下来是 中间件（权限认证） 和 业务逻辑
-                    // 中间件
-                    if auth(&mut stream, db.clone(), &req_headers).await? {
-                        // 业务逻辑-路由
-                        route(&mut stream, db, &req_headers, body).await?;
-                    }
-                }
-                stream.shutdown().await?;
+            if let Some(offset) = Self::header_end(&buf) {
+                break offset;
             }
-            Err(e) => {
-                return Err(Box::new(e));
+            if buf.len() > MAX_HEADER_SIZE {
+                return Self::reject(&mut stream, "431 Request Header Fields Too Large").await;
             }
+        };
+        // 请求头单独保存并只解析一次，buf 之后只存放请求体
+        let head = buf.split_to(offset).freeze();
+        let mut headers = [httparse::EMPTY_HEADER; 24];
+        let mut req_headers = httparse::Request::new(&mut headers);
+        if req_headers.parse(&head)?.is_partial() {
+            return Self::reject(&mut stream, "400 Bad Request").await;
         }
+        // 不支持分块传输编码的请求体
+        if header(&req_headers, "Transfer-Encoding").is_some() {
+            return Self::reject(&mut stream, "501 Not Implemented").await;
+        }
+        let streaming = is_streaming(&req_headers);
+        let content_length = match content_length(&req_headers) {
+            // 流式路由自行限制请求体大小
+            Some(n) if n > MAX_BODY_SIZE && !streaming => {
+                return Self::reject(&mut stream, "413 Payload Too Large").await;
+            }
+            Some(n) => n,
+            None => return Self::reject(&mut stream, "400 Bad Request").await,
+        };
+        // 按 Content-Length 读取完整的请求体，对方提前关闭连接时请求不完整
+        while !streaming && buf.len() < content_length {
+            if stream.read_buf(&mut buf).await? == 0 {
+                return Self::reject(&mut stream, "400 Bad Request").await;
+            }
+        }
+        let mut body = buf;
+        if !streaming {
+            body.truncate(content_length);
+        }
+        #[cfg(debug_assertions)]
+        println!("http package size:{}", offset + body.len());
+
+        // 接下来是 中间件（权限认证） 和 业务逻辑
+        // 中间件
+        let result = match auth(&mut stream, state.clone(), &req_headers).await {
+            // 业务逻辑-路由
+            Ok(Some(ctx)) => route(&mut stream, state.clone(), &ctx, &req_headers, body).await,
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(err) = result {
+            // Redis 不可用时告知客户端稍后重试，其余错误照常上报
+            if !RedisError::is_unavailable(err.as_ref()) {
+                return Err(err);
+            }
+            eprintln!("{}", err);
+            Self::unavailable(&mut stream, state.db.retry_after().as_secs()).await?;
+        }
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    // 请求不合法时直接回复状态码并关闭连接
+    async fn reject(stream: &mut Conn, status: &str) -> Result<(), Box<SyncError>> {
+        let response = format!(
+            "HTTP/1.1 {}\r\n\
+            Content-Type: text/plain\r\n\
+            Content-Length: {}\r\n\
+            Connection: close\r\n\r\n\
+            {}",
+            status,
+            status.len(),
+            status
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+
+    // 请求头结束位置（含空行）
+    fn header_end(buf: &[u8]) -> Option<usize> {
+        buf.windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|pos| pos + 4)
+    }
+
+    async fn unavailable(stream: &mut Conn, retry_after: u64) -> Result<(), Box<SyncError>> {
+        let body = "<!DOCTYPE html><html><head><title>Service Unavailable</title></head><body><h1>Service Unavailable</h1></body></html>";
+        let response = format!(
+            "HTTP/1.1 503 Service Unavailable\r\n\
+            Content-Type: text/html\r\n\
+            Content-Length: {}\r\n\
+            Retry-After: {}\r\n\
+            Connection: close\r\n\r\n\
+            {}",
+            body.len(),
+            retry_after,
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::*;
+    use crate::{
+        config::{SessionConfig, StoreKind},
+        protocol::{mock::MockRedis, resp::RespValue},
+        security::{
+            access::{AccessRules, RolePermissions},
+            binding::BindingPolicy,
+            jwt::JwtConfig,
+        },
+        store::Session,
+    };
+    use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+    // 在系统分配的端口上启动服务器
+    async fn start(config: Config, db: DataBase) -> SocketAddr {
+        let config = Config {
+            listen_addr: "127.0.0.1:0".into(),
+            ..config
+        };
+        let mut server = Server::new(config, db).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move { server.run().await });
+        addr
+    }
+
+    // 发送一个请求并读取完整响应（服务端处理完即关闭连接）
+    async fn http(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    // 响应中下发的会话键
+    fn session_cookie(response: &str) -> Option<String> {
+        response
+            .lines()
+            .find_map(|l| l.strip_prefix("Set-Cookie: key="))
+            .and_then(|l| l.split(';').next())
+            .map(String::from)
+    }
+
+    #[tokio::test]
+    async fn login_with_mock_redis() {
+        let redis = MockRedis::start().await;
+        // 历史明文密码登录后被迁移为哈希
+        redis.execute(&["HSET", "usr-pwd", "alice", "secret"]);
+        login_flow(DataBase::open(&StoreKind::Redis(redis.url())).unwrap()).await;
+        let RespValue::BulkString(Some(hash)) = redis.execute(&["HGET", "usr-pwd", "alice"]) else {
+            panic!("password missing");
+        };
+        assert!(hash.starts_with("$argon2id$"));
+    }
+
+    #[tokio::test]
+    async fn login_with_memory_store() {
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        db.users.set_password("alice", "secret").await.unwrap();
+        login_flow(db).await;
+    }
+
+    #[tokio::test]
+    async fn register_then_login() {
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        let addr = start(Config::default(), db).await;
+
+        let post = |path: &str, body: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n{}",
+                path,
+                body.len(),
+                body
+            )
+        };
+        let register = |body: &str| post("/srs/register", body);
+        let response = http(
+            addr,
+            &register("user=al&password=horse+battery&re-password=horse+battery"),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        let response = http(
+            addr,
+            &register("user=alice&password=horse+battery&re-password=horse"),
+        )
+        .await;
+        assert!(response.ends_with("failed: passwords do not match"));
+        let response = http(
+            addr,
+            &register("user=alice&password=weakpass&re-password=weakpass"),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        let ok = register("user=alice&password=horse+battery%21&re-password=horse+battery%21");
+        let response = http(addr, &ok).await;
+        assert!(response.starts_with("HTTP/1.1 303"));
+        assert!(response.contains("Location: /srs/LoginInterface.html"));
+        assert!(response.ends_with("success"));
+        let response = http(addr, &ok).await;
+        assert!(response.starts_with("HTTP/1.1 409"));
+        assert!(response.ends_with("failed: user already exists"));
+
+        let response = http(
+            addr,
+            &post("/srs/login", "user=alice&password=horse battery!&re-auth="),
+        )
+        .await;
+        assert!(response.ends_with("success"));
+    }
+
+    #[tokio::test]
+    async fn json_register_and_login() {
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        let addr = start(Config::default(), db).await;
+
+        let post = |path: &str, body: &str| {
+            format!(
+                "POST {} HTTP/1.1\r\nHost: test\r\nContent-Type: application/json\r\n\
+                Content-Length: {}\r\n\r\n{}",
+                path,
+                body.len(),
+                body
+            )
+        };
+        let response = http(addr, &post("/srs/register", r#"{"user":"bob"#)).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.contains("Content-Type: application/json"));
+        let register =
+            r#"{"user":"bob","password":"horse battery!","re-password":"horse battery!"}"#;
+        let response = http(addr, &post("/srs/register", register)).await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert!(response.ends_with(r#"{"ok":true,"user":"bob"}"#));
+
+        let response = http(
+            addr,
+            &post("/srs/login", r#"{"user":"bob","password":"wrong"}"#),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains(r#""ok":false"#));
+        let response = http(
+            addr,
+            &post(
+                "/srs/login",
+                r#"{"user":"bob","password":"horse battery!"}"#,
+            ),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Set-Cookie: key="));
+        assert!(response.contains("Cache-Control: no-store"));
+        assert!(response.contains(r#""expires_in":86400"#));
+        assert!(response.contains(r#""csrf_token":""#));
+    }
+
+    #[tokio::test]
+    async fn revoke_other_sessions() {
+        let config = Config {
+            admin_users: vec!["root".into()],
+            ..Config::default()
+        };
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        let ttl = Duration::from_secs(60);
+        let mut csrf = HashMap::new();
+        for (key, user) in [("a1", "alice"), ("a2", "alice"), ("r1", "root")] {
+            let session = Session::new(user, config.roles(user), "127.0.0.1", "test");
+            csrf.insert(key, session.csrf_token.clone());
+            assert!(
+                db.sessions
+                    .create_session(key, &session, ttl)
+                    .await
+                    .unwrap()
+            );
+        }
+        let addr = start(config, db).await;
+
+        let revoke = |key: &str, token: &str, body: &str| {
+            format!(
+                "POST /srs/sessions/revoke HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\n\
+                X-CSRF-Token: {}\r\nContent-Length: {}\r\n\r\n{}",
+                key,
+                token,
+                body.len(),
+                body
+            )
+        };
+        let response = http(addr, &revoke("a1", "wrong", "")).await;
+        assert!(response.contains("invalid csrf token"));
+        let response = http(addr, &revoke("a1", &csrf["a1"], "user=root")).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+        let response = http(addr, &revoke("a1", &csrf["a1"], "")).await;
+        assert!(response.ends_with(r#"{"ok":true,"revoked":1}"#));
+        let response = http(addr, &revoke("a2", &csrf["a2"], "")).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+
+        // 管理员注销其他用户的全部会话
+        let response = http(addr, &revoke("r1", &csrf["r1"], "user=alice")).await;
+        assert!(response.ends_with(r#"{"ok":true,"revoked":1}"#));
+        let response = http(addr, &revoke("a1", &csrf["a1"], "")).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+    }
+
+    #[tokio::test]
+    async fn role_management() {
+        let config = Config {
+            admin_users: vec!["root".into()],
+            access: AccessRules::parse(
+                "* /srs/admin/** permission:roles.manage; * /srs/** public; \
+                * /editor/** permission:upload; * /** authenticated",
+            )
+            .unwrap(),
+            permissions: RolePermissions::parse("editor=upload;manager=roles.manage").unwrap(),
+            ..Config::default()
+        };
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        let ttl = Duration::from_secs(60);
+        let mut csrf = HashMap::new();
+        // carol 可以管理角色，但没有 upload 与 admin 的权限
+        db.users
+            .set_roles("carol", &["manager".into()])
+            .await
+            .unwrap();
+        for user in ["alice", "root", "carol"] {
+            db.users.set_password(user, "secret").await.unwrap();
+            let mut roles = config.roles(user);
+            roles.extend(db.users.roles(user).await.unwrap());
+            let session = Session::new(user, roles, "127.0.0.1", "test");
+            csrf.insert(user, session.csrf_token.clone());
+            assert!(
+                db.sessions
+                    .create_session(user, &session, ttl)
+                    .await
+                    .unwrap()
+            );
+        }
+        let addr = start(config, db).await;
+
+        let get = |path: &str, key: &str| {
+            format!(
+                "GET {} HTTP/1.1\r\nHost: test\r\nAccept: application/json\r\nCookie: key={}\r\n\r\n",
+                path, key
+            )
+        };
+        let set_as = |key: &str, content_type: &str, body: &str| {
+            format!(
+                "POST /srs/admin/roles HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\n\
+                X-CSRF-Token: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+                key,
+                csrf.get(key).map_or("wrong", String::as_str),
+                content_type,
+                body.len(),
+                body
+            )
+        };
+        let set = |body: &str, token: &str| {
+            format!(
+                "POST /srs/admin/roles HTTP/1.1\r\nHost: test\r\nCookie: key=root\r\n\
+                X-CSRF-Token: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                token,
+                body.len(),
+                body
+            )
+        };
+        // 普通用户没有管理权限，得到 403 而不是 401
+        let response = http(addr, &get("/srs/admin/roles?user=alice", "alice")).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert!(response.contains("permission denied"));
+        assert!(
+            http(addr, &get("/editor/x", "alice"))
+                .await
+                .starts_with("HTTP/1.1 403")
+        );
+
+        let response = http(addr, &get("/srs/admin/roles?user=alice", "root")).await;
+        assert!(response.ends_with(r#"{"ok":true,"roles":[],"user":"alice"}"#));
+        let alice = r#"{"user":"alice","roles":["editor","user","editor"]}"#;
+        let response = http(addr, &set(alice, "wrong")).await;
+        assert!(response.contains("invalid csrf token"));
+        let response = http(addr, &set(alice, &csrf["root"])).await;
+        assert!(response.ends_with(r#"{"ok":true,"roles":["editor"],"user":"alice"}"#));
+        let response = http(addr, &set(r#"{"user":"bob","roles":[]}"#, &csrf["root"])).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let response = http(
+            addr,
+            &set(r#"{"user":"alice","roles":["Bad Role"]}"#, &csrf["root"]),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        let response = http(addr, &set(r#"{"user":"alice"}"#, &csrf["root"])).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        let form = "application/x-www-form-urlencoded";
+        let response = http(addr, &set_as("root", form, "user=carol")).await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+
+        // 不能授予或收回自己没有其全部权限的角色
+        let json = "application/json";
+        for body in [
+            r#"{"user":"carol","roles":["manager","admin"]}"#,
+            r#"{"user":"alice","roles":[]}"#,
+            r#"{"user":"alice","roles":["editor","unmapped"]}"#,
+            r#"{"user":"carol","roles":["manager","unmapped"]}"#,
+        ] {
+            let response = http(addr, &set_as("carol", json, body)).await;
+            assert!(response.starts_with("HTTP/1.1 403"));
+        }
+        let response = http(
+            addr,
+            &set_as("carol", form, "user=alice&role=editor&role=manager"),
+        )
+        .await;
+        assert!(response.ends_with(r#"{"ok":true,"roles":["editor","manager"],"user":"alice"}"#));
+        let response = http(addr, &set_as("root", form, "user=carol&role=")).await;
+        assert!(response.ends_with(r#"{"ok":true,"roles":[],"user":"carol"}"#));
+
+        // 角色变化在下一个请求生效，同时更换会话键
+        let response = http(addr, &get("/editor/x", "alice")).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let key = session_cookie(&response).unwrap();
+        let response = http(addr, &get("/srs/session", &key)).await;
+        assert!(response.contains(r#""roles":["user","editor","manager"]"#));
+    }
+
+    #[tokio::test]
+    async fn api_credentials() {
+        let config = Config {
+            access: AccessRules::parse(
+                "* /srs/** public; * /editor/** permission:upload; * /** authenticated",
+            )
+            .unwrap(),
+            permissions: RolePermissions::parse("editor=upload").unwrap(),
+            ..Config::default()
+        };
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        db.users.set_password("alice", "secret").await.unwrap();
+        db.users
+            .set_roles("alice", &["editor".into()])
+            .await
+            .unwrap();
+        let addr = start(config, db).await;
+
+        // Basic: base64("alice:secret")，Basic 错误密码: base64("alice:wrong")
+        let basic = "Basic YWxpY2U6c2VjcmV0";
+        let request = |method: &str, path: &str, authorization: &str, body: &str| {
+            format!(
+                "{} {} HTTP/1.1\r\nHost: test\r\nAuthorization: {}\r\n\
+                Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                method,
+                path,
+                authorization,
+                body.len(),
+                body
+            )
+        };
+        let response = http(addr, &request("GET", "/1.txt", basic, "")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let response = http(
+            addr,
+            &request("GET", "/1.txt", "Basic YWxpY2U6d3Jvbmc=", ""),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains("WWW-Authenticate: Basic realm=\"srs\""));
+        let response = http(addr, &request("GET", "/1.txt", "Bearer bad", "")).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(!response.contains("Basic realm"));
+        // 公开路径上的无效凭据按未登录处理
+        let response = http(addr, &request("GET", "/srs/session", "Bearer bad", "")).await;
+        assert!(response.contains("not logged in"));
+
+        let create = |body: &str| request("POST", "/srs/tokens", basic, body);
+        let response = http(addr, &create(r#"{"name":"ci","scopes":["upload"]}"#)).await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        let created: serde_json::Value =
+            serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        let (id, token) = (
+            created["id"].as_str().unwrap(),
+            created["token"].as_str().unwrap(),
+        );
+        let bearer = format!("Bearer {}", token);
+        let response = http(addr, &create(r#"{"name":"read-only"}"#)).await;
+        let read_only = response.split("\"token\":\"").nth(1).unwrap();
+        let read_only = format!("Bearer {}", read_only.split('"').next().unwrap());
+        assert!(
+            http(addr, &create(r#"{"name":"x","scopes":["Bad"]}"#))
+                .await
+                .starts_with("HTTP/1.1 400")
+        );
+
+        // 令牌的权限是用户角色与令牌范围的交集
+        let response = http(addr, &request("GET", "/editor/x", &bearer, "")).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let response = http(addr, &request("GET", "/editor/x", &read_only, "")).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+        assert!(response.contains("error=\"insufficient_scope\", scope=\"upload\""));
+        assert!(
+            http(addr, &request("GET", "/1.txt", &read_only, ""))
+                .await
+                .starts_with("HTTP/1.1 200")
+        );
+        let response = http(
+            addr,
+            &request("POST", "/srs/tokens", &bearer, r#"{"name":"y"}"#),
+        )
+        .await;
+        assert!(response.contains("tokens cannot create tokens"));
+
+        let response = http(addr, &request("GET", "/srs/tokens", basic, "")).await;
+        assert!(response.contains(r#""name":"ci""#) && response.contains(r#""name":"read-only""#));
+        assert!(
+            !response.contains("hash") && !response.contains(r#""last_used":null,"name":"ci""#)
+        );
+
+        let revoke = format!(r#"{{"id":"{}"}}"#, id);
+        let response = http(addr, &request("POST", "/srs/tokens/revoke", basic, &revoke)).await;
+        assert!(response.ends_with(r#"{"ok":true}"#));
+        let response = http(addr, &request("GET", "/1.txt", &bearer, "")).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains("error=\"invalid_token\""));
+        let response = http(addr, &request("POST", "/srs/tokens/revoke", basic, &revoke)).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+
+        let response = http(addr, &create(r#"{"name":"expired","expires_in":0}"#)).await;
+        let expired = response.split("\"token\":\"").nth(1).unwrap();
+        let expired = format!("Bearer {}", expired.split('"').next().unwrap());
+        assert!(
+            http(addr, &request("GET", "/1.txt", &expired, ""))
+                .await
+                .starts_with("HTTP/1.1 401")
+        );
+        assert!(
+            http(addr, &request("GET", "/1.txt", "Bearer nonsense", ""))
+                .await
+                .starts_with("HTTP/1.1 401")
+        );
+    }
+
+    #[tokio::test]
+    async fn jwt_mode() {
+        let redis = MockRedis::start().await;
+        let db = DataBase::open(&StoreKind::Redis(redis.url())).unwrap();
+        db.users.set_password("alice", "secret").await.unwrap();
+        db.users
+            .set_roles("alice", &["editor".into()])
+            .await
+            .unwrap();
+        let config = Config {
+            jwt: Some(JwtConfig::hs256(&"s".repeat(32)).unwrap()),
+            ..Config::default()
+        };
+        let addr = start(config, db).await;
+
+        let body = r#"{"user":"alice","password":"secret"}"#;
+        let response = http(
+            addr,
+            &format!(
+                "POST /srs/login HTTP/1.1\r\nHost: test\r\nContent-Type: application/json\r\n\
+                Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let login: serde_json::Value =
+            serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        let token = login["token"].as_str().unwrap();
+        let csrf = login["csrf_token"].as_str().unwrap();
+        assert_eq!(login["expires_in"], 3600);
+        assert_eq!(session_cookie(&response).as_deref(), Some(token));
+        // 会话存储中没有任何会话
+        let RespValue::Integer(0) = redis.execute(&["EXISTS", &format!("Session-{}", token)])
+        else {
+            panic!("session written in jwt mode");
+        };
+
+        let request = |method: &str, path: &str, credential: &str| {
+            format!(
+                "{} {} HTTP/1.1\r\nHost: test\r\n{}\r\nAccept: application/json\r\n\
+                Content-Length: 2\r\n\r\n{{}}",
+                method, path, credential
+            )
+        };
+        let cookie = format!("Cookie: key={}", token);
+        let bearer = format!("Authorization: Bearer {}", token);
+        let response = http(addr, &request("GET", "/srs/session", &cookie)).await;
+        assert!(response.contains(r#""roles":["user","editor"]"#));
+        assert!(response.contains(&format!(r#""csrf_token":"{}""#, csrf)));
+        assert!(
+            http(addr, &request("GET", "/1.txt", &bearer))
+                .await
+                .starts_with("HTTP/1.1 200")
+        );
+
+        // 以 Cookie 携带令牌时修改状态的请求需要 CSRF 令牌
+        let response = http(addr, &request("POST", "/srs/logout", &cookie)).await;
+        assert!(response.contains("invalid csrf token"));
+        // 会话列表与批量注销在 JWT 模式下不可用
+        let with_csrf = format!("{}\r\nX-CSRF-Token: {}", cookie, csrf);
+        let response = http(addr, &request("POST", "/srs/sessions/revoke", &with_csrf)).await;
+        assert!(response.starts_with("HTTP/1.1 501"));
+        let response = http(addr, &request("GET", "/srs/sessions", &bearer)).await;
+        assert!(response.starts_with("HTTP/1.1 501"));
+
+        // 篡改签名的令牌
+        let forged = format!("Authorization: Bearer {}x", token);
+        let response = http(addr, &request("GET", "/1.txt", &forged)).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains("error=\"invalid_token\""));
+
+        // 注销后令牌进入注销列表，签名依然有效也不再被接受
+        let response = http(addr, &request("POST", "/srs/logout", &bearer)).await;
+        assert!(response.ends_with(r#"{"ok":true}"#));
+        assert!(
+            http(addr, &request("GET", "/1.txt", &bearer))
+                .await
+                .starts_with("HTTP/1.1 401")
+        );
+        let response = http(addr, &request("GET", "/1.txt", &cookie)).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains(r#""error":"authentication required""#));
+    }
+
+    #[tokio::test]
+    async fn session_renewal() {
+        let config = Config {
+            admin_users: vec!["root".into()],
+            session: SessionConfig {
+                idle_timeout: Duration::from_secs(600),
+                max_lifetime: Duration::from_secs(3600),
+                rotate_interval: Duration::from_secs(300),
+            },
+            ..Config::default()
+        };
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        let ttl = Duration::from_secs(600);
+        let fresh = Session::new("alice", config.roles("alice"), "127.0.0.1", "test");
+        let mut due = fresh.clone();
+        due.rotated_at -= 301;
+        let mut expired = fresh.clone();
+        expired.created_at -= 3601;
+        // 角色与配置不一致，视为权限变化
+        let promoted = Session::new("root", config.roles("alice"), "127.0.0.1", "test");
+        for (key, session) in [
+            ("fresh", &fresh),
+            ("due", &due),
+            ("expired", &expired),
+            ("promoted", &promoted),
+        ] {
+            assert!(db.sessions.create_session(key, session, ttl).await.unwrap());
+        }
+        let addr = start(config, db).await;
+
+        let get = |key: &str| {
+            format!(
+                "GET /1.txt HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\n\r\n",
+                key
+            )
+        };
+        let response = http(addr, &get("fresh")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert_eq!(session_cookie(&response), None);
+
+        let response = http(addr, &get("due")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let rotated = session_cookie(&response).unwrap();
+        // 旧键在宽限期内仍然可用且不会再次更换
+        let response = http(addr, &get("due")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert_eq!(session_cookie(&response), None);
+        assert!(http(addr, &get(&rotated)).await.starts_with("HTTP/1.1 200"));
+
+        let response = http(addr, &get("promoted")).await;
+        let promoted = session_cookie(&response).unwrap();
+        let response = http(addr, &get(&promoted).replace("/1.txt", "/srs/session")).await;
+        assert!(response.contains(r#""roles":["user","admin"]"#));
+
+        assert!(
+            http(addr, &get("expired"))
+                .await
+                .starts_with("HTTP/1.1 303")
+        );
+    }
+
+    #[tokio::test]
+    async fn session_binding() {
+        let config = Config {
+            binding: BindingPolicy::parse("prefix,ua", 24, 64).unwrap(),
+            ..Config::default()
+        };
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        let ttl = Duration::from_secs(600);
+        let session = Session::new("alice", config.roles("alice"), "127.0.0.9", "Firefox/120.0");
+        assert!(
+            db.sessions
+                .create_session("k", &session, ttl)
+                .await
+                .unwrap()
+        );
+        let sessions = db.sessions.clone();
+        let addr = start(config, db).await;
+
+        let get = |user_agent: &str| {
+            format!(
+                "GET /1.txt HTTP/1.1\r\nHost: test\r\nUser-Agent: {}\r\nCookie: key=k\r\n\r\n",
+                user_agent
+            )
+        };
+        assert!(
+            http(addr, &get("curl/8.0"))
+                .await
+                .starts_with("HTTP/1.1 303")
+        );
+        // 同一网段且只是浏览器版本变化，绑定随之更新
+        assert!(
+            http(addr, &get("Firefox/121.0"))
+                .await
+                .starts_with("HTTP/1.1 200")
+        );
+        let session = sessions.session("k").await.unwrap().unwrap();
+        assert_eq!(session.ip, "127.0.0.1");
+        assert_eq!(session.user_agent, "Firefox/121.0");
+    }
+
+    #[tokio::test]
+    async fn access_rules() {
+        let config = Config {
+            admin_users: vec!["root".into()],
+            access: AccessRules::parse("* /srs/** public; * /admin/** role:admin; GET /** public")
+                .unwrap(),
+            ..Config::default()
+        };
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        db.users.set_password("alice", "secret").await.unwrap();
+        let ttl = Duration::from_secs(600);
+        for user in ["alice", "root"] {
+            let session = Session::new(user, config.roles(user), "127.0.0.1", "");
+            assert!(
+                db.sessions
+                    .create_session(user, &session, ttl)
+                    .await
+                    .unwrap()
+            );
+        }
+        let addr = start(config, db).await;
+
+        let get = |path: &str, key: &str| {
+            format!(
+                "GET {} HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\n\r\n",
+                path, key
+            )
+        };
+        assert!(
+            http(addr, &get("/1.txt", "none"))
+                .await
+                .starts_with("HTTP/1.1 200")
+        );
+        let response = http(addr, &get("/admin/x", "none")).await;
+        assert!(response.starts_with("HTTP/1.1 303"));
+        assert!(response.contains("next=%2Fadmin%2Fx"));
+        // 已登录但缺少角色时是 403 而不是跳转
+        let response = http(addr, &get("/admin/x", "alice")).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+        let response = http(addr, &get("/admin/x", "root")).await;
+        assert!(response.starts_with("HTTP/1.1 404"));
+        let post = "POST /1.txt HTTP/1.1\r\nHost: test\r\nAccept: application/json\r\n\r\n";
+        assert!(http(addr, post).await.starts_with("HTTP/1.1 401"));
+
+        // 登录后回到原地址，站外地址被忽略
+        let login = |next: &str| {
+            let body = format!("user=alice&password=secret&next={}", next);
+            format!(
+                "POST /srs/login HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        };
+        let response = http(addr, &login("%2Fadmin%2Fx%3Fa%3D1")).await;
+        assert!(response.starts_with("HTTP/1.1 303"));
+        assert!(response.contains("Location: /admin/x?a=1\r\n"));
+        assert!(session_cookie(&response).is_some());
+        let response = http(addr, &login("%2F%2Fevil.example")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("success"));
+    }
+
+    #[tokio::test]
+    async fn body_split_across_packets() {
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        db.users.set_password("a&b", "p=1 +2").await.unwrap();
+        let addr = start(Config::default(), db).await;
+
+        // 字段顺序任意，值经过百分号编码
+        let body = "password=p%3D1+%2B2&user=a%26b";
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "POST /srs/login HTTP/1.1\r\nHost: test\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\npassword=",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stream
+            .write_all(&body.as_bytes()["password=".len()..])
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.ends_with("success"));
+
+        let response = http(
+            addr,
+            "POST /srs/login HTTP/1.1\r\nContent-Length: 10\r\n\r\nuser=a%26b",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert!(response.ends_with("failed: missing form field 'password'"));
+        let response = http(
+            addr,
+            "POST /srs/login HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 400"));
+        let response = http(
+            addr,
+            "POST /srs/login HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 501"));
+
+        // 请求体未发送完整就关闭了写端
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"POST /srs/login HTTP/1.1\r\nContent-Length: 20\r\n\r\nuser=alice")
+            .await
+            .unwrap();
+        stream.shutdown().await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 400"));
+    }
+
+    #[tokio::test]
+    async fn upload_files() {
+        // 放在 static 下，上传的文件可以通过文件路由取回
+        let dir = PathBuf::from(format!(
+            "./static/uploads-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let config = Config {
+            upload_dir: dir.clone(),
+            // 即使访问规则放开了上传路径，上传仍然需要登录
+            access: AccessRules::parse("* /upload public; * /** authenticated").unwrap(),
+            max_upload_size: 16,
+            ..Config::default()
+        };
+        let db = DataBase::open(&StoreKind::Memory).unwrap();
+        db.users.set_password("alice", "secret").await.unwrap();
+        let session = Session::new("alice", Vec::new(), "127.0.0.1", "test");
+        db.sessions
+            .create_session("k", &session, Duration::from_secs(60))
+            .await
+            .unwrap();
+        let addr = start(config, db).await;
+
+        let upload_with = |cookie: &str, csrf: &str, content: &str| {
+            let body = format!(
+                "--b\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n\
+                --b\r\nContent-Disposition: form-data; name=\"f\"; filename=\"a/b.txt\"\r\n\r\n\
+                {}\r\n--b--\r\n",
+                content
+            );
+            format!(
+                "POST /upload HTTP/1.1\r\nCookie: {}\r\nX-CSRF-Token: {}\r\nContent-Type: multipart/form-data; boundary=b\r\nContent-Length: {}\r\n\r\n{}",
+                cookie,
+                csrf,
+                body.len(),
+                body
+            )
+        };
+        let upload =
+            |cookie: &str, content: &str| upload_with(cookie, &session.csrf_token, content);
+        let response = http(addr, &upload("key=none", "data")).await;
+        assert!(response.starts_with("HTTP/1.1 303"));
+        let response = http(addr, &upload_with("key=k", "forged", "data")).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+
+        let response = http(addr, &upload("key=k", "file content")).await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert!(response.ends_with("ab.txt\n"));
+        assert_eq!(std::fs::read(dir.join("ab.txt")).unwrap(), b"file content");
+        let url = response.lines().last().unwrap();
+        let response = http(
+            addr,
+            &format!("GET {} HTTP/1.1\r\nCookie: key=k\r\n\r\n", url),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("Content-Type: application/octet-stream\r\n"));
+        assert!(response.contains("Content-Disposition: attachment\r\n"));
+        assert!(response.contains("X-Content-Type-Options: nosniff\r\n"));
+        assert!(response.ends_with("file content"));
+
+        // 同名文件不会被覆盖；超过大小上限的文件不会留下
+        let response = http(addr, &upload("key=k", "0123456789abcdefg")).await;
+        assert!(response.starts_with("HTTP/1.1 413"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        let response = http(addr, &upload("key=k", "second")).await;
+        assert!(response.starts_with("HTTP/1.1 201"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    async fn login_flow(db: DataBase) {
+        let addr = start(Config::default(), db).await;
+
+        // 公开路径无需登录，其余路径跳转到登录页并带上原地址
+        let response = http(addr, "GET / HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let response = http(addr, "GET /1.txt?a=1 HTTP/1.1\r\nHost: test\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 303"));
+        assert!(
+            response.contains("Location: /srs/LoginInterface.html?next=%2F1%2Etxt%3Fa%3D1\r\n")
+        );
+        let response = http(
+            addr,
+            "GET /1.txt HTTP/1.1\r\nHost: test\r\nAccept: application/json\r\n\r\n",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(response.contains(r#""error":"authentication required""#));
+
+        let body = "user=alice&password=secret&remember=on";
+        let response = http(
+            addr,
+            &format!(
+                "POST /srs/login HTTP/1.1\r\nHost: test\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            ),
+        )
+        .await;
+        assert!(response.ends_with("success"));
+        let key = session_cookie(&response).unwrap();
+        let key = key.as_str();
+
+        let response = http(
+            addr,
+            &format!(
+                "GET /1.txt HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\n\r\n",
+                key
+            ),
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        let get = |path: &str| {
+            format!(
+                "GET {} HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\n\r\n",
+                path, key
+            )
+        };
+        let response = http(addr, &get("/srs/session")).await;
+        assert!(response.contains(r#""user":"alice""#));
+        let csrf = response.split(r#""csrf_token":""#).nth(1).unwrap();
+        let csrf = &csrf[..csrf.find('"').unwrap()];
+        assert!(response.contains(r#""roles":["user"]"#));
+        let response = http(addr, &get("/srs/sessions")).await;
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains(r#""current":true"#));
+        assert!(!response.contains(key));
+        let response = http(addr, &get("/srs/sessions?user=bob")).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+
+        // 跨站提交的注销请求带不上 CSRF 令牌
+        let logout = format!(
+            "POST /srs/logout HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\n\r\n",
+            key
+        );
+        let response = http(addr, &logout).await;
+        assert!(response.starts_with("HTTP/1.1 403"));
+        let logout = format!(
+            "POST /srs/logout HTTP/1.1\r\nHost: test\r\nCookie: key={}\r\nX-CSRF-Token: {}\r\n\r\n",
+            key, csrf
+        );
+        let response = http(addr, &logout).await;
+        assert!(response.starts_with("HTTP/1.1 303"));
+        assert!(response.contains("Max-Age=0"));
+        let response = http(addr, &get("/1.txt")).await;
+        assert!(response.starts_with("HTTP/1.1 303"));
+        let response = http(addr, &get("/srs/sessions")).await;
+        assert!(response.starts_with("HTTP/1.1 401"));
+    }
+
     #[test]
     fn httparse_use() {
         let buf = b"GET /404 HTTP/1.1\r\nHost:";
@@ -0,0 +1,159 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+// 客户端连接：中间件登记的响应头在状态行之后插入，处理函数无需关心
+pub struct Conn {
+    stream: TcpStream,
+    headers: Vec<String>,
+    // 已经写过状态行，之后的数据原样转发
+    started: bool,
+    // 状态行可能分多次写入，读到行尾之前先缓存
+    status: Vec<u8>,
+    // 插入头部后尚未写出的数据
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl Conn {
+    pub fn new(stream: TcpStream) -> Self {
+        Conn {
+            stream,
+            headers: Vec::new(),
+            started: false,
+            status: Vec::new(),
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    // 完整的头部行，如 "Set-Cookie: key=..."
+    pub fn add_header(&mut self, line: String) {
+        self.headers.push(line);
+    }
+
+    // 在状态行之后插入登记的头部，连同缓存的数据放入待写出的缓冲区
+    fn start(&mut self, status_end: usize) {
+        self.started = true;
+        let status = std::mem::take(&mut self.status);
+        let (line, rest) = status.split_at(status_end);
+        self.pending.extend_from_slice(line);
+        for header in self.headers.drain(..) {
+            self.pending.extend_from_slice(header.as_bytes());
+            self.pending.extend_from_slice(b"\r\n");
+        }
+        self.pending.extend_from_slice(rest);
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.stream).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for Conn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Conn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_pending(cx))?;
+        if !this.started {
+            if this.headers.is_empty() {
+                this.started = true;
+            } else {
+                this.status.extend_from_slice(buf);
+                if let Some(end) = this.status.windows(2).position(|w| w == b"\r\n") {
+                    this.start(end + 2);
+                    // 数据已进入缓冲区，剩余部分在之后的写入或 flush 中发出
+                    let _ = this.poll_pending(cx)?;
+                }
+                return Poll::Ready(Ok(buf.len()));
+            }
+        }
+        Pin::new(&mut this.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // 没有写完状态行就结束时原样发出已缓存的数据
+        if !self.started {
+            let this = &mut *self;
+            this.started = true;
+            this.pending.append(&mut this.status);
+        }
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[tokio::test]
+    async fn inject_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut conn = Conn::new(stream);
+        conn.add_header("Set-Cookie: key=new".into());
+        // 状态行被拆成两次写入
+        conn.write_all(b"HTTP/1.1 200").await.unwrap();
+        conn.write_all(b" OK\r\nContent-Length: 2\r\n\r\n")
+            .await
+            .unwrap();
+        conn.write_all(b"ok").await.unwrap();
+        conn.shutdown().await.unwrap();
+        assert_eq!(
+            client.await.unwrap(),
+            "HTTP/1.1 200 OK\r\nSet-Cookie: key=new\r\nContent-Length: 2\r\n\r\nok"
+        );
+    }
+}
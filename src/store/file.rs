@@ -0,0 +1,702 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, mpsc},
+    thread,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::sync::oneshot;
+
+use crate::{
+    server::SyncError,
+    store::{
+        ApiToken, Session, SessionIndex, SessionInfo, SessionStore, TokenStore, UserStore, now,
+    },
+};
+
+const LOG_FILE: &str = "store.log";
+// 日志中的记录数超过存活条目的倍数（且不少于下限）时压缩
+const COMPACT_RATIO: usize = 2;
+const COMPACT_MIN_RECORDS: usize = 1024;
+
+struct Entry {
+    session: Session,
+    // UNIX 时间戳（秒），重启后依然有效
+    expires_at: u64,
+}
+
+// 交给写入线程的任务，按提交顺序执行
+enum Job {
+    Append(String),
+    // 以当前状态的全部记录替换日志
+    Rewrite(Vec<String>),
+}
+
+struct Inner {
+    sessions: HashMap<String, Entry>,
+    index: SessionIndex,
+    users: HashMap<String, String>,
+    roles: HashMap<String, Vec<String>>,
+    tokens: HashMap<String, ApiToken>,
+    // 已注销的 JWT id 到其过期时间
+    denied: HashMap<String, u64>,
+    writer: mpsc::Sender<(Job, Option<oneshot::Sender<io::Result<()>>>)>,
+    // 当前日志文件中的记录数
+    records: usize,
+}
+
+// 本地持久化存储：每次修改追加一行 JSON 到数据目录下的日志，定期压缩为当前状态；
+// 内存中的状态由锁保护，阻塞的文件 I/O 都在单独的写入线程中完成
+pub struct FileStore {
+    inner: Mutex<Inner>,
+    // 修改依次进行：检查、写入日志、更新内存状态之间不会穿插其他修改
+    write: tokio::sync::Mutex<()>,
+}
+
+impl FileStore {
+    pub fn open(dir: &Path) -> Result<Self, Box<SyncError>> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOG_FILE);
+        let mut sessions = HashMap::new();
+        let mut users = HashMap::new();
+        let mut roles = HashMap::new();
+        let mut tokens = HashMap::new();
+        let mut denied = HashMap::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                // 崩溃时可能留下写了一半的最后一行，忽略即可
+                let Ok(record) = serde_json::from_str::<Value>(&line) else {
+                    eprintln!("Skip corrupt record in {}", path.display());
+                    continue;
+                };
+                Self::apply(
+                    &record,
+                    &mut sessions,
+                    &mut users,
+                    &mut roles,
+                    &mut tokens,
+                    &mut denied,
+                );
+            }
+        }
+        let mut index = SessionIndex::default();
+        for (key, e) in &sessions {
+            index.insert(&e.session.user, key);
+        }
+        let (writer, jobs) = mpsc::channel();
+        let mut inner = Inner {
+            sessions,
+            index,
+            users,
+            roles,
+            tokens,
+            denied,
+            writer,
+            records: 0,
+        };
+        // 启动时同步压缩一次，顺带去掉残缺的记录
+        let lines = inner.snapshot();
+        inner.records = lines.len();
+        let mut log = Log {
+            dir: dir.to_path_buf(),
+            file: OpenOptions::new().create(true).append(true).open(&path)?,
+            len: 0,
+        };
+        log.rewrite(&lines)?;
+        thread::Builder::new()
+            .name("file-store".into())
+            .spawn(move || log.run(jobs))?;
+        Ok(FileStore {
+            inner: Mutex::new(inner),
+            write: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    fn apply(
+        record: &Value,
+        sessions: &mut HashMap<String, Entry>,
+        users: &mut HashMap<String, String>,
+        roles: &mut HashMap<String, Vec<String>>,
+        tokens: &mut HashMap<String, ApiToken>,
+        denied: &mut HashMap<String, u64>,
+    ) {
+        let field = |name: &str| record[name].as_str().unwrap_or_default().to_string();
+        match record["op"].as_str() {
+            Some("user") => {
+                users.insert(field("user"), field("password"));
+            }
+            Some("roles") => {
+                let assigned = Vec::deserialize(&record["roles"]).unwrap_or_default();
+                roles.insert(field("user"), assigned);
+            }
+            Some("session") => {
+                // 旧格式的会话记录无法解析，丢弃即可
+                if let Ok(session) = Session::deserialize(&record["session"]) {
+                    let expires_at = record["expires_at"].as_u64().unwrap_or(0);
+                    sessions.insert(
+                        field("key"),
+                        Entry {
+                            session,
+                            expires_at,
+                        },
+                    );
+                }
+            }
+            Some("del_session") => {
+                sessions.remove(&field("key"));
+            }
+            Some("token") => {
+                if let Ok(token) = ApiToken::deserialize(&record["token"]) {
+                    tokens.insert(token.id.clone(), token);
+                }
+            }
+            Some("del_token") => {
+                tokens.remove(&field("id"));
+            }
+            Some("deny") => {
+                let expires_at = record["expires_at"].as_u64().unwrap_or(0);
+                denied.insert(field("jti"), expires_at);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl FileStore {
+    // 先写日志再修改内存中的状态：写入失败时状态保持不变，之后的压缩也不会把它写入文件；
+    // 调用方需持有 write 锁，保证检查时看到的状态在写入期间不变
+    async fn commit(
+        &self,
+        record: Value,
+        apply: impl FnOnce(&mut Inner) + Send,
+    ) -> Result<(), Box<SyncError>> {
+        let written = self.inner.lock().unwrap().append(record);
+        written.await?;
+        let mut inner = self.inner.lock().unwrap();
+        apply(&mut inner);
+        inner.compact();
+        Ok(())
+    }
+}
+
+impl Inner {
+    // 提交一条记录，返回的 future 在记录写入文件后完成；需要在释放锁之后再等待
+    fn append(
+        &mut self,
+        record: Value,
+    ) -> impl Future<Output = Result<(), Box<SyncError>>> + Send + use<> {
+        let mut line = record.to_string();
+        line.push('\n');
+        let (done, written) = oneshot::channel();
+        let _ = self.writer.send((Job::Append(line), Some(done)));
+        self.records += 1;
+        async move {
+            written
+                .await
+                .map_err(|_| "Err: File store writer stopped")??;
+            Ok(())
+        }
+    }
+
+    // 日志中的记录过多时以当前状态替换日志
+    fn compact(&mut self) {
+        let live = self.sessions.len()
+            + self.users.len()
+            + self.roles.len()
+            + self.tokens.len()
+            + self.denied.len();
+        if self.records > COMPACT_MIN_RECORDS.max(live * COMPACT_RATIO) {
+            let lines = self.snapshot();
+            self.records = lines.len();
+            let _ = self.writer.send((Job::Rewrite(lines), None));
+        }
+    }
+
+    fn insert_session(&mut self, key: &str, entry: Entry) {
+        self.remove_session(key);
+        self.index.insert(&entry.session.user, key);
+        self.sessions.insert(key.to_string(), entry);
+    }
+
+    fn remove_session(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.sessions.remove(key)?;
+        self.index.remove(&entry.session.user, key);
+        Some(entry)
+    }
+
+    // 当前状态对应的全部记录，过期会话与注销记录在此时被丢弃
+    fn snapshot(&mut self) -> Vec<String> {
+        let now = now();
+        let index = &mut self.index;
+        self.sessions.retain(|key, e| {
+            let live = e.expires_at > now;
+            if !live {
+                index.remove(&e.session.user, key);
+            }
+            live
+        });
+        self.denied.retain(|_, expires_at| *expires_at > now);
+        let mut lines = Vec::new();
+        for (user, password) in &self.users {
+            lines.push(json!({"op": "user", "user": user, "password": password}).to_string());
+        }
+        for (user, roles) in self.roles.iter().filter(|(_, roles)| !roles.is_empty()) {
+            lines.push(json!({"op": "roles", "user": user, "roles": roles}).to_string());
+        }
+        for token in self.tokens.values() {
+            lines.push(json!({"op": "token", "token": token}).to_string());
+        }
+        for (key, e) in &self.sessions {
+            lines.push(session_record(key, e).to_string());
+        }
+        for (jti, expires_at) in &self.denied {
+            lines.push(deny_record(jti, *expires_at).to_string());
+        }
+        lines
+    }
+}
+
+// 写入线程持有的日志文件
+struct Log {
+    dir: PathBuf,
+    file: File,
+    // 最后一条完整记录之后的文件长度
+    len: u64,
+}
+
+impl Log {
+    fn run(mut self, jobs: mpsc::Receiver<(Job, Option<oneshot::Sender<io::Result<()>>>)>) {
+        // 存储被释放后通道关闭，线程随之退出
+        for (job, done) in jobs {
+            let result = match job {
+                Job::Append(line) => self.append(&line),
+                Job::Rewrite(lines) => self.rewrite(&lines),
+            };
+            match done {
+                Some(done) => {
+                    let _ = done.send(result);
+                }
+                None => {
+                    if let Err(e) = result {
+                        eprintln!("Err: Compact {} failed: {}", LOG_FILE, e);
+                    }
+                }
+            }
+        }
+    }
+
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        match self
+            .file
+            .write_all(line.as_bytes())
+            .and_then(|()| self.file.flush())
+        {
+            Ok(()) => {
+                self.len += line.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                // 截掉写了一半的记录，避免与下一条记录拼接成一行
+                let _ = self.file.set_len(self.len);
+                Err(e)
+            }
+        }
+    }
+
+    // 把记录写入临时文件再原子替换日志
+    fn rewrite(&mut self, lines: &[String]) -> io::Result<()> {
+        let path = self.dir.join(LOG_FILE);
+        let tmp = self.dir.join(format!("{}.tmp", LOG_FILE));
+        let mut file = File::create(&tmp)?;
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        // 目录项同步到磁盘后，崩溃重启才能看到替换后的日志
+        #[cfg(unix)]
+        File::open(&self.dir)?.sync_all()?;
+        self.file = OpenOptions::new().append(true).open(&path)?;
+        self.len = self.file.metadata()?.len();
+        Ok(())
+    }
+}
+
+fn session_record(key: &str, entry: &Entry) -> Value {
+    json!({
+        "op": "session",
+        "key": key,
+        "session": entry.session,
+        "expires_at": entry.expires_at,
+    })
+}
+
+fn deny_record(jti: &str, expires_at: u64) -> Value {
+    json!({"op": "deny", "jti": jti, "expires_at": expires_at})
+}
+
+#[async_trait]
+impl SessionStore for FileStore {
+    async fn create_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let now = now();
+        {
+            let inner = self.inner.lock().unwrap();
+            if inner.sessions.get(key).is_some_and(|e| e.expires_at > now) {
+                return Ok(false);
+            }
+        }
+        let entry = Entry {
+            session: session.clone(),
+            expires_at: now + ttl.as_secs(),
+        };
+        self.commit(session_record(key, &entry), |inner| {
+            inner.insert_session(key, entry)
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn session(&self, key: &str) -> Result<Option<Session>, Box<SyncError>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .sessions
+            .get(key)
+            .filter(|e| e.expires_at > now())
+            .map(|e| e.session.clone()))
+    }
+
+    async fn update_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let now = now();
+        {
+            let inner = self.inner.lock().unwrap();
+            if inner.sessions.get(key).is_none_or(|e| e.expires_at <= now) {
+                return Ok(false);
+            }
+        }
+        let entry = Entry {
+            session: session.clone(),
+            expires_at: now + ttl.as_secs(),
+        };
+        self.commit(session_record(key, &entry), |inner| {
+            inner.insert_session(key, entry)
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn replace_session(
+        &self,
+        key: &str,
+        current: &Session,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let now = now();
+        {
+            let inner = self.inner.lock().unwrap();
+            if inner
+                .sessions
+                .get(key)
+                .is_none_or(|e| e.expires_at <= now || e.session != *current)
+            {
+                return Ok(false);
+            }
+        }
+        let entry = Entry {
+            session: session.clone(),
+            expires_at: now + ttl.as_secs(),
+        };
+        self.commit(session_record(key, &entry), |inner| {
+            inner.insert_session(key, entry)
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn delete_session(&self, key: &str) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let live = {
+            let inner = self.inner.lock().unwrap();
+            match inner.sessions.get(key) {
+                Some(entry) => entry.expires_at > now(),
+                None => return Ok(false),
+            }
+        };
+        self.commit(json!({"op": "del_session", "key": key}), |inner| {
+            inner.remove_session(key);
+        })
+        .await?;
+        Ok(live)
+    }
+
+    async fn deny_token(&self, jti: &str, ttl: Duration) -> Result<(), Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let expires_at = now() + ttl.as_secs();
+        self.commit(deny_record(jti, expires_at), |inner| {
+            inner.denied.insert(jti.to_string(), expires_at);
+        })
+        .await
+    }
+
+    async fn is_denied(&self, jti: &str) -> Result<bool, Box<SyncError>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .denied
+            .get(jti)
+            .is_some_and(|expires_at| *expires_at > now()))
+    }
+
+    async fn list_sessions(&self, user: &str) -> Result<Vec<SessionInfo>, Box<SyncError>> {
+        let now = now();
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .index
+            .keys(user)
+            .filter_map(|key| Some((key, inner.sessions.get(key)?)))
+            .filter(|(_, e)| e.expires_at > now)
+            .map(|(key, e)| SessionInfo {
+                key: key.clone(),
+                session: e.session.clone(),
+                expires_in: Duration::from_secs(e.expires_at - now),
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl UserStore for FileStore {
+    async fn password_hash(&self, user: &str) -> Result<Option<String>, Box<SyncError>> {
+        Ok(self.inner.lock().unwrap().users.get(user).cloned())
+    }
+
+    async fn set_password_hash(&self, user: &str, hash: &str) -> Result<(), Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let record = json!({"op": "user", "user": user, "password": hash});
+        self.commit(record, |inner| {
+            inner.users.insert(user.to_string(), hash.to_string());
+        })
+        .await
+    }
+
+    async fn replace_password_hash(
+        &self,
+        user: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        if self
+            .inner
+            .lock()
+            .unwrap()
+            .users
+            .get(user)
+            .map(String::as_str)
+            != Some(old)
+        {
+            return Ok(false);
+        }
+        let record = json!({"op": "user", "user": user, "password": new});
+        self.commit(record, |inner| {
+            inner.users.insert(user.to_string(), new.to_string());
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn create_user_hash(&self, user: &str, hash: &str) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        if self.inner.lock().unwrap().users.contains_key(user) {
+            return Ok(false);
+        }
+        let record = json!({"op": "user", "user": user, "password": hash});
+        self.commit(record, |inner| {
+            inner.users.insert(user.to_string(), hash.to_string());
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn roles(&self, user: &str) -> Result<Vec<String>, Box<SyncError>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.roles.get(user).cloned().unwrap_or_default())
+    }
+
+    async fn set_roles(&self, user: &str, roles: &[String]) -> Result<(), Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let record = json!({"op": "roles", "user": user, "roles": roles});
+        self.commit(record, |inner| {
+            inner.roles.insert(user.to_string(), roles.to_vec());
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl TokenStore for FileStore {
+    async fn create_token(&self, token: &ApiToken) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        if self.inner.lock().unwrap().tokens.contains_key(&token.id) {
+            return Ok(false);
+        }
+        self.commit(json!({"op": "token", "token": token}), |inner| {
+            inner.tokens.insert(token.id.clone(), token.clone());
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn token(&self, id: &str) -> Result<Option<ApiToken>, Box<SyncError>> {
+        Ok(self.inner.lock().unwrap().tokens.get(id).cloned())
+    }
+
+    async fn list_tokens(&self, user: &str) -> Result<Vec<ApiToken>, Box<SyncError>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .tokens
+            .values()
+            .filter(|t| t.user == user)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_token(&self, id: &str) -> Result<bool, Box<SyncError>> {
+        let _write = self.write.lock().await;
+        if !self.inner.lock().unwrap().tokens.contains_key(id) {
+            return Ok(false);
+        }
+        self.commit(json!({"op": "del_token", "id": id}), |inner| {
+            inner.tokens.remove(id);
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn touch_token(&self, id: &str, last_used: u64) -> Result<(), Box<SyncError>> {
+        let _write = self.write.lock().await;
+        let Some(mut token) = self.inner.lock().unwrap().tokens.get(id).cloned() else {
+            return Ok(());
+        };
+        token.last_used = Some(last_used);
+        self.commit(json!({"op": "token", "token": token}), |inner| {
+            inner.tokens.insert(id.to_string(), token);
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn survives_restart() {
+        let dir = temp_dir("file-store");
+        let store = FileStore::open(&dir).unwrap();
+        store.set_password_hash("alice", "h1").await.unwrap();
+        store.set_password_hash("alice", "h2").await.unwrap();
+        let editor = vec!["editor".to_string()];
+        store.set_roles("alice", &["admin".into()]).await.unwrap();
+        store.set_roles("alice", &editor).await.unwrap();
+        // 清空的角色在压缩时不再保留
+        store.set_roles("bob", &["editor".into()]).await.unwrap();
+        store.set_roles("bob", &[]).await.unwrap();
+        let mut token = ApiToken {
+            id: "t1".into(),
+            user: "alice".into(),
+            name: "ci".into(),
+            scopes: vec!["upload".into()],
+            hash: "h".into(),
+            created_at: 1,
+            last_used: None,
+            expires_at: None,
+        };
+        assert!(store.create_token(&token).await.unwrap());
+        assert!(!store.create_token(&token).await.unwrap());
+        store.touch_token("t1", 5).await.unwrap();
+        token.last_used = Some(5);
+        assert!(
+            store
+                .create_token(&ApiToken {
+                    id: "t2".into(),
+                    ..token.clone()
+                })
+                .await
+                .unwrap()
+        );
+        assert!(store.delete_token("t2").await.unwrap());
+        let day = Duration::from_secs(3600 * 24);
+        let mut alice = Session::new("alice", vec!["admin".into()], "1.1.1.1", "curl");
+        let bob = Session::new("bob", Vec::new(), "2.2.2.2", "curl");
+        assert!(store.create_session("s1", &alice, day).await.unwrap());
+        assert!(!store.create_session("s1", &bob, day).await.unwrap());
+        alice.last_seen += 1;
+        assert!(store.update_session("s1", &alice, day).await.unwrap());
+        // 已过期的会话在重新打开时被丢弃
+        assert!(
+            store
+                .create_session("old", &alice, Duration::ZERO)
+                .await
+                .unwrap()
+        );
+        // 注销的会话在重新打开后依然无效
+        assert!(store.create_session("s2", &alice, day).await.unwrap());
+        assert!(store.delete_session("s2").await.unwrap());
+        store.deny_token("j1", day).await.unwrap();
+        store.deny_token("j2", Duration::ZERO).await.unwrap();
+        drop(store);
+
+        // 模拟写到一半时崩溃
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(b"{\"op\":\"user\",\"us").unwrap();
+
+        let store = FileStore::open(&dir).unwrap();
+        assert_eq!(
+            store.password_hash("alice").await.unwrap().as_deref(),
+            Some("h2")
+        );
+        assert_eq!(store.roles("alice").await.unwrap(), editor);
+        assert!(store.roles("bob").await.unwrap().is_empty());
+        assert_eq!(store.list_tokens("alice").await.unwrap(), vec![token]);
+        assert_eq!(store.session("s1").await.unwrap(), Some(alice));
+        assert!(store.session("old").await.unwrap().is_none());
+        assert!(store.session("s2").await.unwrap().is_none());
+        assert_eq!(store.list_sessions("alice").await.unwrap().len(), 1);
+        assert!(store.is_denied("j1").await.unwrap());
+        assert!(!store.is_denied("j2").await.unwrap());
+        // 重新打开时已压缩：一个用户、一条角色、一个令牌、一个会话、一条注销记录
+        assert_eq!(store.inner.lock().unwrap().records, 5);
+        let content = fs::read_to_string(dir.join(LOG_FILE)).unwrap();
+        assert_eq!(content.lines().count(), 5);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+use crate::{
+    server::SyncError,
+    store::{ApiToken, Session, SessionIndex, SessionInfo, SessionStore, TokenStore, UserStore},
+};
+
+// 定期清理过期会话与注销记录的间隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+struct Entry {
+    session: Session,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+struct Sessions {
+    entries: HashMap<String, Entry>,
+    index: SessionIndex,
+}
+
+impl Sessions {
+    fn insert(&mut self, key: &str, entry: Entry) {
+        self.remove(key);
+        self.index.insert(&entry.session.user, key);
+        self.entries.insert(key.to_string(), entry);
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.index.remove(&entry.session.user, key);
+        Some(entry)
+    }
+
+    fn retain_live(&mut self, now: Instant) {
+        let index = &mut self.index;
+        self.entries.retain(|key, e| {
+            let live = e.expires_at > now;
+            if !live {
+                index.remove(&e.session.user, key);
+            }
+            live
+        });
+    }
+}
+
+// 进程内存储，重启后数据丢失，适合开发与单节点部署
+pub struct MemoryStore {
+    sessions: Mutex<Sessions>,
+    users: Mutex<HashMap<String, String>>,
+    roles: Mutex<HashMap<String, Vec<String>>>,
+    tokens: Mutex<HashMap<String, ApiToken>>,
+    denied: Mutex<HashMap<String, Instant>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            sessions: Mutex::new(Sessions::default()),
+            users: Mutex::new(HashMap::new()),
+            roles: Mutex::new(HashMap::new()),
+            tokens: Mutex::new(HashMap::new()),
+            denied: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 后台定期清理，store 被释放后任务随之退出
+    pub fn spawn_sweeper(store: &Arc<Self>) {
+        let store = Arc::downgrade(store);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+                store.sweep();
+            }
+        });
+    }
+
+    fn sweep(&self) {
+        let now = Instant::now();
+        self.sessions.lock().unwrap().retain_live(now);
+        self.denied
+            .lock()
+            .unwrap()
+            .retain(|_, expires_at| *expires_at > now);
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemoryStore {
+    async fn create_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .entries
+            .get(key)
+            .is_some_and(|e| e.expires_at > now)
+        {
+            return Ok(false);
+        }
+        sessions.insert(
+            key,
+            Entry {
+                session: session.clone(),
+                expires_at: now + ttl,
+            },
+        );
+        Ok(true)
+    }
+
+    async fn session(&self, key: &str) -> Result<Option<Session>, Box<SyncError>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.entries.get(key) {
+            Some(e) if e.expires_at <= Instant::now() => {
+                sessions.remove(key);
+                Ok(None)
+            }
+            Some(e) => Ok(Some(e.session.clone())),
+            None => Ok(None),
+        }
+    }
+
+    async fn update_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .entries
+            .get(key)
+            .is_none_or(|e| e.expires_at <= now)
+        {
+            return Ok(false);
+        }
+        sessions.insert(
+            key,
+            Entry {
+                session: session.clone(),
+                expires_at: now + ttl,
+            },
+        );
+        Ok(true)
+    }
+
+    async fn replace_session(
+        &self,
+        key: &str,
+        current: &Session,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions
+            .entries
+            .get(key)
+            .is_none_or(|e| e.expires_at <= now || e.session != *current)
+        {
+            return Ok(false);
+        }
+        sessions.insert(
+            key,
+            Entry {
+                session: session.clone(),
+                expires_at: now + ttl,
+            },
+        );
+        Ok(true)
+    }
+
+    async fn delete_session(&self, key: &str) -> Result<bool, Box<SyncError>> {
+        let removed = self.sessions.lock().unwrap().remove(key);
+        Ok(removed.is_some_and(|e| e.expires_at > Instant::now()))
+    }
+
+    async fn deny_token(&self, jti: &str, ttl: Duration) -> Result<(), Box<SyncError>> {
+        let expires_at = Instant::now() + ttl;
+        let mut denied = self.denied.lock().unwrap();
+        denied.insert(jti.to_string(), expires_at);
+        Ok(())
+    }
+
+    async fn is_denied(&self, jti: &str) -> Result<bool, Box<SyncError>> {
+        let denied = self.denied.lock().unwrap();
+        Ok(denied
+            .get(jti)
+            .is_some_and(|expires_at| *expires_at > Instant::now()))
+    }
+
+    async fn list_sessions(&self, user: &str) -> Result<Vec<SessionInfo>, Box<SyncError>> {
+        let now = Instant::now();
+        let sessions = self.sessions.lock().unwrap();
+        Ok(sessions
+            .index
+            .keys(user)
+            .filter_map(|key| Some((key, sessions.entries.get(key)?)))
+            .filter(|(_, e)| e.expires_at > now)
+            .map(|(key, e)| SessionInfo {
+                key: key.clone(),
+                session: e.session.clone(),
+                expires_in: e.expires_at - now,
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn password_hash(&self, user: &str) -> Result<Option<String>, Box<SyncError>> {
+        Ok(self.users.lock().unwrap().get(user).cloned())
+    }
+
+    async fn set_password_hash(&self, user: &str, hash: &str) -> Result<(), Box<SyncError>> {
+        self.users
+            .lock()
+            .unwrap()
+            .insert(user.to_string(), hash.to_string());
+        Ok(())
+    }
+
+    async fn replace_password_hash(
+        &self,
+        user: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<bool, Box<SyncError>> {
+        let mut users = self.users.lock().unwrap();
+        match users.get_mut(user) {
+            Some(hash) if hash == old => {
+                *hash = new.to_string();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn create_user_hash(&self, user: &str, hash: &str) -> Result<bool, Box<SyncError>> {
+        let mut users = self.users.lock().unwrap();
+        if users.contains_key(user) {
+            return Ok(false);
+        }
+        users.insert(user.to_string(), hash.to_string());
+        Ok(true)
+    }
+
+    async fn roles(&self, user: &str) -> Result<Vec<String>, Box<SyncError>> {
+        Ok(self
+            .roles
+            .lock()
+            .unwrap()
+            .get(user)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn set_roles(&self, user: &str, roles: &[String]) -> Result<(), Box<SyncError>> {
+        self.roles
+            .lock()
+            .unwrap()
+            .insert(user.to_string(), roles.to_vec());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryStore {
+    async fn create_token(&self, token: &ApiToken) -> Result<bool, Box<SyncError>> {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.contains_key(&token.id) {
+            return Ok(false);
+        }
+        tokens.insert(token.id.clone(), token.clone());
+        Ok(true)
+    }
+
+    async fn token(&self, id: &str) -> Result<Option<ApiToken>, Box<SyncError>> {
+        Ok(self.tokens.lock().unwrap().get(id).cloned())
+    }
+
+    async fn list_tokens(&self, user: &str) -> Result<Vec<ApiToken>, Box<SyncError>> {
+        let tokens = self.tokens.lock().unwrap();
+        Ok(tokens
+            .values()
+            .filter(|t| t.user == user)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_token(&self, id: &str) -> Result<bool, Box<SyncError>> {
+        Ok(self.tokens.lock().unwrap().remove(id).is_some())
+    }
+
+    async fn touch_token(&self, id: &str, last_used: u64) -> Result<(), Box<SyncError>> {
+        if let Some(token) = self.tokens.lock().unwrap().get_mut(id) {
+            token.last_used = Some(last_used);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn session(user: &str, ip: &str) -> Session {
+        Session::new(user, Vec::new(), ip, "test")
+    }
+
+    #[tokio::test]
+    async fn sessions_expire() {
+        let store = MemoryStore::new();
+        let ttl = Duration::from_millis(20);
+        let u = session("u", "1.1.1.1");
+        assert!(store.create_session("a", &u, ttl).await.unwrap());
+        assert!(
+            !store
+                .create_session("a", &session("v", "2.2.2.2"), ttl)
+                .await
+                .unwrap()
+        );
+        assert_eq!(store.session("a").await.unwrap(), Some(u.clone()));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(store.session("a").await.unwrap().is_none());
+        assert!(
+            store
+                .create_session("a", &session("v", "2.2.2.2"), ttl)
+                .await
+                .unwrap()
+        );
+
+        // 从未再被读取的过期会话由定期清理释放
+        assert!(store.create_session("b", &u, ttl).await.unwrap());
+        store.deny_token("j", ttl).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        store.sweep();
+        let sessions = store.sessions.lock().unwrap();
+        assert!(sessions.entries.is_empty());
+        assert_eq!(sessions.index.keys("u").count(), 0);
+        assert!(store.denied.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn update_and_revoke() {
+        let store = MemoryStore::new();
+        let ttl = Duration::from_secs(60);
+        for key in ["a", "b", "c"] {
+            let u = session("u", "1.1.1.1");
+            assert!(store.create_session(key, &u, ttl).await.unwrap());
+        }
+        let v = session("v", "1.1.1.1");
+        assert!(store.create_session("d", &v, ttl).await.unwrap());
+        assert_eq!(store.list_sessions("u").await.unwrap().len(), 3);
+
+        // 更新记录的同时重设有效期
+        let mut a = store.session("a").await.unwrap().unwrap();
+        a.last_seen += 1;
+        let short = Duration::from_millis(20);
+        assert!(store.update_session("a", &a, short).await.unwrap());
+        assert_eq!(store.session("a").await.unwrap(), Some(a.clone()));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!store.update_session("a", &a, ttl).await.unwrap());
+        assert!(store.create_session("a", &a, ttl).await.unwrap());
+
+        // 记录已被其他请求修改时不覆盖
+        let mut b = store.session("b").await.unwrap().unwrap();
+        let stale = b.clone();
+        b.replaced = true;
+        assert!(store.replace_session("b", &stale, &b, ttl).await.unwrap());
+        assert!(!store.replace_session("b", &stale, &b, ttl).await.unwrap());
+        assert_eq!(store.session("b").await.unwrap(), Some(b));
+
+        assert!(store.delete_session("a").await.unwrap());
+        assert!(!store.delete_session("a").await.unwrap());
+        assert_eq!(store.revoke_sessions("u", Some("b")).await.unwrap(), 1);
+        let left = store.list_sessions("u").await.unwrap();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].key, "b");
+        assert!(store.session("d").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn users() {
+        let store = MemoryStore::new();
+        assert!(!store.verify_password("alice", "secret").await.unwrap());
+        store.set_password("alice", "secret").await.unwrap();
+        assert!(store.verify_password("alice", "secret").await.unwrap());
+        assert!(!store.verify_password("alice", "wrong").await.unwrap());
+        let hash = store.password_hash("alice").await.unwrap().unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+
+        // 明文密码在首次成功登录后被替换为哈希
+        store.set_password_hash("bob", "plain").await.unwrap();
+        assert!(!store.verify_password("bob", "wrong").await.unwrap());
+        assert_eq!(store.password_hash("bob").await.unwrap().unwrap(), "plain");
+        assert!(store.verify_password("bob", "plain").await.unwrap());
+        let hash = store.password_hash("bob").await.unwrap().unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(store.verify_password("bob", "plain").await.unwrap());
+
+        // 只替换仍为旧值的哈希
+        assert!(
+            !store
+                .replace_password_hash("bob", "plain", "x")
+                .await
+                .unwrap()
+        );
+        assert!(!store.replace_password_hash("carol", "", "x").await.unwrap());
+        assert!(
+            store
+                .replace_password_hash("bob", &hash, "x")
+                .await
+                .unwrap()
+        );
+        assert_eq!(store.password_hash("bob").await.unwrap().unwrap(), "x");
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    config::StoreKind,
+    protocol::Redis,
+    security::password::{self, Verification},
+    server::SyncError,
+};
+
+pub mod file;
+pub mod memory;
+pub mod redis;
+
+// 会话记录，各后端序列化为 JSON 保存
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub user: String,
+    pub roles: Vec<String>,
+    // 登录时的客户端 IP 与 User-Agent
+    pub ip: String,
+    pub user_agent: String,
+    // 修改状态的请求需要在 X-CSRF-Token 中回传
+    pub csrf_token: String,
+    // UNIX 时间戳（秒）
+    pub created_at: u64,
+    pub last_seen: u64,
+    // 当前会话键的签发时间，更换会话键时更新
+    #[serde(default)]
+    pub rotated_at: u64,
+    // 已被更换的旧会话键，只在宽限期内使用，不再顺延或更换
+    #[serde(default)]
+    pub replaced: bool,
+}
+
+impl Session {
+    pub fn new(user: &str, roles: Vec<String>, ip: &str, user_agent: &str) -> Self {
+        let now = now();
+        Session {
+            user: user.to_string(),
+            roles,
+            ip: ip.to_string(),
+            user_agent: user_agent.to_string(),
+            csrf_token: uuid::Uuid::new_v4().simple().to_string(),
+            created_at: now,
+            last_seen: now,
+            rotated_at: now,
+            replaced: false,
+        }
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+// 会话存储：会话键到会话记录的映射，过期后自动失效
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    // 仅当会话键未被占用时写入，返回是否写入成功
+    async fn create_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>>;
+
+    async fn session(&self, key: &str) -> Result<Option<Session>, Box<SyncError>>;
+
+    // 覆盖已有会话的记录并重设有效期；会话不存在时返回 false
+    async fn update_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>>;
+
+    // 仅当会话记录仍为 current 时覆盖并重设有效期，返回是否写入成功
+    async fn replace_session(
+        &self,
+        key: &str,
+        current: &Session,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>>;
+
+    // 返回会话删除前是否有效
+    async fn delete_session(&self, key: &str) -> Result<bool, Box<SyncError>>;
+
+    // JWT 模式下已注销的令牌 id，保留 ttl 即令牌剩余的有效期
+    async fn deny_token(&self, jti: &str, ttl: Duration) -> Result<(), Box<SyncError>>;
+
+    async fn is_denied(&self, jti: &str) -> Result<bool, Box<SyncError>>;
+
+    // 用户当前有效的全部会话
+    async fn list_sessions(&self, user: &str) -> Result<Vec<SessionInfo>, Box<SyncError>>;
+
+    // 注销用户的全部会话，keep 用于保留当前会话，返回注销的数量
+    async fn revoke_sessions(
+        &self,
+        user: &str,
+        keep: Option<&str>,
+    ) -> Result<usize, Box<SyncError>> {
+        let mut revoked = 0;
+        for info in self.list_sessions(user).await? {
+            if Some(info.key.as_str()) != keep && self.delete_session(&info.key).await? {
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+}
+
+// API 令牌记录，只保存令牌密文部分的哈希
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    // 令牌中的公开部分，用于查找记录
+    pub id: String,
+    pub user: String,
+    pub name: String,
+    // 令牌可以使用的权限，`*` 表示用户拥有的全部权限
+    pub scopes: Vec<String>,
+    pub hash: String,
+    // UNIX 时间戳（秒）
+    pub created_at: u64,
+    #[serde(default)]
+    pub last_used: Option<u64>,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl ApiToken {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+// API 令牌存储：令牌 id 到记录的映射，过期的令牌由调用方判断
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    // 仅当 id 未被占用时写入，返回是否写入成功
+    async fn create_token(&self, token: &ApiToken) -> Result<bool, Box<SyncError>>;
+
+    async fn token(&self, id: &str) -> Result<Option<ApiToken>, Box<SyncError>>;
+
+    async fn list_tokens(&self, user: &str) -> Result<Vec<ApiToken>, Box<SyncError>>;
+
+    // 返回令牌删除前是否存在
+    async fn delete_token(&self, id: &str) -> Result<bool, Box<SyncError>>;
+
+    // 记录最后使用时间，令牌已被删除时不做任何事
+    async fn touch_token(&self, id: &str, last_used: u64) -> Result<(), Box<SyncError>>;
+}
+
+// 用户到其会话键的索引，内存与文件后端据此列出用户的会话而不必遍历全部会话
+#[derive(Default)]
+pub(crate) struct SessionIndex(HashMap<String, HashSet<String>>);
+
+impl SessionIndex {
+    pub fn insert(&mut self, user: &str, key: &str) {
+        self.0
+            .entry(user.to_string())
+            .or_default()
+            .insert(key.to_string());
+    }
+
+    pub fn remove(&mut self, user: &str, key: &str) {
+        if let Some(keys) = self.0.get_mut(user) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.0.remove(user);
+            }
+        }
+    }
+
+    pub fn keys(&self, user: &str) -> impl Iterator<Item = &String> {
+        self.0.get(user).into_iter().flatten()
+    }
+}
+
+pub struct SessionInfo {
+    pub key: String,
+    pub session: Session,
+    pub expires_in: Duration,
+}
+
+// 用户存储：用户名到密码哈希（PHC 格式，历史数据可能是明文）的映射
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn password_hash(&self, user: &str) -> Result<Option<String>, Box<SyncError>>;
+
+    // 直接覆盖密码，目前只在测试中用来准备数据
+    #[allow(dead_code)]
+    async fn set_password_hash(&self, user: &str, hash: &str) -> Result<(), Box<SyncError>>;
+
+    // 仅当当前哈希仍为 old 时替换，检查与写入是原子的，返回是否替换成功
+    async fn replace_password_hash(
+        &self,
+        user: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<bool, Box<SyncError>>;
+
+    // 仅当用户不存在时写入，检查与写入是原子的，返回是否创建成功
+    async fn create_user_hash(&self, user: &str, hash: &str) -> Result<bool, Box<SyncError>>;
+
+    // 管理员分配给用户的角色，不含内置的 user 角色
+    async fn roles(&self, user: &str) -> Result<Vec<String>, Box<SyncError>>;
+
+    async fn set_roles(&self, user: &str, roles: &[String]) -> Result<(), Box<SyncError>>;
+
+    // 校验通过且存储的是明文或旧参数哈希时，顺带写回新哈希
+    async fn verify_password(&self, user: &str, password: &str) -> Result<bool, Box<SyncError>> {
+        let Some(stored) = self.password_hash(user).await? else {
+            let password = password.to_string();
+            tokio::task::spawn_blocking(move || password::verify_dummy(&password)).await?;
+            return Ok(false);
+        };
+        let (password, hash) = (password.to_string(), stored.clone());
+        // 哈希计算耗时较长，放到阻塞线程池
+        let (verification, password) = tokio::task::spawn_blocking(move || {
+            (password::verify_password(&hash, &password), password)
+        })
+        .await?;
+        match verification {
+            Verification::Invalid => Ok(false),
+            Verification::Valid => Ok(true),
+            Verification::NeedsRehash => {
+                // 期间密码已被修改时放弃写回，避免用旧密码覆盖新密码
+                let hash = hash_password(&password).await?;
+                self.replace_password_hash(user, &stored, &hash).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    async fn set_password(&self, user: &str, password: &str) -> Result<(), Box<SyncError>> {
+        self.set_password_hash(user, &hash_password(password).await?)
+            .await
+    }
+
+    async fn create_user(&self, user: &str, password: &str) -> Result<bool, Box<SyncError>> {
+        self.create_user_hash(user, &hash_password(password).await?)
+            .await
+    }
+}
+
+async fn hash_password(password: &str) -> Result<String, Box<SyncError>> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || password::hash_password(&password)).await?
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// 对外展示与记录日志用的会话标识，不能反推出会话键
+pub fn session_id(key: &str) -> String {
+    Sha1::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// 用户名规则：3 到 32 个字符，只能包含字母、数字、`_`、`-`、`.`，以字母或数字开头
+pub fn validate_username(user: &str) -> Result<(), &'static str> {
+    if !(3..=32).contains(&user.len()) {
+        return Err("username must be 3 to 32 characters");
+    }
+    if !user.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+        return Err("username must start with a letter or digit");
+    }
+    if !user
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err("username may only contain letters, digits, '_', '-' and '.'");
+    }
+    Ok(())
+}
+
+// 角色名规则：1 到 32 个小写字母、数字、`_`、`-`
+pub fn validate_role(role: &str) -> Result<(), &'static str> {
+    if !(1..=32).contains(&role.len())
+        || !role
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-'))
+    {
+        return Err("role must be 1 to 32 lowercase letters, digits, '_' or '-'");
+    }
+    Ok(())
+}
+
+// 同时提供会话、用户与令牌存储的后端
+pub trait Store: SessionStore + UserStore + TokenStore {}
+
+impl<T: SessionStore + UserStore + TokenStore> Store for T {}
+
+pub struct DataBase {
+    pub sessions: Arc<dyn SessionStore>,
+    pub users: Arc<dyn UserStore>,
+    pub tokens: Arc<dyn TokenStore>,
+    // Redis 后端用于计算 503 响应的 Retry-After
+    redis: Option<Arc<Redis>>,
+    name: String,
+}
+
+impl DataBase {
+    pub fn open(kind: &StoreKind) -> Result<Self, Box<SyncError>> {
+        match kind {
+            StoreKind::Memory => {
+                let store = Arc::new(memory::MemoryStore::new());
+                memory::MemoryStore::spawn_sweeper(&store);
+                Ok(Self::from_store(store, "memory".into()))
+            }
+            StoreKind::File(dir) => Ok(Self::from_store(
+                Arc::new(file::FileStore::open(dir)?),
+                format!("file://{}", dir.display()),
+            )),
+            StoreKind::Redis(url) => {
+                let redis = Arc::new(Redis::new(url)?);
+                let name = redis.addr().to_string();
+                let mut db = Self::from_store(redis.clone(), name);
+                db.redis = Some(redis);
+                Ok(db)
+            }
+        }
+    }
+
+    fn from_store<S: Store + 'static>(store: Arc<S>, name: String) -> Self {
+        DataBase {
+            sessions: store.clone(),
+            users: store.clone(),
+            tokens: store,
+            redis: None,
+            name,
+        }
+    }
+
+    // 启动时预先加载 Redis 脚本；失败不影响启动，调用时仍会退回 EVAL
+    pub async fn load_scripts(&self) {
+        let Some(redis) = &self.redis else {
+            return;
+        };
+        for script in [&*redis::REPLACE_HASH, &*redis::REPLACE_VALUE] {
+            if let Err(e) = script.load(redis).await {
+                eprintln!("Err: Load script {} failed: {}", script.sha(), e);
+            }
+        }
+    }
+
+    pub fn retry_after(&self) -> Duration {
+        self.redis
+            .as_ref()
+            .map_or(Duration::from_secs(1), |redis| redis.retry_after())
+    }
+}
+
+impl fmt::Display for DataBase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
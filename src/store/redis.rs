@@ -0,0 +1,276 @@
+use std::{sync::LazyLock, time::Duration};
+
+use async_trait::async_trait;
+
+use crate::{
+    protocol::{Redis, commands::SetOptions, script::Script},
+    server::SyncError,
+    store::{ApiToken, Session, SessionInfo, SessionStore, TokenStore, UserStore},
+};
+
+// 比较并替换哈希字段：KEYS[1] 哈希表，ARGV 为字段、旧值、新值
+pub(crate) static REPLACE_HASH: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        "if redis.call('HGET', KEYS[1], ARGV[1]) == ARGV[2] then \
+         redis.call('HSET', KEYS[1], ARGV[1], ARGV[3]) return 1 end return 0",
+    )
+});
+
+// 比较并替换字符串：KEYS[1] 键，ARGV 为旧值、新值、有效期（秒）
+pub(crate) static REPLACE_VALUE: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        "if redis.call('GET', KEYS[1]) == ARGV[1] then \
+         redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3]) return 1 end return 0",
+    )
+});
+
+fn session_key(key: &str) -> String {
+    format!("Session-{}", key)
+}
+
+// 用户的会话索引，成员为会话键，会话过期或删除后由 list_sessions 顺带清理
+fn user_sessions_key(user: &str) -> String {
+    format!("UserSessions-{}", user)
+}
+
+// JWT 模式下已注销的令牌 id，随令牌一同过期
+fn denied_key(jti: &str) -> String {
+    format!("DeniedJwt-{}", jti)
+}
+
+fn token_key(id: &str) -> String {
+    format!("ApiToken-{}", id)
+}
+
+// 用户的令牌索引，成员为令牌 id
+fn user_tokens_key(user: &str) -> String {
+    format!("UserTokens-{}", user)
+}
+
+// 索引的有效期不短于其中任何一个会话
+async fn extend_index(redis: &Redis, index: &str, ttl: Duration) -> Result<(), Box<SyncError>> {
+    if redis.ttl(index).await? < ttl.as_secs() as i64 {
+        redis.expire(index, ttl.as_secs()).await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl SessionStore for Redis {
+    async fn create_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        // 会话键与索引在集群中可能位于不同槽位，无法放进同一个脚本；
+        // 先占用会话键，写索引失败时删除会话，保证创建出的会话都能被注销
+        let value = serde_json::to_string(session)?;
+        if !self
+            .create_session_key(key, value, ttl.as_secs() as usize)
+            .await?
+        {
+            return Ok(false);
+        }
+        let index = user_sessions_key(&session.user);
+        let indexed = match self.sadd(&index, &[key]).await {
+            Ok(_) => extend_index(self, &index, ttl).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = indexed {
+            let _ = self.del(&[&session_key(key)]).await;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    // Session-<key> 的值为 JSON 记录；早期版本只保存了 IP，解析失败时按无效会话处理
+    async fn session(&self, key: &str) -> Result<Option<Session>, Box<SyncError>> {
+        Ok(self
+            .get(&session_key(key))
+            .await?
+            .and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    async fn update_session(
+        &self,
+        key: &str,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let value = serde_json::to_string(session)?;
+        extend_index(self, &user_sessions_key(&session.user), ttl).await?;
+        self.set(
+            &session_key(key),
+            &value,
+            SetOptions::default().ex(ttl.as_secs()).xx(),
+        )
+        .await
+    }
+
+    // 按序列化后的记录比较，记录格式与当前版本不同时视为已被修改
+    async fn replace_session(
+        &self,
+        key: &str,
+        current: &Session,
+        session: &Session,
+        ttl: Duration,
+    ) -> Result<bool, Box<SyncError>> {
+        let current = serde_json::to_string(current)?;
+        let value = serde_json::to_string(session)?;
+        let ttl = ttl.as_secs().max(1).to_string();
+        self.invoke_script(
+            &REPLACE_VALUE,
+            &[&session_key(key)],
+            &[&current, &value, &ttl],
+        )
+        .await
+    }
+
+    async fn delete_session(&self, key: &str) -> Result<bool, Box<SyncError>> {
+        Ok(self.del(&[&session_key(key)]).await? > 0)
+    }
+
+    async fn deny_token(&self, jti: &str, ttl: Duration) -> Result<(), Box<SyncError>> {
+        // EX 不接受 0
+        let ttl = ttl.as_secs().max(1);
+        self.set(&denied_key(jti), "1", SetOptions::default().ex(ttl))
+            .await?;
+        Ok(())
+    }
+
+    async fn is_denied(&self, jti: &str) -> Result<bool, Box<SyncError>> {
+        self.exists(&denied_key(jti)).await
+    }
+
+    async fn list_sessions(&self, user: &str) -> Result<Vec<SessionInfo>, Box<SyncError>> {
+        let index = user_sessions_key(user);
+        let mut sessions = Vec::new();
+        let mut stale = Vec::new();
+        for key in self.smembers(&index).await? {
+            let session = self.session(&key).await?;
+            let ttl = self.ttl(&session_key(&key)).await?;
+            match session {
+                Some(session) if session.user == user && ttl > 0 => sessions.push(SessionInfo {
+                    key,
+                    session,
+                    expires_in: Duration::from_secs(ttl as u64),
+                }),
+                _ => stale.push(key),
+            }
+        }
+        if !stale.is_empty() {
+            let stale = stale.iter().map(String::as_str).collect::<Vec<_>>();
+            self.srem(&index, &stale).await?;
+        }
+        Ok(sessions)
+    }
+}
+
+#[async_trait]
+impl UserStore for Redis {
+    async fn password_hash(&self, user: &str) -> Result<Option<String>, Box<SyncError>> {
+        self.hget("usr-pwd", user).await
+    }
+
+    async fn set_password_hash(&self, user: &str, hash: &str) -> Result<(), Box<SyncError>> {
+        self.hset("usr-pwd", &[(user, hash)]).await?;
+        Ok(())
+    }
+
+    async fn replace_password_hash(
+        &self,
+        user: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<bool, Box<SyncError>> {
+        self.invoke_script(&REPLACE_HASH, &["usr-pwd"], &[user, old, new])
+            .await
+    }
+
+    async fn create_user_hash(&self, user: &str, hash: &str) -> Result<bool, Box<SyncError>> {
+        self.hsetnx("usr-pwd", user, hash).await
+    }
+
+    // 角色列表以 JSON 数组保存在 usr-roles 中
+    async fn roles(&self, user: &str) -> Result<Vec<String>, Box<SyncError>> {
+        // 损坏的记录按没有角色处理，不影响用户登录
+        match self.hget("usr-roles", user).await? {
+            Some(roles) => Ok(serde_json::from_str(&roles).unwrap_or_else(|e| {
+                eprintln!("Err: Invalid roles of user {}: {}", user, e);
+                Vec::new()
+            })),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn set_roles(&self, user: &str, roles: &[String]) -> Result<(), Box<SyncError>> {
+        let roles = serde_json::to_string(roles)?;
+        self.hset("usr-roles", &[(user, &roles)]).await?;
+        Ok(())
+    }
+}
+
+// 令牌不设置过期时间，有效期由调用方按 expires_at 判断
+#[async_trait]
+impl TokenStore for Redis {
+    // 先占用令牌 id 再写索引，写索引失败时删除令牌，保证创建出的令牌都能被列出
+    async fn create_token(&self, token: &ApiToken) -> Result<bool, Box<SyncError>> {
+        let value = serde_json::to_string(token)?;
+        if !self
+            .set(&token_key(&token.id), &value, SetOptions::default().nx())
+            .await?
+        {
+            return Ok(false);
+        }
+        if let Err(e) = self.sadd(&user_tokens_key(&token.user), &[&token.id]).await {
+            let _ = self.del(&[&token_key(&token.id)]).await;
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    async fn token(&self, id: &str) -> Result<Option<ApiToken>, Box<SyncError>> {
+        match self.get(&token_key(id)).await? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_tokens(&self, user: &str) -> Result<Vec<ApiToken>, Box<SyncError>> {
+        let index = user_tokens_key(user);
+        let mut tokens = Vec::new();
+        let mut stale = Vec::new();
+        for id in self.smembers(&index).await? {
+            match self.token(&id).await? {
+                Some(token) if token.user == user => tokens.push(token),
+                _ => stale.push(id),
+            }
+        }
+        if !stale.is_empty() {
+            let stale = stale.iter().map(String::as_str).collect::<Vec<_>>();
+            self.srem(&index, &stale).await?;
+        }
+        Ok(tokens)
+    }
+
+    async fn delete_token(&self, id: &str) -> Result<bool, Box<SyncError>> {
+        // 记录损坏时无法得知所属用户，索引中的残留由 list_tokens 清理
+        let token = self.token(id).await.ok().flatten();
+        let deleted = self.del(&[&token_key(id)]).await? > 0;
+        if let Some(token) = token {
+            self.srem(&user_tokens_key(&token.user), &[id]).await?;
+        }
+        Ok(deleted)
+    }
+
+    async fn touch_token(&self, id: &str, last_used: u64) -> Result<(), Box<SyncError>> {
+        if let Some(mut token) = self.token(id).await? {
+            token.last_used = Some(last_used);
+            let value = serde_json::to_string(&token)?;
+            self.set(&token_key(id), &value, SetOptions::default().xx())
+                .await?;
+        }
+        Ok(())
+    }
+}